@@ -0,0 +1,434 @@
+//! API keys, roles and the audit log for the HTTP API.
+
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    body::{Body, to_bytes},
+    extract::{Request, State},
+    http::{Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+
+use crate::database::DATABASE;
+use crate::order_book;
+use crate::server::ServerConfig;
+
+/// Header carrying the key; `Authorization: Bearer <key>` works too.
+pub const API_KEY_HEADER: &str = "x-api-key";
+/// Browsers cannot set headers on WebSocket upgrades, so these take the key as `?api_key=`.
+const QUERY_KEY_PATHS: [&str; 1] = ["/orders/ws"];
+/// Most of a response body kept in the audit log.
+const AUDIT_OUTCOME_LENGTH: usize = 500;
+
+/// What a key may do; each role can also do everything the ones before it can.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Quotes, positions, orders and other reads.
+    ReadOnly,
+    /// Placing, amending and cancelling orders and closing positions.
+    Trader,
+    /// Connecting, the kill switch, replay control and the audit log.
+    Admin,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ApiKey {
+    /// Who the key belongs to, as written to the audit log.
+    pub name: String,
+    /// Kept to letters, digits, `-` and `_` so it can ride on a WebSocket URL unescaped.
+    pub key: String,
+    pub role: Role,
+}
+
+/// The `[auth]` section of the config. With no keys the API is open only to a server on
+/// loopback; anywhere else callers can read but not trade.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub keys: Vec<ApiKey>,
+}
+
+/// Who may call the API, as the middleware sees it.
+#[derive(Clone, Debug)]
+pub struct Access {
+    pub keys: Vec<ApiKey>,
+    /// The role of every caller when no keys are configured.
+    pub anonymous: Role,
+}
+
+impl Access {
+    pub fn new(keys: Vec<ApiKey>, server: &ServerConfig) -> Self {
+        let anonymous = if server.is_loopback() {
+            Role::Admin
+        } else {
+            Role::ReadOnly
+        };
+        Access { keys, anonymous }
+    }
+}
+
+/// The role an endpoint needs, or None for the public order panel page.
+pub fn required_role(method: &Method, path: &str) -> Option<Role> {
+    const ADMIN_PATHS: [&str; 4] = ["/connect", "/disconnect", "/kill_switch", "/audit"];
+    if path == "/" {
+        return None;
+    }
+    if path == "/audit" || path.starts_with("/kill_switch/") {
+        return Some(Role::Admin);
+    }
+    // A what-if order is transmitted nowhere.
+    if method == Method::GET || path == "/order/preview" {
+        return Some(Role::ReadOnly);
+    }
+    if ADMIN_PATHS.contains(&path) || path.starts_with("/replay") {
+        return Some(Role::Admin);
+    }
+    Some(Role::Trader)
+}
+
+/// Compares without returning early, so timing does not reveal how much of a key matched.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let mut difference = a.len() ^ b.len();
+    for i in 0..a.len().max(b.len()) {
+        let x = a.get(i).copied().unwrap_or(0);
+        let y = b.get(i).copied().unwrap_or(0);
+        difference |= (x ^ y) as usize;
+    }
+    difference == 0
+}
+
+/// The configured key matching `presented`. Every key is compared, whichever matches.
+pub fn identify<'a>(keys: &'a [ApiKey], presented: &str) -> Option<&'a ApiKey> {
+    let mut found = None;
+    for key in keys.iter().filter(|k| !k.key.is_empty()) {
+        if constant_time_eq(key.key.as_bytes(), presented.as_bytes()) {
+            found = Some(key);
+        }
+    }
+    found
+}
+
+fn presented_key(request: &Request) -> Option<String> {
+    let headers = request.headers();
+    if let Some(key) = headers.get(API_KEY_HEADER).and_then(|v| v.to_str().ok()) {
+        return Some(key.to_string());
+    }
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if let Some(key) = bearer {
+        return Some(key.trim().to_string());
+    }
+    if !QUERY_KEY_PATHS.contains(&request.uri().path()) {
+        return None;
+    }
+    request
+        .uri()
+        .query()?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("api_key="))
+        .map(str::to_string)
+}
+
+/// One request as written to the audit log.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub time: String,
+    pub caller: String,
+    pub role: Option<Role>,
+    pub method: String,
+    pub uri: String,
+    pub status: u16,
+    pub outcome: String,
+}
+
+pub fn create_tables(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS audit_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            time TEXT NOT NULL,
+            caller TEXT NOT NULL,
+            role TEXT,
+            method TEXT NOT NULL,
+            uri TEXT NOT NULL,
+            status INTEGER NOT NULL,
+            outcome TEXT NOT NULL
+        );",
+    )
+}
+
+pub fn record(conn: &Connection, entry: &AuditEntry) -> rusqlite::Result<i64> {
+    conn.execute(
+        "INSERT INTO audit_log (time, caller, role, method, uri, status, outcome)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            entry.time,
+            entry.caller,
+            entry
+                .role
+                .map(|r| serde_json::to_string(&r).unwrap_or_default()),
+            entry.method,
+            entry.uri,
+            entry.status,
+            entry.outcome
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// The latest `limit` entries, newest first, optionally only those of one caller.
+pub fn list(
+    conn: &Connection,
+    caller: Option<&str>,
+    limit: usize,
+) -> rusqlite::Result<Vec<AuditEntry>> {
+    let mut statement = conn.prepare(
+        "SELECT id, time, caller, role, method, uri, status, outcome FROM audit_log
+         WHERE (?1 IS NULL OR caller = ?1) ORDER BY id DESC LIMIT ?2",
+    )?;
+    let rows = statement.query_map(params![caller, limit as i64], |row| {
+        let role: Option<String> = row.get(3)?;
+        Ok(AuditEntry {
+            id: row.get(0)?,
+            time: row.get(1)?,
+            caller: row.get(2)?,
+            role: role.and_then(|r| serde_json::from_str(&r).ok()),
+            method: row.get(4)?,
+            uri: row.get(5)?,
+            status: row.get(6)?,
+            outcome: row.get(7)?,
+        })
+    })?;
+    rows.collect()
+}
+
+fn audit(
+    caller: &str,
+    role: Option<Role>,
+    method: &Method,
+    uri: &str,
+    status: StatusCode,
+    outcome: &str,
+) {
+    let entry = AuditEntry {
+        id: 0,
+        time: order_book::now(),
+        caller: caller.to_string(),
+        role,
+        method: method.to_string(),
+        uri: uri.to_string(),
+        status: status.as_u16(),
+        outcome: outcome.chars().take(AUDIT_OUTCOME_LENGTH).collect(),
+    };
+    let conn = DATABASE.lock().unwrap();
+    if let Err(e) = record(&conn, &entry) {
+        println!("Error writing the audit log: {}", e);
+    }
+}
+
+/// Middleware checking the caller's key against the endpoint's role. Refusals and every
+/// request that changes something are written to the audit log with the caller's name.
+pub async fn authorize(
+    State(access): State<Arc<Access>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    // Keys passed in the query stay out of the log.
+    let uri = if QUERY_KEY_PATHS.contains(&request.uri().path()) {
+        request.uri().path().to_string()
+    } else {
+        request.uri().to_string()
+    };
+    let Some(required) = required_role(&method, request.uri().path()) else {
+        return next.run(request).await;
+    };
+
+    let (caller, role) = if access.keys.is_empty() {
+        ("anonymous".to_string(), access.anonymous)
+    } else {
+        let Some(key) = presented_key(&request).and_then(|k| identify(&access.keys, &k)) else {
+            let message = "Missing or unknown API key";
+            audit(
+                "unknown",
+                None,
+                &method,
+                &uri,
+                StatusCode::UNAUTHORIZED,
+                message,
+            );
+            return (StatusCode::UNAUTHORIZED, Json((false, message))).into_response();
+        };
+        (key.name.clone(), key.role)
+    };
+    if role < required {
+        let message = if access.keys.is_empty() {
+            format!(
+                "{} needs the {:?} role, which needs API keys when the server is not on loopback",
+                uri, required
+            )
+        } else {
+            format!("{} needs the {:?} role", uri, required)
+        };
+        audit(
+            &caller,
+            Some(role),
+            &method,
+            &uri,
+            StatusCode::FORBIDDEN,
+            &message,
+        );
+        return (StatusCode::FORBIDDEN, Json((false, message))).into_response();
+    }
+
+    let response = next.run(request).await;
+    if method == Method::GET {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let bytes = to_bytes(body, usize::MAX).await.unwrap_or_default();
+    audit(
+        &caller,
+        Some(role),
+        &method,
+        &uri,
+        parts.status,
+        &String::from_utf8_lossy(&bytes),
+    );
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str, key: &str, role: Role) -> ApiKey {
+        ApiKey {
+            name: name.to_string(),
+            key: key.to_string(),
+            role,
+        }
+    }
+
+    #[test]
+    fn endpoints_need_the_right_role() {
+        assert_eq!(required_role(&Method::GET, "/"), None);
+        assert_eq!(required_role(&Method::GET, "/orders"), Some(Role::ReadOnly));
+        assert_eq!(
+            required_role(&Method::POST, "/order/preview"),
+            Some(Role::ReadOnly)
+        );
+        assert_eq!(required_role(&Method::POST, "/order"), Some(Role::Trader));
+        assert_eq!(
+            required_role(&Method::DELETE, "/orders/7"),
+            Some(Role::Trader)
+        );
+        assert_eq!(
+            required_role(&Method::POST, "/positions/TSLA/close"),
+            Some(Role::Trader)
+        );
+        assert_eq!(required_role(&Method::POST, "/connect"), Some(Role::Admin));
+        assert_eq!(
+            required_role(&Method::POST, "/kill_switch"),
+            Some(Role::Admin)
+        );
+        assert_eq!(
+            required_role(&Method::GET, "/kill_switch"),
+            Some(Role::ReadOnly)
+        );
+        assert_eq!(required_role(&Method::GET, "/audit"), Some(Role::Admin));
+        assert_eq!(
+            required_role(&Method::POST, "/replay/start"),
+            Some(Role::Admin)
+        );
+    }
+
+    #[test]
+    fn keys_are_matched_whole() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"s"));
+
+        let keys = vec![
+            key("desk", "r-123", Role::ReadOnly),
+            key("empty", "", Role::Admin),
+            key("alice", "t-456", Role::Trader),
+        ];
+        assert_eq!(
+            identify(&keys, "t-456").map(|k| k.name.as_str()),
+            Some("alice")
+        );
+        assert!(identify(&keys, "t-45").is_none());
+        assert!(identify(&keys, "").is_none());
+    }
+
+    #[test]
+    fn audit_log_lists_newest_first() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables(&conn).unwrap();
+        for (caller, uri) in [
+            ("alice", "/order?ticker=TSLA"),
+            ("bob", "/connect"),
+            ("alice", "/orders/7"),
+        ] {
+            let entry = AuditEntry {
+                id: 0,
+                time: "2025-01-02 14:30:00".to_string(),
+                caller: caller.to_string(),
+                role: Some(Role::Trader),
+                method: "POST".to_string(),
+                uri: uri.to_string(),
+                status: 200,
+                outcome: "[true,\"ok\"]".to_string(),
+            };
+            record(&conn, &entry).unwrap();
+        }
+
+        let entries = list(&conn, Some("alice"), 10).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].uri, "/orders/7");
+        assert_eq!(entries[0].role, Some(Role::Trader));
+        assert_eq!(list(&conn, None, 1).unwrap()[0].caller, "alice");
+    }
+
+    #[test]
+    fn without_keys_only_loopback_servers_trade() {
+        let server = |bind: &str| ServerConfig {
+            bind: bind.to_string(),
+            ..ServerConfig::default()
+        };
+        assert_eq!(
+            Access::new(Vec::new(), &server("127.0.0.1")).anonymous,
+            Role::Admin
+        );
+        assert_eq!(
+            Access::new(Vec::new(), &server("::1")).anonymous,
+            Role::Admin
+        );
+        assert_eq!(
+            Access::new(Vec::new(), &server("localhost")).anonymous,
+            Role::Admin
+        );
+        assert_eq!(
+            Access::new(Vec::new(), &server("0.0.0.0")).anonymous,
+            Role::ReadOnly
+        );
+        assert_eq!(
+            Access::new(Vec::new(), &server("192.168.1.20")).anonymous,
+            Role::ReadOnly
+        );
+        let socket = ServerConfig {
+            unix_socket: Some("/run/panel.sock".to_string()),
+            ..server("127.0.0.1")
+        };
+        assert_eq!(Access::new(Vec::new(), &socket).anonymous, Role::ReadOnly);
+    }
+}
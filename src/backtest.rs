@@ -0,0 +1,634 @@
+//! Replays past entries bar by bar against ladder presets to compare scale-out schemes.
+//!
+//! Bars are cached per symbol as `{dir}/{SYMBOL}.csv` with `time,open,high,low,close,volume`
+//! rows. Exits are priced from the same `StopPlan` and exit rules used for live trades.
+//! When a bar reaches both a leg's stop and its target the stop is assumed to fill first,
+//! so results lean pessimistic.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+
+use ibapi::orders::Action;
+use serde::Serialize;
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+
+use crate::broker::Bar;
+use crate::database::DATABASE;
+use crate::exit_rules::ManagedTrade;
+use crate::journal::{self, JournalQuery};
+use crate::ladder::{Ladder, Trail, round_to_tick};
+use crate::stop_plan::StopPlan;
+
+/// Header of a cached bar file.
+const BARS_HEADER: &str = "time,open,high,low,close,volume";
+/// Header of an entries file.
+const ENTRIES_HEADER: &str = "time,symbol,action,entry_price,stop_price";
+/// Shares the ladder is split over; results are per unit of risk, so only the split matters.
+const PLAN_QUANTITY: i32 = 10_000;
+
+/// A past entry to replay: filled at `entry_price` at `time`, protected by `stop_price`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BacktestEntry {
+    pub time: OffsetDateTime,
+    pub symbol: String,
+    pub is_long: bool,
+    pub entry_price: f64,
+    pub stop_price: f64,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitReason {
+    Stop,
+    Target,
+    /// Still open when the bars ran out; closed at the last close.
+    EndOfData,
+}
+
+/// How one tranche left the trade.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct LegExit {
+    /// Share of the position in this tranche.
+    pub fraction: f64,
+    pub price: f64,
+    pub reason: ExitReason,
+    /// Bars after the entry, counting the exit bar.
+    pub bars_held: usize,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct TradeResult {
+    pub time: String,
+    pub symbol: String,
+    pub action: String,
+    pub entry_price: f64,
+    pub stop_price: f64,
+    pub r_multiple: f64,
+    pub exits: Vec<LegExit>,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Summary {
+    pub trades: usize,
+    pub wins: usize,
+    pub losses: usize,
+    pub win_rate: f64,
+    /// Mean R of winning trades.
+    pub average_win: f64,
+    /// Mean R of losing trades, negative.
+    pub average_loss: f64,
+    /// Mean R per trade.
+    pub expectancy: f64,
+    pub total_r: f64,
+    /// Largest peak-to-trough fall of cumulative R, in entry order.
+    pub max_drawdown: f64,
+}
+
+impl Summary {
+    pub fn of(trades: &[TradeResult]) -> Self {
+        let rs: Vec<f64> = trades.iter().map(|t| t.r_multiple).collect();
+        let wins: Vec<f64> = rs.iter().copied().filter(|r| *r > 0.0).collect();
+        let losses: Vec<f64> = rs.iter().copied().filter(|r| *r < 0.0).collect();
+        let mean = |values: &[f64]| {
+            if values.is_empty() {
+                0.0
+            } else {
+                values.iter().sum::<f64>() / values.len() as f64
+            }
+        };
+        let (mut total, mut peak, mut max_drawdown) = (0.0, 0.0, 0.0);
+        for r in &rs {
+            total += r;
+            peak = f64::max(peak, total);
+            max_drawdown = f64::max(max_drawdown, peak - total);
+        }
+        Summary {
+            trades: rs.len(),
+            wins: wins.len(),
+            losses: losses.len(),
+            win_rate: if rs.is_empty() {
+                0.0
+            } else {
+                wins.len() as f64 / rs.len() as f64
+            },
+            average_win: mean(&wins),
+            average_loss: mean(&losses),
+            expectancy: mean(&rs),
+            total_r: total,
+            max_drawdown,
+        }
+    }
+}
+
+/// Results of every entry under one ladder.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct LadderReport {
+    pub ladder: String,
+    pub summary: Summary,
+    pub trades: Vec<TradeResult>,
+    /// Entries that could not be replayed, with the reason.
+    pub skipped: Vec<String>,
+}
+
+/// A tranche still in the trade.
+struct OpenLeg {
+    tranche: usize,
+    fraction: f64,
+    stop: f64,
+    target: Option<f64>,
+    trail: Option<Trail>,
+    /// Best price seen since entry, for trailing stops.
+    best: f64,
+}
+
+/// Replays `entry` through `bars` (sorted, for its symbol) under `ladder`.
+pub fn simulate(
+    entry: &BacktestEntry,
+    ladder: &Ladder,
+    bars: &[Bar],
+    min_tick: f64,
+) -> Result<TradeResult, String> {
+    let action = if entry.is_long {
+        Action::Buy
+    } else {
+        Action::Sell
+    };
+    let fill = entry.entry_price;
+    let plan = StopPlan::build(
+        action,
+        PLAN_QUANTITY,
+        fill,
+        entry.stop_price,
+        ladder,
+        min_tick,
+    )?;
+    let start = bars.partition_point(|b| b.time < entry.time);
+    let bars = &bars[start..];
+    if bars.is_empty() {
+        return Err(format!("No bars for {} after the entry", entry.symbol));
+    }
+
+    let direction = if entry.is_long { 1.0 } else { -1.0 };
+    let mut open: Vec<OpenLeg> = plan
+        .legs
+        .iter()
+        .enumerate()
+        .map(|(i, leg)| OpenLeg {
+            tranche: i + 1,
+            fraction: leg.quantity as f64 / PLAN_QUANTITY as f64,
+            stop: leg.stop_price,
+            target: leg.target_price,
+            trail: leg.trail.clone(),
+            best: fill,
+        })
+        .collect();
+    let managed = ManagedTrade {
+        trade_id: 0,
+        symbol: entry.symbol.clone(),
+        is_long: entry.is_long,
+        fill,
+        min_tick,
+        rules: ladder.rules.clone(),
+    };
+    let mut filled_targets = HashSet::new();
+    let mut exits = Vec::new();
+
+    for (i, bar) in bars.iter().enumerate() {
+        let (worst, best) = if entry.is_long {
+            (bar.low, bar.high)
+        } else {
+            (bar.high, bar.low)
+        };
+        open.retain(|leg| {
+            // Gaps fill at the open: worse than the stop, better than the target.
+            let exit = if (worst - leg.stop) * direction <= 0.0 {
+                let price = if (bar.open - leg.stop) * direction < 0.0 {
+                    bar.open
+                } else {
+                    leg.stop
+                };
+                Some((price, ExitReason::Stop))
+            } else {
+                leg.target
+                    .filter(|target| (best - target) * direction >= 0.0)
+                    .map(|target| {
+                        let price = if (bar.open - target) * direction > 0.0 {
+                            bar.open
+                        } else {
+                            target
+                        };
+                        (price, ExitReason::Target)
+                    })
+            };
+            let Some((price, reason)) = exit else {
+                return true;
+            };
+            if reason == ExitReason::Target {
+                filled_targets.insert(leg.tranche);
+            }
+            exits.push(LegExit {
+                fraction: leg.fraction,
+                price,
+                reason,
+                bars_held: i + 1,
+            });
+            false
+        });
+        if open.is_empty() {
+            break;
+        }
+
+        for leg in open.iter_mut() {
+            let Some(trail) = &leg.trail else {
+                continue;
+            };
+            leg.best = if entry.is_long {
+                leg.best.max(best)
+            } else {
+                leg.best.min(best)
+            };
+            let offset = match trail {
+                Trail::Amount(amount) => *amount,
+                Trail::Percent(percent) => leg.best * percent / 100.0,
+            };
+            let trailed = round_to_tick(leg.best - direction * offset, min_tick);
+            if (trailed - leg.stop) * direction > 0.0 {
+                leg.stop = trailed;
+            }
+        }
+        if let Some(wanted) = managed.wanted_stop(&filled_targets, &bars[..=i]) {
+            for leg in open.iter_mut().filter(|leg| leg.trail.is_none()) {
+                if let Some(stop) = managed.tightened(Some(leg.stop), wanted) {
+                    leg.stop = stop;
+                }
+            }
+        }
+    }
+
+    let last = bars.last().unwrap();
+    for leg in open {
+        exits.push(LegExit {
+            fraction: leg.fraction,
+            price: last.close,
+            reason: ExitReason::EndOfData,
+            bars_held: bars.len(),
+        });
+    }
+    let risk = (fill - entry.stop_price) * direction;
+    let r_multiple = exits
+        .iter()
+        .map(|exit| exit.fraction * (exit.price - fill) * direction)
+        .sum::<f64>()
+        / risk;
+
+    Ok(TradeResult {
+        time: entry.time.format(&Rfc3339).unwrap_or_default(),
+        symbol: entry.symbol.clone(),
+        action: if entry.is_long { "BUY" } else { "SELL" }.to_string(),
+        entry_price: fill,
+        stop_price: entry.stop_price,
+        r_multiple,
+        exits,
+    })
+}
+
+/// Replays every entry under each named ladder.
+pub fn run(
+    entries: &[BacktestEntry],
+    bars: &HashMap<String, Vec<Bar>>,
+    ladders: &[(String, Ladder)],
+    min_tick: f64,
+) -> Vec<LadderReport> {
+    ladders
+        .iter()
+        .map(|(name, ladder)| {
+            let mut trades = Vec::new();
+            let mut skipped = Vec::new();
+            for entry in entries {
+                let symbol_bars = bars.get(&entry.symbol).map_or(&[][..], Vec::as_slice);
+                match simulate(entry, ladder, symbol_bars, min_tick) {
+                    Ok(trade) => trades.push(trade),
+                    Err(e) => skipped.push(format!(
+                        "{} {}: {}",
+                        entry.symbol,
+                        entry.time.format(&Rfc3339).unwrap_or_default(),
+                        e
+                    )),
+                }
+            }
+            LadderReport {
+                ladder: name.clone(),
+                summary: Summary::of(&trades),
+                trades,
+                skipped,
+            }
+        })
+        .collect()
+}
+
+type SummaryColumn = fn(&Summary) -> String;
+
+/// Renders the ladders' summaries side by side, one column per ladder.
+pub fn comparison_table(reports: &[LadderReport]) -> String {
+    let rows: [(&str, SummaryColumn); 9] = [
+        ("trades", |s| s.trades.to_string()),
+        ("wins", |s| s.wins.to_string()),
+        ("losses", |s| s.losses.to_string()),
+        ("win rate", |s| format!("{:.1}%", s.win_rate * 100.0)),
+        ("avg win", |s| format!("{:.2}R", s.average_win)),
+        ("avg loss", |s| format!("{:.2}R", s.average_loss)),
+        ("expectancy", |s| format!("{:.2}R", s.expectancy)),
+        ("total", |s| format!("{:.2}R", s.total_r)),
+        ("max drawdown", |s| format!("{:.2}R", s.max_drawdown)),
+    ];
+    let width = reports
+        .iter()
+        .map(|r| r.ladder.len())
+        .max()
+        .unwrap_or(0)
+        .max(10);
+    let mut table = format!("{:<14}", "");
+    for report in reports {
+        let _ = write!(table, " {:>width$}", report.ladder);
+    }
+    table.push('\n');
+    for (label, value) in rows {
+        let _ = write!(table, "{:<14}", label);
+        for report in reports {
+            let _ = write!(table, " {:>width$}", value(&report.summary));
+        }
+        table.push('\n');
+    }
+    table
+}
+
+fn parse_time(field: &str, line: usize) -> Result<OffsetDateTime, String> {
+    OffsetDateTime::parse(field, &Rfc3339)
+        .map_err(|e| format!("Line {}: bad time {}: {}", line, field, e))
+}
+
+fn parse_number(field: &str, line: usize) -> Result<f64, String> {
+    field
+        .parse()
+        .map_err(|_| format!("Line {}: {} is not a number", line, field))
+}
+
+/// Data rows of a CSV file with the given header, split into trimmed fields.
+fn rows<'a>(
+    contents: &'a str,
+    header: &'a str,
+) -> impl Iterator<Item = Result<(usize, Vec<&'a str>), String>> + 'a {
+    let columns = header.split(',').count();
+    contents
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(move |(_, line)| !line.is_empty() && *line != header)
+        .map(move |(i, line)| {
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            if fields.len() != columns {
+                return Err(format!(
+                    "Line {}: expected {} fields, got {}",
+                    i,
+                    columns,
+                    fields.len()
+                ));
+            }
+            Ok((i, fields))
+        })
+}
+
+/// Parses `time,open,high,low,close,volume` rows with RFC 3339 bar start times.
+pub fn parse_bars(contents: &str) -> Result<Vec<Bar>, String> {
+    let mut bars = Vec::new();
+    for row in rows(contents, BARS_HEADER) {
+        let (i, fields) = row?;
+        bars.push(Bar {
+            time: parse_time(fields[0], i)?,
+            open: parse_number(fields[1], i)?,
+            high: parse_number(fields[2], i)?,
+            low: parse_number(fields[3], i)?,
+            close: parse_number(fields[4], i)?,
+            volume: parse_number(fields[5], i)?,
+        });
+    }
+    bars.sort_by_key(|b| b.time);
+    Ok(bars)
+}
+
+/// Renders bars in the cache's CSV format.
+pub fn bars_to_csv(bars: &[Bar]) -> String {
+    let mut csv = format!("{}\n", BARS_HEADER);
+    for bar in bars {
+        let _ = writeln!(
+            csv,
+            "{},{},{},{},{},{}",
+            bar.time.format(&Rfc3339).unwrap_or_default(),
+            bar.open,
+            bar.high,
+            bar.low,
+            bar.close,
+            bar.volume
+        );
+    }
+    csv
+}
+
+/// Loads the cached bars of each symbol from `{dir}/{SYMBOL}.csv`.
+pub fn load_bars(dir: &str, symbols: &[String]) -> Result<HashMap<String, Vec<Bar>>, String> {
+    let mut bars = HashMap::new();
+    for symbol in symbols {
+        let path = format!("{}/{}.csv", dir, symbol);
+        let contents =
+            std::fs::read_to_string(&path).map_err(|e| format!("Error reading {}: {}", path, e))?;
+        let parsed = parse_bars(&contents).map_err(|e| format!("{}: {}", path, e))?;
+        bars.insert(symbol.clone(), parsed);
+    }
+    Ok(bars)
+}
+
+/// Parses `time,symbol,action,entry_price,stop_price` rows; action is BUY or SELL.
+pub fn parse_entries(contents: &str) -> Result<Vec<BacktestEntry>, String> {
+    let mut entries = Vec::new();
+    for row in rows(contents, ENTRIES_HEADER) {
+        let (i, fields) = row?;
+        let is_long = match fields[2].to_uppercase().as_str() {
+            "BUY" => true,
+            "SELL" => false,
+            other => {
+                return Err(format!(
+                    "Line {}: action must be BUY or SELL, got {}",
+                    i, other
+                ));
+            }
+        };
+        entries.push(BacktestEntry {
+            time: parse_time(fields[0], i)?,
+            symbol: fields[1].to_uppercase(),
+            is_long,
+            entry_price: parse_number(fields[3], i)?,
+            stop_price: parse_number(fields[4], i)?,
+        });
+    }
+    entries.sort_by_key(|e| e.time);
+    Ok(entries)
+}
+
+pub fn load_entries(path: &str) -> Result<Vec<BacktestEntry>, String> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|e| format!("Error reading {}: {}", path, e))?;
+    parse_entries(&contents)
+}
+
+/// Journaled trades matching `filter` that have an entry fill, as backtest entries.
+pub fn journal_entries(filter: &JournalQuery) -> Result<Vec<BacktestEntry>, String> {
+    let conn = DATABASE.lock().unwrap();
+    let journaled = journal::query(&conn, filter).map_err(|e| e.to_string())?;
+    Ok(journaled
+        .into_iter()
+        .filter_map(|trade| {
+            Some(BacktestEntry {
+                time: OffsetDateTime::parse(&trade.opened_at, &Rfc3339).ok()?,
+                symbol: trade.symbol,
+                is_long: trade.action == "BUY",
+                entry_price: trade.entry_price?,
+                stop_price: trade.stop_price,
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exit_rules::ExitRule;
+    use crate::ladder::{Level, Tranche};
+
+    const START: i64 = 1_735_828_200;
+
+    fn bars(prices: &[(f64, f64, f64, f64)]) -> Vec<Bar> {
+        prices
+            .iter()
+            .enumerate()
+            .map(|(i, (open, high, low, close))| Bar {
+                time: OffsetDateTime::from_unix_timestamp(START + 60 * i as i64).unwrap(),
+                open: *open,
+                high: *high,
+                low: *low,
+                close: *close,
+                volume: 1000.0,
+            })
+            .collect()
+    }
+
+    fn long(entry_price: f64, stop_price: f64) -> BacktestEntry {
+        BacktestEntry {
+            time: OffsetDateTime::from_unix_timestamp(START).unwrap(),
+            symbol: "TSLA".to_string(),
+            is_long: true,
+            entry_price,
+            stop_price,
+        }
+    }
+
+    fn halves(rules: Vec<ExitRule>) -> Ladder {
+        let tranche = |target: f64| Tranche {
+            size_percent: 50.0,
+            stop: Level::R(1.0),
+            target: Some(Level::R(target)),
+            trail: None,
+        };
+        Ladder {
+            tranches: vec![tranche(1.0), tranche(2.0)],
+            rules,
+        }
+    }
+
+    #[test]
+    fn scales_out_at_targets_then_stops() {
+        let bars = bars(&[
+            (100.0, 101.5, 99.5, 101.0),
+            (101.0, 102.5, 100.5, 102.0),
+            (102.0, 102.2, 98.0, 98.5),
+        ]);
+        let trade = simulate(&long(100.0, 98.0), &halves(Vec::new()), &bars, 0.01).unwrap();
+
+        let reasons: Vec<ExitReason> = trade.exits.iter().map(|e| e.reason).collect();
+        assert_eq!(reasons, vec![ExitReason::Target, ExitReason::Stop]);
+        assert_eq!(trade.exits[0].price, 102.0);
+        assert_eq!(trade.exits[1].price, 98.0);
+        assert!((trade.r_multiple - 0.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn stop_wins_when_a_bar_reaches_both_and_gaps_fill_at_the_open() {
+        let both = bars(&[(100.0, 104.5, 97.5, 100.0)]);
+        let trade = simulate(&long(100.0, 98.0), &halves(Vec::new()), &both, 0.01).unwrap();
+        assert_eq!(trade.r_multiple, -1.0);
+
+        let gap = bars(&[(100.0, 100.5, 99.5, 100.0), (96.0, 96.5, 95.0, 95.5)]);
+        let trade = simulate(&long(100.0, 98.0), &halves(Vec::new()), &gap, 0.01).unwrap();
+        assert!(trade.exits.iter().all(|e| e.price == 96.0));
+        assert_eq!(trade.r_multiple, -2.0);
+    }
+
+    #[test]
+    fn breakeven_rule_moves_the_runner_stop() {
+        let bars = bars(&[(100.0, 102.1, 99.5, 101.5), (101.5, 101.8, 99.0, 99.5)]);
+        let ladder = halves(vec![ExitRule::Breakeven { after_tranche: 1 }]);
+        let trade = simulate(&long(100.0, 98.0), &ladder, &bars, 0.01).unwrap();
+
+        assert_eq!(trade.exits[1].price, 100.0);
+        assert!((trade.r_multiple - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn open_legs_close_at_the_last_bar() {
+        let bars = bars(&[(100.0, 101.0, 99.5, 100.5)]);
+        let trade = simulate(&long(100.0, 98.0), &halves(Vec::new()), &bars, 0.01).unwrap();
+
+        assert!(
+            trade
+                .exits
+                .iter()
+                .all(|e| e.reason == ExitReason::EndOfData)
+        );
+        assert!((trade.r_multiple - 0.25).abs() < 1e-9);
+        assert!(simulate(&long(100.0, 98.0), &halves(Vec::new()), &[], 0.01).is_err());
+    }
+
+    #[test]
+    fn summary_tracks_expectancy_and_drawdown() {
+        let trade = |r_multiple: f64| TradeResult {
+            time: String::new(),
+            symbol: "TSLA".to_string(),
+            action: "BUY".to_string(),
+            entry_price: 100.0,
+            stop_price: 98.0,
+            r_multiple,
+            exits: Vec::new(),
+        };
+        let summary = Summary::of(&[trade(2.0), trade(-1.0), trade(-1.0), trade(3.0)]);
+
+        assert_eq!((summary.wins, summary.losses), (2, 2));
+        assert_eq!(summary.win_rate, 0.5);
+        assert_eq!(summary.expectancy, 0.75);
+        assert_eq!(summary.max_drawdown, 2.0);
+        assert_eq!(summary.average_loss, -1.0);
+    }
+
+    #[test]
+    fn parses_entries_and_round_trips_bars() {
+        let entries = parse_entries(
+            "time,symbol,action,entry_price,stop_price\n\
+             2025-01-02T14:35:00Z,tsla,sell,250.0,252.5\n",
+        )
+        .unwrap();
+        assert_eq!(entries[0].symbol, "TSLA");
+        assert!(!entries[0].is_long);
+        assert!(parse_entries("2025-01-02T14:35:00Z,TSLA,HOLD,250,252").is_err());
+
+        let cached = bars(&[(100.0, 101.0, 99.5, 100.5)]);
+        assert_eq!(parse_bars(&bars_to_csv(&cached)).unwrap(), cached);
+    }
+}
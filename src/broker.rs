@@ -0,0 +1,188 @@
+//! The trading backend the HTTP API runs against.
+//!
+//! Handlers only see the `Broker` trait and the domain types below, so IBKR can be swapped
+//! for a simulated backend in tests and demos.
+
+use std::future::Future;
+
+use ibapi::orders::Order;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::executions::{ExecutionQuery, ExecutionRecord};
+use crate::positions::{CloseReport, ExitStyle};
+use crate::preview::OrderPreview;
+use crate::sizing::PositionSize;
+use crate::trading;
+
+pub use crate::quotes::Quote;
+pub use ibapi::market_data::historical::BarSize;
+
+/// One completed OHLCV bar.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bar {
+    pub time: OffsetDateTime,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+}
+
+/// Shares held in one symbol; negative when short.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Position {
+    pub account: String,
+    pub symbol: String,
+    pub quantity: f64,
+    pub average_cost: f64,
+}
+
+/// An entry as requested by a caller, before sizing.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OrderRequest {
+    pub ticker: String,
+    pub qty: Option<i32>,
+    pub stop_price: f64,
+    pub entry_price: f64,
+    pub action: String,
+    pub ladder: Option<String>,
+    pub risk_percent: Option<f64>,
+    /// Caller-chosen key that makes retries of the same order safe for the trading day.
+    pub client_order_key: Option<String>,
+}
+
+/// An update to a placed order reported by the backend, applied to the order book.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OrderEvent {
+    Status {
+        order_id: i32,
+        client_id: i32,
+        status: String,
+        filled: f64,
+        remaining: f64,
+        average_fill_price: f64,
+        perm_id: i32,
+    },
+    Fill {
+        order_id: i32,
+        client_id: i32,
+        execution_id: String,
+        shares: f64,
+        price: f64,
+        time: String,
+    },
+    Commission {
+        execution_id: String,
+        commission: f64,
+        /// Absent on opening executions.
+        realized_pnl: Option<f64>,
+    },
+}
+
+/// A trading backend. Implementations are cheap handles, cloned into each request and
+/// background task.
+///
+/// Backends place, cancel and modify single orders; entries with their ladders, closes and
+/// reversals are built on top of those in `trading` and shared by every backend.
+pub trait Broker: Clone + Send + Sync + 'static {
+    fn connect(
+        &self,
+        address: &str,
+        port: u16,
+        client_id: i32,
+    ) -> impl Future<Output = bool> + Send;
+    fn is_connected(&self) -> impl Future<Output = bool> + Send;
+    /// The client id this backend's orders are placed under, or `None` when not connected.
+    fn client_id(&self) -> impl Future<Output = Option<i32>> + Send;
+    fn disconnect(&self) -> impl Future<Output = ()> + Send;
+    fn account_values(&self) -> impl Future<Output = Option<Vec<String>>> + Send;
+    fn positions(&self) -> impl Future<Output = Option<Vec<Position>>> + Send;
+    /// The last traded price, or `None` for unknown symbols.
+    fn last_price(&self, symbol: &str) -> impl Future<Output = Option<f64>> + Send;
+    /// Streams `symbol` if it is not streaming yet and returns its latest quote.
+    fn quote(&self, symbol: &str) -> impl Future<Output = Result<Quote, String>> + Send;
+    /// Today's low and high, `(0.0, 0.0)` when unknown.
+    fn day_range(&self, symbol: &str) -> impl Future<Output = (f64, f64)> + Send;
+    /// The symbol's price increment, used to round stops and targets.
+    fn min_tick(&self, symbol: &str) -> impl Future<Output = f64> + Send;
+    /// The last `count` completed bars of today's regular session.
+    fn recent_bars(
+        &self,
+        symbol: &str,
+        bar_size: BarSize,
+        count: usize,
+    ) -> impl Future<Output = Result<Vec<Bar>, String>> + Send;
+    fn size_position(
+        &self,
+        symbol: &str,
+        entry_price: Option<f64>,
+        stop_price: f64,
+        risk_percent: Option<f64>,
+    ) -> impl Future<Output = Result<PositionSize, String>> + Send;
+    /// Places a single stock order, records it in the order book and keeps it updated there.
+    fn place_order(
+        &self,
+        symbol: &str,
+        order: &Order,
+    ) -> impl Future<Output = Result<i32, String>> + Send;
+    /// Places the entry for `request` at `qty` shares, then its ladder's exits.
+    fn submit_order(
+        &self,
+        request: &OrderRequest,
+        qty: i32,
+    ) -> impl Future<Output = (bool, String)> + Send {
+        trading::submit_order(self, request, qty)
+    }
+    fn preview_order(
+        &self,
+        symbol: &str,
+        qty: i32,
+        action: &str,
+    ) -> impl Future<Output = Result<OrderPreview, String>> + Send;
+    /// Syncs working orders into the order book.
+    fn refresh_orders(&self, all: bool) -> impl Future<Output = bool> + Send;
+    fn executions(
+        &self,
+        query: &ExecutionQuery,
+    ) -> impl Future<Output = Result<Vec<ExecutionRecord>, String>> + Send;
+    fn cancel_order(&self, order_id: i32) -> impl Future<Output = (bool, String)> + Send;
+    fn cancel_all(&self, symbol: Option<&str>) -> impl Future<Output = (bool, String)> + Send;
+    /// Cancels every working order on the account, including other client ids' orders.
+    fn global_cancel(&self) -> impl Future<Output = (bool, String)> + Send;
+    fn modify_order(
+        &self,
+        order_id: i32,
+        quantity: Option<f64>,
+        limit_price: Option<f64>,
+        stop_price: Option<f64>,
+    ) -> impl Future<Output = (bool, String)> + Send;
+    fn close_position(
+        &self,
+        symbol: &str,
+        percent: Option<f64>,
+        style: ExitStyle,
+    ) -> impl Future<Output = Result<CloseReport, String>> + Send {
+        trading::close_position(self, symbol, percent, style)
+    }
+    fn close_all(
+        &self,
+        style: ExitStyle,
+    ) -> impl Future<Output = Vec<Result<CloseReport, String>>> + Send {
+        trading::close_all(self, style)
+    }
+    fn reverse_position(
+        &self,
+        symbol: &str,
+        style: ExitStyle,
+    ) -> impl Future<Output = Result<CloseReport, String>> + Send {
+        trading::reverse_position(self, symbol, style)
+    }
+    fn flatten_all(&self) -> impl Future<Output = (bool, String)> + Send {
+        trading::flatten_all(self)
+    }
+    /// Locks entries and cancels every order, optionally closing all positions too.
+    fn kill_switch(&self, flatten: bool) -> impl Future<Output = (bool, String)> + Send {
+        trading::engage_kill_switch(self, flatten)
+    }
+}
@@ -0,0 +1,627 @@
+//! Command-line entry points: one-shot commands for scripting and smoke tests, run against
+//! the same backends as the HTTP server in `server`.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::backtest::{self, BacktestEntry};
+use crate::broker::{Broker, OrderRequest};
+use crate::config::CONFIG;
+use crate::journal::JournalQuery;
+use crate::ladder::{DEFAULT_LADDER, Ladder};
+use crate::replay::{self, ReplayFormat};
+use crate::router;
+use crate::tui::{self, Panel};
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1";
+/// Client id for one-shot commands, so they do not knock a running server off its session.
+const DEFAULT_CLIENT_ID: i32 = 90;
+/// Options every command that talks to the broker accepts.
+const CONNECTION_OPTIONS: [&str; 3] = ["--address", "--port", "--client-id"];
+
+pub const USAGE: &str = "usage: rust [COMMAND] [OPTIONS]
+
+commands:
+  serve                                  run the HTTP API and order panel (the default)
+  connect-check                          connect, print account and positions counts, disconnect
+  quote SYMBOL...                        print the latest quote of each symbol
+  positions                              print open positions
+  order buy|sell SYMBOL --stop PRICE     enter with the ladder's exits
+        [--entry PRICE] [--qty N | --risk PERCENT] [--ladder NAME] [--key KEY] [--preview]
+  flatten                                cancel all orders and close every position at market
+  tui                                    trade the watchlist from the terminal with the config hotkeys
+  backtest (--entries FILE | --journal [--symbol S] [--from DATE] [--to DATE])
+        [--bars DIR] [--ladder NAME]... [--min-tick TICK] [--trades] [--json]
+  replay record FILE --symbols A,B [--seconds N] [--format csv|binary]
+  replay play FILE [--speed N]           serve the simulator on a recording; speed 0 steps
+
+replay FILEs are names inside the config's replay_dir.
+broker commands also take --address HOST, --port PORT and --client-id ID (default 90).
+Orders belong to the client id that placed them: a server on another id lists them but
+cannot modify or cancel them, and `order` refuses ladders with exit rules because nothing
+would be left running to move their stops. `flatten` cancels every order on the account,
+whichever client placed it.";
+
+/// Where one-shot commands connect.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Connection {
+    pub address: String,
+    pub port: u16,
+    pub client_id: i32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BacktestArgs {
+    pub entries_file: Option<String>,
+    pub journal: Option<JournalQuery>,
+    pub bars_dir: String,
+    pub ladders: Vec<String>,
+    pub min_tick: f64,
+    pub show_trades: bool,
+    pub json: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Serve,
+    ConnectCheck(Connection),
+    Quote(Connection, Vec<String>),
+    Positions(Connection),
+    Order {
+        connection: Connection,
+        request: OrderRequest,
+        preview: bool,
+    },
+    Flatten(Connection),
+    Tui(Connection),
+    Backtest(BacktestArgs),
+    Record {
+        connection: Connection,
+        file: String,
+        symbols: Vec<String>,
+        seconds: Option<u64>,
+        format: Option<ReplayFormat>,
+    },
+    Play {
+        file: String,
+        speed: f64,
+    },
+    Help,
+}
+
+/// Positional arguments and `--name value` options, with bare switches mapped to "".
+struct Parsed {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+}
+
+impl Parsed {
+    fn new(args: &[String], with_values: &[&str], switches: &[&str]) -> Result<Self, String> {
+        let mut positional = Vec::new();
+        let mut options = HashMap::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if switches.contains(&arg.as_str()) {
+                options.insert(arg.clone(), String::new());
+            } else if with_values.contains(&arg.as_str()) {
+                let value = args.next().ok_or(format!("{} needs a value", arg))?;
+                options
+                    .entry(arg.clone())
+                    .and_modify(|v: &mut String| {
+                        v.push(',');
+                        v.push_str(value);
+                    })
+                    .or_insert_with(|| value.clone());
+            } else if arg.starts_with("--") {
+                return Err(format!("Unknown option {}", arg));
+            } else {
+                positional.push(arg.clone());
+            }
+        }
+        Ok(Parsed {
+            positional,
+            options,
+        })
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.options.contains_key(name)
+    }
+
+    fn number<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>, String> {
+        self.get(name)
+            .map(|v| v.parse().map_err(|_| format!("{} must be a number", name)))
+            .transpose()
+    }
+
+    fn list(&self, name: &str) -> Vec<String> {
+        self.get(name)
+            .map(|v| {
+                v.split(',')
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn connection(&self) -> Result<Connection, String> {
+        let port = match self.number("--port")? {
+            Some(port) => port,
+            None => CONFIG
+                .port
+                .trim()
+                .parse()
+                .map_err(|_| format!("Config port {} is not a number", CONFIG.port))?,
+        };
+        Ok(Connection {
+            address: self.get("--address").unwrap_or(DEFAULT_ADDRESS).to_string(),
+            port,
+            client_id: self.number("--client-id")?.unwrap_or(DEFAULT_CLIENT_ID),
+        })
+    }
+
+    fn expect_positional(&self, count: usize, what: &str) -> Result<(), String> {
+        if self.positional.len() != count {
+            return Err(format!("Expected {}", what));
+        }
+        Ok(())
+    }
+}
+
+/// Parses the arguments after the program name; no command means `serve`.
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    let Some((command, rest)) = args.split_first() else {
+        return Ok(Command::Serve);
+    };
+    let broker_options = |extra: &[&'static str]| -> Vec<&'static str> {
+        CONNECTION_OPTIONS.iter().chain(extra).copied().collect()
+    };
+    match command.as_str() {
+        "serve" => {
+            Parsed::new(rest, &[], &[])?.expect_positional(0, "no arguments to serve")?;
+            Ok(Command::Serve)
+        }
+        "connect-check" => {
+            let parsed = Parsed::new(rest, &broker_options(&[]), &[])?;
+            parsed.expect_positional(0, "no arguments to connect-check")?;
+            Ok(Command::ConnectCheck(parsed.connection()?))
+        }
+        "quote" => {
+            let parsed = Parsed::new(rest, &broker_options(&[]), &[])?;
+            if parsed.positional.is_empty() {
+                return Err("Expected at least one symbol".to_string());
+            }
+            let symbols = parsed.positional.iter().map(|s| s.to_uppercase()).collect();
+            Ok(Command::Quote(parsed.connection()?, symbols))
+        }
+        "positions" => {
+            let parsed = Parsed::new(rest, &broker_options(&[]), &[])?;
+            parsed.expect_positional(0, "no arguments to positions")?;
+            Ok(Command::Positions(parsed.connection()?))
+        }
+        "order" => {
+            let parsed = Parsed::new(
+                rest,
+                &broker_options(&["--stop", "--entry", "--qty", "--risk", "--ladder", "--key"]),
+                &["--preview"],
+            )?;
+            parsed.expect_positional(2, "an action and a symbol, e.g. order buy TSLA")?;
+            let action = parsed.positional[0].to_uppercase();
+            if action != "BUY" && action != "SELL" {
+                return Err(format!("Action must be buy or sell, got {}", action));
+            }
+            let request = OrderRequest {
+                ticker: parsed.positional[1].to_uppercase(),
+                qty: parsed.number("--qty")?,
+                stop_price: parsed.number("--stop")?.ok_or("--stop is required")?,
+                entry_price: parsed.number("--entry")?.unwrap_or(0.0),
+                action,
+                ladder: parsed.get("--ladder").map(str::to_string),
+                risk_percent: parsed.number("--risk")?,
+                client_order_key: parsed.get("--key").map(str::to_string),
+            };
+            Ok(Command::Order {
+                connection: parsed.connection()?,
+                request,
+                preview: parsed.has("--preview"),
+            })
+        }
+        "flatten" => {
+            let parsed = Parsed::new(rest, &broker_options(&[]), &[])?;
+            parsed.expect_positional(0, "no arguments to flatten")?;
+            Ok(Command::Flatten(parsed.connection()?))
+        }
+        "tui" => {
+            let parsed = Parsed::new(rest, &broker_options(&[]), &[])?;
+            parsed.expect_positional(0, "no arguments to tui")?;
+            Ok(Command::Tui(parsed.connection()?))
+        }
+        "backtest" => parse_backtest(rest),
+        "replay" => parse_replay(rest),
+        "help" | "--help" | "-h" => Ok(Command::Help),
+        other => Err(format!("Unknown command {}", other)),
+    }
+}
+
+fn parse_backtest(args: &[String]) -> Result<Command, String> {
+    let parsed = Parsed::new(
+        args,
+        &[
+            "--entries",
+            "--symbol",
+            "--from",
+            "--to",
+            "--bars",
+            "--ladder",
+            "--min-tick",
+        ],
+        &["--journal", "--trades", "--json"],
+    )?;
+    parsed.expect_positional(0, "only options to backtest")?;
+    let entries_file = parsed.get("--entries").map(str::to_string);
+    if entries_file.is_some() == parsed.has("--journal") {
+        return Err("Give exactly one of --entries or --journal".to_string());
+    }
+    let journal = parsed.has("--journal").then(|| JournalQuery {
+        symbol: parsed.get("--symbol").map(str::to_uppercase),
+        from: parsed.get("--from").map(str::to_string),
+        to: parsed.get("--to").map(str::to_string),
+    });
+    Ok(Command::Backtest(BacktestArgs {
+        entries_file,
+        journal,
+        bars_dir: parsed.get("--bars").unwrap_or("bars").to_string(),
+        ladders: parsed.list("--ladder"),
+        min_tick: parsed.number("--min-tick")?.unwrap_or(0.01),
+        show_trades: parsed.has("--trades"),
+        json: parsed.has("--json"),
+    }))
+}
+
+fn parse_replay(args: &[String]) -> Result<Command, String> {
+    let Some((action, rest)) = args.split_first() else {
+        return Err("Expected replay record or replay play".to_string());
+    };
+    match action.as_str() {
+        "record" => {
+            let options = CONNECTION_OPTIONS
+                .iter()
+                .chain(&["--symbols", "--seconds", "--format"])
+                .copied()
+                .collect::<Vec<_>>();
+            let parsed = Parsed::new(rest, &options, &[])?;
+            parsed.expect_positional(1, "a file to record to")?;
+            let symbols: Vec<String> = parsed
+                .list("--symbols")
+                .iter()
+                .map(|s| s.to_uppercase())
+                .collect();
+            if symbols.is_empty() {
+                return Err("--symbols is required".to_string());
+            }
+            let format = match parsed.get("--format") {
+                None => None,
+                Some("csv") => Some(ReplayFormat::Csv),
+                Some("binary") => Some(ReplayFormat::Binary),
+                Some(other) => return Err(format!("Format must be csv or binary, got {}", other)),
+            };
+            Ok(Command::Record {
+                connection: parsed.connection()?,
+                file: parsed.positional[0].clone(),
+                symbols,
+                seconds: parsed.number("--seconds")?,
+                format,
+            })
+        }
+        "play" => {
+            let parsed = Parsed::new(rest, &["--speed"], &[])?;
+            parsed.expect_positional(1, "a recording to play")?;
+            Ok(Command::Play {
+                file: parsed.positional[0].clone(),
+                speed: parsed.number("--speed")?.unwrap_or(1.0),
+            })
+        }
+        other => Err(format!("Unknown replay command {}", other)),
+    }
+}
+
+async fn connect<B: Broker>(broker: &B, connection: &Connection) -> Result<(), String> {
+    if broker
+        .connect(&connection.address, connection.port, connection.client_id)
+        .await
+    {
+        Ok(())
+    } else {
+        Err(format!(
+            "Could not connect to {}:{} as client {}",
+            connection.address, connection.port, connection.client_id
+        ))
+    }
+}
+
+/// Runs a command other than `serve`, `replay play` and `help` against `broker`, returning
+/// what to print.
+pub async fn run<B: Broker>(broker: B, command: Command) -> Result<String, String> {
+    match command {
+        Command::ConnectCheck(connection) => {
+            connect(&broker, &connection).await?;
+            let values = broker.account_values().await.unwrap_or_default();
+            let positions = broker.positions().await.unwrap_or_default();
+            broker.disconnect().await;
+            Ok(format!(
+                "Connected to {}:{} as client {}: {} account values, {} positions.\n",
+                connection.address,
+                connection.port,
+                connection.client_id,
+                values.len(),
+                positions.len()
+            ))
+        }
+        Command::Quote(connection, symbols) => {
+            connect(&broker, &connection).await?;
+            let mut output = String::new();
+            for symbol in symbols {
+                let line = match broker.quote(&symbol).await {
+                    Ok(quote) => format!(
+                        "{:<6} bid {} ask {} last {} low {} high {} volume {}\n",
+                        symbol,
+                        price(quote.bid),
+                        price(quote.ask),
+                        price(quote.last),
+                        price(quote.low),
+                        price(quote.high),
+                        quote
+                            .volume
+                            .map_or("-".to_string(), |v| format!("{:.0}", v))
+                    ),
+                    Err(e) => format!("{:<6} {}\n", symbol, e),
+                };
+                output.push_str(&line);
+            }
+            broker.disconnect().await;
+            Ok(output)
+        }
+        Command::Positions(connection) => {
+            connect(&broker, &connection).await?;
+            let positions = broker.positions().await;
+            broker.disconnect().await;
+            let positions = positions.ok_or("Could not read positions")?;
+            let mut output = String::new();
+            for position in positions.iter().filter(|p| p.quantity != 0.0) {
+                output.push_str(&format!(
+                    "{:<6} {:>8} @ {:.2}  {}\n",
+                    position.symbol, position.quantity, position.average_cost, position.account
+                ));
+            }
+            if output.is_empty() {
+                output.push_str("No open positions.\n");
+            }
+            Ok(output)
+        }
+        Command::Order {
+            connection,
+            request,
+            preview,
+        } => {
+            // Exit rules run inside this process, which exits as soon as the order is in.
+            if !preview && !Ladder::preset(request.ladder.as_deref())?.rules.is_empty() {
+                return Err(format!(
+                    "Ladder {} moves its stops with exit rules, which only run while the \
+                     process stays up; place it through the server or the tui instead",
+                    request.ladder.as_deref().unwrap_or("(default)")
+                ));
+            }
+            connect(&broker, &connection).await?;
+            let result = if preview {
+                preview_order(&broker, &request).await
+            } else {
+                match router::place_order(&broker, &request).await {
+                    (true, message) => Ok(format!("{}\n", message)),
+                    (false, message) => Err(message),
+                }
+            };
+            broker.disconnect().await;
+            result
+        }
+        Command::Flatten(connection) => {
+            connect(&broker, &connection).await?;
+            let (ok, message) = broker.flatten_all().await;
+            broker.disconnect().await;
+            if ok {
+                Ok(format!("{}\n", message))
+            } else {
+                Err(message)
+            }
+        }
+        Command::Tui(connection) => {
+            let panel = Panel::from_config()?;
+            connect(&broker, &connection).await?;
+            let result = tui::run(broker.clone(), panel).await;
+            broker.disconnect().await;
+            result.map(|()| String::new())
+        }
+        Command::Backtest(args) => run_backtest(&args),
+        Command::Record {
+            connection,
+            file,
+            symbols,
+            seconds,
+            format,
+        } => {
+            connect(&broker, &connection).await?;
+            let status = replay::start_recording(&file, format).await?;
+            for symbol in &symbols {
+                if let Err(e) = broker.quote(symbol).await {
+                    println!("{}: {}", symbol, e);
+                }
+            }
+            match seconds {
+                Some(seconds) => tokio::time::sleep(Duration::from_secs(seconds)).await,
+                None => {
+                    println!("Recording to {}, press Ctrl-C to stop.", status.path);
+                    let _ = tokio::signal::ctrl_c().await;
+                }
+            }
+            let status = replay::stop_recording().await?;
+            broker.disconnect().await;
+            Ok(format!(
+                "Recorded {} quote updates to {}.\n",
+                status.ticks, status.path
+            ))
+        }
+        Command::Serve | Command::Play { .. } | Command::Help => Ok(format!("{}\n", USAGE)),
+    }
+}
+
+fn price(value: Option<f64>) -> String {
+    value.map_or("-".to_string(), |v| format!("{:.2}", v))
+}
+
+async fn preview_order<B: Broker>(broker: &B, request: &OrderRequest) -> Result<String, String> {
+    let qty = router::order_qty(broker, request).await?;
+    let preview = broker
+        .preview_order(&request.ticker, qty, &request.action)
+        .await?;
+    let money = |value: Option<f64>| value.map_or("-".to_string(), |v| format!("${:.2}", v));
+    Ok(format!(
+        "{} {} {}: initial margin {}, maintenance margin {}, commission {}{}\n",
+        request.action,
+        qty,
+        request.ticker,
+        money(preview.initial_margin_change),
+        money(preview.maintenance_margin_change),
+        money(preview.commission),
+        if preview.warning_text.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", preview.warning_text)
+        }
+    ))
+}
+
+/// Replays entries from a CSV or the journal against ladder presets and renders the comparison.
+fn run_backtest(args: &BacktestArgs) -> Result<String, String> {
+    let entries: Vec<BacktestEntry> = match (&args.entries_file, &args.journal) {
+        (Some(path), _) => backtest::load_entries(path)?,
+        (None, Some(filter)) => backtest::journal_entries(filter)?,
+        (None, None) => return Err("Give exactly one of --entries or --journal".to_string()),
+    };
+    let mut names = args.ladders.clone();
+    if names.is_empty() {
+        // Compare every configured preset with the built-in one.
+        names.push(DEFAULT_LADDER.to_string());
+        let mut configured: Vec<String> = CONFIG.ladders.keys().cloned().collect();
+        configured.sort();
+        names.extend(configured.into_iter().filter(|n| n != DEFAULT_LADDER));
+    }
+    let ladders = names
+        .into_iter()
+        .map(|name| Ladder::preset(Some(&name)).map(|ladder| (name, ladder)))
+        .collect::<Result<Vec<_>, String>>()?;
+    let mut symbols: Vec<String> = entries.iter().map(|e| e.symbol.clone()).collect();
+    symbols.sort();
+    symbols.dedup();
+    let bars = backtest::load_bars(&args.bars_dir, &symbols)?;
+
+    let reports = backtest::run(&entries, &bars, &ladders, args.min_tick);
+    if args.json {
+        return serde_json::to_string_pretty(&reports)
+            .map(|json| json + "\n")
+            .map_err(|e| e.to_string());
+    }
+    let mut output = backtest::comparison_table(&reports);
+    for report in &reports {
+        if args.show_trades {
+            output.push_str(&format!("\n{}\n", report.ladder));
+            for trade in &report.trades {
+                output.push_str(&format!(
+                    "  {} {} {} {:.2} stop {:.2}: {:+.2}R\n",
+                    trade.time,
+                    trade.action,
+                    trade.symbol,
+                    trade.entry_price,
+                    trade.stop_price,
+                    trade.r_multiple
+                ));
+            }
+        }
+        for skipped in &report.skipped {
+            output.push_str(&format!("{}: skipped {}\n", report.ladder, skipped));
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn no_command_serves() {
+        assert_eq!(parse(&[]), Ok(Command::Serve));
+        assert_eq!(parse(&args("serve")), Ok(Command::Serve));
+        assert!(parse(&args("serve now")).is_err());
+        assert!(parse(&args("trade")).is_err());
+    }
+
+    #[test]
+    fn parses_order_with_risk_sizing() {
+        let command = parse(&args(
+            "order buy tsla --stop 240 --risk 0.25 --port 4002 --preview",
+        ))
+        .unwrap();
+        let Command::Order {
+            connection,
+            request,
+            preview,
+        } = command
+        else {
+            panic!("not an order: {:?}", command);
+        };
+        assert_eq!(connection.port, 4002);
+        assert_eq!(connection.client_id, DEFAULT_CLIENT_ID);
+        assert_eq!(request.ticker, "TSLA");
+        assert_eq!(request.action, "BUY");
+        assert_eq!((request.stop_price, request.entry_price), (240.0, 0.0));
+        assert_eq!((request.qty, request.risk_percent), (None, Some(0.25)));
+        assert!(preview);
+
+        assert!(parse(&args("order hold TSLA --stop 240")).is_err());
+        assert!(parse(&args("order buy TSLA")).is_err());
+        assert!(parse(&args("order buy TSLA --stop x")).is_err());
+        assert!(parse(&args("order buy TSLA --stop 240 --limit 1")).is_err());
+    }
+
+    #[test]
+    fn parses_backtest_and_replay() {
+        let Ok(Command::Backtest(backtest)) = parse(&args(
+            "backtest --journal --symbol tsla --ladder thirds --ladder halves,runner",
+        )) else {
+            panic!("not a backtest");
+        };
+        assert_eq!(backtest.ladders, vec!["thirds", "halves", "runner"]);
+        assert_eq!(
+            backtest.journal.and_then(|q| q.symbol),
+            Some("TSLA".to_string())
+        );
+        assert!(parse(&args("backtest --journal --entries e.csv")).is_err());
+
+        assert_eq!(
+            parse(&args("replay play day.bin --speed 10")),
+            Ok(Command::Play {
+                file: "day.bin".to_string(),
+                speed: 10.0
+            })
+        );
+        assert!(parse(&args("replay record day.csv")).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::auth::AuthConfig;
+use crate::kill_switch::LossLimits;
+use crate::ladder::Ladder;
+use crate::risk::RiskConfig;
+use crate::server::ServerConfig;
+use crate::simulator::SimulatorConfig;
+use crate::sizing::SizingConfig;
+
+/// Path of the config file, overridable with `IBKR_PANEL_CONFIG`.
+const DEFAULT_CONFIG_PATH: &str = "watchlist.toml";
+const DEFAULT_DATABASE_PATH: &str = "ibkr_panel.db";
+const DEFAULT_REPLAY_DIR: &str = "replays";
+
+#[allow(dead_code)]
+#[derive(Deserialize, Clone, Debug)]
+pub struct Config {
+    pub risk_percent: String,
+    pub hotkey_refresh: String,
+    pub hotkey_place_order: String,
+    pub watchlist: Vec<String>,
+    pub port: String,
+    /// SQLite file holding executions and the trade journal.
+    #[serde(default = "default_database_path")]
+    pub database_path: String,
+    /// Directory replay files are recorded to and played from by name.
+    #[serde(default = "default_replay_dir")]
+    pub replay_dir: String,
+    #[serde(default)]
+    pub default_ladder: Option<String>,
+    #[serde(default)]
+    pub ladders: HashMap<String, Ladder>,
+    #[serde(default)]
+    pub sizing: SizingConfig,
+    #[serde(default)]
+    pub risk: RiskConfig,
+    #[serde(default)]
+    pub loss_limits: LossLimits,
+    #[serde(default)]
+    pub simulator: SimulatorConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub server: ServerConfig,
+    #[serde(default)]
+    pub timeouts: Timeouts,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            risk_percent: "0.25".to_string(),
+            hotkey_refresh: "Return".to_string(),
+            hotkey_place_order: "F1".to_string(),
+            watchlist: Vec::new(),
+            port: "7496".to_string(),
+            database_path: default_database_path(),
+            replay_dir: default_replay_dir(),
+            default_ladder: None,
+            ladders: HashMap::new(),
+            sizing: SizingConfig::default(),
+            risk: RiskConfig::default(),
+            loss_limits: LossLimits::default(),
+            simulator: SimulatorConfig::default(),
+            auth: AuthConfig::default(),
+            server: ServerConfig::default(),
+            timeouts: Timeouts::default(),
+        }
+    }
+}
+
+/// The `[timeouts]` section: how long to wait on the broker, in milliseconds.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Timeouts {
+    /// For a market entry to fill before it is cancelled.
+    pub entry_fill_ms: u64,
+    /// For close and reverse orders to fill before reporting.
+    pub exit_fill_ms: u64,
+    /// For the first quote after subscribing to a symbol's market data.
+    pub market_data_ms: u64,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            entry_fill_ms: 30_000,
+            exit_fill_ms: 10_000,
+            market_data_ms: 1_000,
+        }
+    }
+}
+
+impl Timeouts {
+    pub fn entry_fill(&self) -> Duration {
+        Duration::from_millis(self.entry_fill_ms)
+    }
+
+    pub fn exit_fill(&self) -> Duration {
+        Duration::from_millis(self.exit_fill_ms)
+    }
+
+    pub fn market_data(&self) -> Duration {
+        Duration::from_millis(self.market_data_ms)
+    }
+}
+
+fn default_database_path() -> String {
+    DEFAULT_DATABASE_PATH.to_string()
+}
+
+fn default_replay_dir() -> String {
+    DEFAULT_REPLAY_DIR.to_string()
+}
+
+impl Config {
+    /// `risk_percent` is kept as a string in the file; this parses it.
+    pub fn risk_percent(&self) -> Option<f64> {
+        self.risk_percent.trim().parse().ok()
+    }
+
+    /// Where simulated history goes: `[simulator] database_path`, else the live file's name
+    /// with `-sim` added, so paper trades never land in the live journal.
+    pub fn simulator_database_path(&self) -> String {
+        if let Some(path) = &self.simulator.database_path {
+            return path.clone();
+        }
+        let live = std::path::Path::new(&self.database_path);
+        match (live.file_stem(), live.extension()) {
+            _ if self.database_path == ":memory:" => self.database_path.clone(),
+            (Some(stem), Some(extension)) => live
+                .with_file_name(format!(
+                    "{}-sim.{}",
+                    stem.to_string_lossy(),
+                    extension.to_string_lossy()
+                ))
+                .to_string_lossy()
+                .to_string(),
+            _ => format!("{}-sim", self.database_path),
+        }
+    }
+
+    pub fn load() -> Self {
+        let path =
+            std::env::var("IBKR_PANEL_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG_PATH.to_string());
+        let parsed = std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| toml::from_str::<Config>(&contents).map_err(|e| e.to_string()));
+        match parsed {
+            Ok(config) => config,
+            Err(e) => {
+                println!("Error loading config from {}: {}, using defaults", path, e);
+                Config::default()
+            }
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    pub static ref CONFIG: Config = Config::load();
+}
@@ -1,66 +1,70 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use ibapi::{
     Client,
+    accounts::PnL,
     accounts::types::AccountId,
-    market_data::historical::{Duration, WhatToShow},
-    orders::{Action, Order, PlaceOrder, builder::OrderType},
-    prelude::{AccountUpdate, HistoricalBarSize, TradingHours},
+    contracts::Contract,
+    market_data::historical::{Bar, Duration, WhatToShow},
+    orders::{CancelOrder, ExecutionFilter, Executions, Order, OrderUpdate, Orders, PlaceOrder},
+    prelude::{AccountUpdate, HistoricalBarSize, PositionUpdate, TradingHours},
+    subscriptions::Subscription,
 };
-use time::macros::datetime;
+use tokio::sync::RwLock;
 
-#[allow(async_fn_in_trait)]
-pub(crate) struct Connector {
-    ib: Option<Client>,
-}
+use crate::broker::{self, Broker, Position};
+use crate::config::CONFIG;
+use crate::database::DATABASE;
+use crate::executions::{self, ExecutionQuery, ExecutionRecord};
+use crate::kill_switch;
+use crate::order_book::{self, ORDER_BOOK, TrackedOrder};
+use crate::preview::OrderPreview;
+use crate::quotes::{self, QUOTES, Quote};
+use crate::sizing::{self, PositionSize, SizingLimits};
+use crate::trading;
 
-lazy_static::lazy_static! {
-    pub(crate) static ref CONNECTOR: tokio::sync::RwLock<Connector> = tokio::sync::RwLock::new(Connector::new());
-}
+/// How long to wait for IBKR to evaluate a what-if order.
+const PREVIEW_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
 
-#[allow(async_fn_in_trait)]
-pub trait ConnectorTrait {
-    fn new() -> Self;
-    async fn connect(&self, address: &str, port: u16, client_id: i32) -> bool;
-    fn is_connected(&self) -> bool;
-    fn disconnect(&mut self);
-    async fn get_account_values(&self) -> Option<Vec<String>>;
-    async fn get_positions(&self) -> Option<Vec<String>>;
-    async fn market_data(&self, ticker: &str) -> Option<f64>;
-    async fn get_lod_hod(&self, ticker: &str) -> (f64, f64);
-    async fn submit_order(
-        &self,
-        ticker: &str,
-        qty: i32,
-        stop_price: f64,
-        entry_price: f64,
-        action: String,
-    ) -> (bool, String);
+/// A connection to TWS or IB Gateway; the IBKR side of `IbkrBroker`.
+#[derive(Default)]
+pub struct Connector {
+    ib: Option<Client>,
 }
 
-impl ConnectorTrait for Connector {
-    fn new() -> Self {
+impl Connector {
+    pub fn new() -> Self {
         Connector { ib: None }
     }
 
-    async fn connect(&self, address: &str, port: u16, client_id: i32) -> bool {
-        (Client::connect(format!("{}:{}", address, port).as_str(), client_id).await).is_ok()
+    pub async fn connect(&mut self, address: &str, port: u16, client_id: i32) -> bool {
+        match Client::connect(format!("{}:{}", address, port).as_str(), client_id).await {
+            Ok(client) => {
+                self.ib = Some(client);
+                true
+            }
+            Err(e) => {
+                println!("Error connecting to IB Gateway: {:?}", e);
+                false
+            }
+        }
     }
 
-    fn is_connected(&self) -> bool {
+    pub fn is_connected(&self) -> bool {
         match &self.ib {
             Some(client) => client.is_connected(),
             None => false,
         }
     }
 
-    fn disconnect(&mut self) {
+    pub fn disconnect(&mut self) {
         if self.ib.is_some() {
             self.ib = None;
         }
     }
 
-    async fn get_account_values(&self) -> Option<Vec<String>> {
+    pub async fn get_account_values(&self) -> Option<Vec<String>> {
         let mut accounts;
         let mut results = Vec::new();
         if !self.is_connected() {
@@ -94,52 +98,44 @@ impl ConnectorTrait for Connector {
                         val.account.unwrap()
                     ));
                 }
+                AccountUpdate::End => break,
                 other => println!("Other update: {:?}", other),
             }
         }
         Some(results)
     }
 
-    async fn get_positions(&self) -> Option<Vec<String>> {
+    pub async fn get_positions(&self) -> Option<Vec<Position>> {
         if !self.is_connected() {
             return None;
         }
-        let position_subscription = self.ib.as_ref().unwrap().positions().await;
-        let results = match position_subscription {
-            Ok(mut positions) => {
-                let mut res = Vec::new();
-                while let Some(position) = positions.next().await {
-                    let pos = position.unwrap();
-                    match pos {
-                        ibapi::prelude::PositionUpdate::Position(pos_value) => {
-                            println!(
-                                "Account: {}, Contract: {:?}, Position: {}, Avg cost: {}",
-                                pos_value.account,
-                                pos_value.contract,
-                                pos_value.position,
-                                pos_value.average_cost
-                            );
-                            res.push(format!(
-                                "Account: {}, Contract: {:?}, Position: {}, Avg cost: {}",
-                                pos_value.account,
-                                pos_value.contract,
-                                pos_value.position,
-                                pos_value.average_cost
-                            ));
-                        }
-                        _ => {
-                            println!("Other position data: {:?}", pos);
-                        }
-                    }
-                }
-                res
+        let mut positions = match self.ib.as_ref().unwrap().positions().await {
+            Ok(positions) => positions,
+            Err(e) => {
+                println!("Error requesting positions: {:?}", e);
+                return None;
             }
-            Err(_) => Vec::new(),
         };
+        let mut results = Vec::new();
+        while let Some(position) = positions.next().await {
+            match position {
+                Ok(PositionUpdate::Position(pos_value)) => results.push(Position {
+                    account: pos_value.account,
+                    symbol: pos_value.contract.symbol.0,
+                    quantity: pos_value.position,
+                    average_cost: pos_value.average_cost,
+                }),
+                Ok(PositionUpdate::PositionEnd) => break,
+                Err(e) => {
+                    println!("Error reading positions: {:?}", e);
+                    return None;
+                }
+            }
+        }
         Some(results)
     }
 
-    async fn market_data(&self, ticker: &str) -> Option<f64> {
+    pub async fn market_data(&self, ticker: &str) -> Option<f64> {
         //Get market data for a ticker
         //Returns: current_price or None
         let mut subbed_hash = HashMap::new();
@@ -164,7 +160,8 @@ impl ConnectorTrait for Connector {
                 println!("Error getting contract details: {:?}", e);
             }
         }
-        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        let wait = CONFIG.timeouts.market_data();
+        tokio::time::sleep(wait).await;
         let mut current_price = None;
 
         for _ in 0..10 {
@@ -185,7 +182,7 @@ impl ConnectorTrait for Connector {
                 }
             }
 
-            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+            tokio::time::sleep(wait / 2).await;
         }
 
         match current_price {
@@ -220,11 +217,12 @@ impl ConnectorTrait for Connector {
         }
     }
 
-    async fn get_lod_hod(&self, ticker: &str) -> (f64, f64) {
+    pub async fn get_lod_hod(&self, ticker: &str) -> (f64, f64) {
         let stock = ibapi::contracts::Contract::stock(ticker);
         let contract = stock.build();
-        let interval_end = Some(datetime!(2023-04-11 20:00 UTC));
-        let duration = Duration::seconds(1);
+        // Up to now, over today's regular session.
+        let interval_end = None;
+        let duration = Duration::days(1);
         let bar_size = HistoricalBarSize::Min;
         let what_to_show = Some(WhatToShow::Trades);
         let trading_hours = TradingHours::Regular;
@@ -243,7 +241,7 @@ impl ConnectorTrait for Connector {
             .await;
 
         match historical_data {
-            Ok(bars) => {
+            Ok(bars) if !bars.bars.is_empty() => {
                 let mut lod = f64::MAX;
                 let mut hod = f64::MIN;
 
@@ -257,175 +255,659 @@ impl ConnectorTrait for Connector {
                 });
                 (lod, hod)
             }
-            Err(_) => (0.0, 0.0),
+            _ => (0.0, 0.0),
         }
     }
 
-    //TODO other order types where different stops are needed
-    async fn submit_order(
+    /// Starts streaming `ticker` into the quote cache unless it is already streaming.
+    pub async fn watch_quotes(&self, ticker: &str) -> Result<(), String> {
+        if !self.is_connected() {
+            return Err("Not connected to IB Gateway".to_string());
+        }
+        {
+            let mut quotes = QUOTES.write().await;
+            if quotes.contains_key(ticker) {
+                return Ok(());
+            }
+            quotes.insert(
+                ticker.to_string(),
+                Quote {
+                    symbol: ticker.to_string(),
+                    ..Default::default()
+                },
+            );
+        }
+        let contract = ibapi::contracts::Contract::stock(ticker).build();
+        match self
+            .ib
+            .as_ref()
+            .unwrap()
+            .market_data(&contract)
+            .subscribe()
+            .await
+        {
+            Ok(subscription) => {
+                tokio::spawn(quotes::stream(ticker.to_string(), subscription));
+                Ok(())
+            }
+            Err(e) => {
+                QUOTES.write().await.remove(ticker);
+                Err(format!("Error subscribing to {}: {:?}", ticker, e))
+            }
+        }
+    }
+
+    /// The last `count` completed bars of today's regular session.
+    pub async fn recent_bars(
+        &self,
+        ticker: &str,
+        bar_size: HistoricalBarSize,
+        count: usize,
+    ) -> Result<Vec<broker::Bar>, String> {
+        if !self.is_connected() {
+            return Err("Not connected to IB Gateway".to_string());
+        }
+        let contract = ibapi::contracts::Contract::stock(ticker).build();
+        let mut bars = self
+            .ib
+            .as_ref()
+            .unwrap()
+            .historical_data(
+                &contract,
+                None,
+                Duration::days(1),
+                bar_size,
+                Some(WhatToShow::Trades),
+                TradingHours::Regular,
+            )
+            .await
+            .map_err(|e| format!("Error requesting bars: {:?}", e))?
+            .bars;
+        // The last bar is still forming.
+        bars.pop();
+        let skip = bars.len().saturating_sub(count);
+        Ok(bars.split_off(skip).iter().map(broker::Bar::from).collect())
+    }
+
+    pub async fn preview_order(
         &self,
         ticker: &str,
         qty: i32,
-        stop_price: f64,
-        entry_price: f64,
         action: String,
-    ) -> (bool, String) {
+    ) -> Result<OrderPreview, String> {
         if !self.is_connected() {
-            return (false, "Not connected to IB Gateway".to_string());
+            return Err("Not connected to IB Gateway".to_string());
         }
+        let ib = self.ib.as_ref().unwrap();
         let contract = ibapi::contracts::Contract::stock(ticker).build();
+        // Same entry as submit_order, flagged what-if so IBKR only evaluates it.
+        let order = Order {
+            action: trading::parse_action(&action)?,
+            order_type: "MKT".to_string(),
+            total_quantity: qty as f64,
+            what_if: true,
+            ..Default::default()
+        };
 
-        match OrderType::Market {
-            OrderType::Market => {
-                let mut order = Order {
-                    action: match action.as_str() {
-                        "BUY" => Action::Buy,
-                        "SELL" => Action::Sell,
-                        _ => Action::Buy,
-                    },
-                    order_type: "".to_string(),
-                    total_quantity: qty as f64,
-                    ..Default::default()
-                };
+        let mut responses = ib
+            .place_order(ib.next_order_id(), &contract, &order)
+            .await
+            .map_err(|e| format!("Error requesting preview: {:?}", e))?;
+        let preview = async {
+            while let Some(response) = responses.next().await {
+                match response {
+                    Ok(PlaceOrder::OpenOrder(data)) => {
+                        return Ok(OrderPreview::from(&data.order_state));
+                    }
+                    Ok(PlaceOrder::Message(notice)) => {
+                        return Err(format!("Preview rejected: {}", notice.message));
+                    }
+                    Ok(_) => {}
+                    Err(e) => return Err(format!("Error reading preview: {:?}", e)),
+                }
+            }
+            Err("No preview returned".to_string())
+        };
+        tokio::time::timeout(PREVIEW_TIMEOUT, preview)
+            .await
+            .unwrap_or(Err("Timed out waiting for preview".to_string()))
+    }
 
-                let order_id = self.ib.as_ref().unwrap().next_order_id();
+    pub async fn refresh_open_orders(&self, all: bool) -> bool {
+        if !self.is_connected() {
+            return false;
+        }
+        let ib = self.ib.as_ref().unwrap();
+        let subscription = if all {
+            ib.all_open_orders().await
+        } else {
+            ib.open_orders().await
+        };
+        let mut orders = match subscription {
+            Ok(orders) => orders,
+            Err(e) => {
+                println!("Error requesting open orders: {:?}", e);
+                return false;
+            }
+        };
+        while let Some(update) = orders.next().await {
+            match update {
+                Ok(Orders::OrderData(data)) => ORDER_BOOK.write().await.apply_open_order(&data),
+                Ok(Orders::OrderStatus(status)) => {
+                    ORDER_BOOK.write().await.apply(&(&status).into())
+                }
+                Ok(Orders::Notice(notice)) => println!("Open orders notice: {:?}", notice),
+                Err(e) => {
+                    println!("Error reading open orders: {:?}", e);
+                    return false;
+                }
+            }
+        }
+        true
+    }
 
-                let mut trade = self
-                    .ib
-                    .as_ref()
-                    .unwrap()
-                    .place_order(order_id, &contract, &order)
-                    .await
-                    .unwrap();
-
-                while let Some(status) = trade.next().await {
-                    match status {
-                        Ok(placeorder) => {
-                            if let PlaceOrder::OrderStatus(order_status) = placeorder {
-                                if order_status.status != "Filled" {
-                                    return (false, "Market order was not filled.".to_string());
-                                }
-                                let avg_fill_price = order_status.average_fill_price;
-                                let price_diff = if action == "BUY" {
-                                    avg_fill_price - stop_price
-                                } else {
-                                    stop_price - avg_fill_price
-                                };
-
-                                let stop_prices = [
-                                    if action == "BUY" {
-                                        (stop_price + price_diff * 2.0 / 3.0 * 100.0).round()
-                                            / 100.0
-                                    } else {
-                                        (stop_price - price_diff * 2.0 / 3.0 * 100.0).round()
-                                            / 100.0
-                                    },
-                                    if action == "BUY" {
-                                        (stop_price + price_diff * 1.0 / 3.0 * 100.0).round()
-                                            / 100.0
-                                    } else {
-                                        (stop_price - price_diff * 1.0 / 3.0 * 100.0).round()
-                                            / 100.0
-                                    },
-                                    (stop_price * 100.0).round() / 100.0,
-                                ];
-
-                                let stop_sizes = [qty / 3, qty / 3, qty - 2 * (qty / 3)];
-
-                                for (sp, sq) in stop_prices.iter().zip(stop_sizes.iter()) {
-                                    order.action = if action == "BUY" {
-                                        Action::Buy
-                                    } else {
-                                        Action::Sell
-                                    };
-                                    order.order_type = "STOP".to_string();
-                                    order.total_quantity = *sq as f64;
-                                    order.aux_price = Some(*sp);
-
-                                    let stop_order_id = self.ib.as_ref().unwrap().next_order_id();
-                                    let _ = self
-                                        .ib
-                                        .as_ref()
-                                        .unwrap()
-                                        .place_order(stop_order_id, &contract, &order)
-                                        .await;
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            return (false, format!("Error placing order: {:?}", e));
-                        }
+    pub async fn executions(&self, query: &ExecutionQuery) -> Result<Vec<ExecutionRecord>, String> {
+        // Fills from earlier sessions are still in the database when we are offline.
+        if self.is_connected() {
+            let filter = ExecutionFilter {
+                account_code: query.account.clone().unwrap_or_default(),
+                time: query.since.clone().unwrap_or_default(),
+                symbol: query.symbol.clone().unwrap_or_default(),
+                side: query.side.clone().unwrap_or_default(),
+                ..Default::default()
+            };
+            let mut reports = self
+                .ib
+                .as_ref()
+                .unwrap()
+                .executions(filter)
+                .await
+                .map_err(|e| format!("Error requesting executions: {:?}", e))?;
+            while let Some(update) = reports.next().await {
+                match update {
+                    Ok(Executions::ExecutionData(data)) => {
+                        let order = ORDER_BOOK
+                            .read()
+                            .await
+                            .get(data.execution.client_id, data.execution.order_id);
+                        executions::persist_execution(&data, order.as_ref());
+                    }
+                    Ok(Executions::CommissionReport(report)) => {
+                        executions::persist_commission(&report)
                     }
+                    Ok(Executions::Notice(notice)) => println!("Executions notice: {:?}", notice),
+                    Err(e) => return Err(format!("Error reading executions: {:?}", e)),
                 }
             }
-            OrderType::Limit => {
-                let order = Order {
-                    action: match action.as_str() {
-                        "BUY" => Action::Buy,
-                        "SELL" => Action::Sell,
-                        _ => Action::Buy,
-                    },
-                    order_type: "STOP".to_string(),
-                    total_quantity: qty as f64,
-                    aux_price: Some(stop_price),
-                    ..Default::default()
-                };
-                let order_id = self.ib.as_ref().unwrap().next_order_id();
-                let _trade = self
-                    .ib
-                    .as_ref()
-                    .unwrap()
-                    .place_order(order_id, &contract, &order)
-                    .await
-                    .unwrap();
+        }
+        let conn = DATABASE.lock().unwrap();
+        executions::query(&conn, query).map_err(|e| format!("Error reading executions: {}", e))
+    }
+
+    pub async fn size_position(
+        &self,
+        ticker: &str,
+        entry_price: Option<f64>,
+        stop_price: f64,
+        risk_percent: Option<f64>,
+    ) -> Result<PositionSize, String> {
+        if !self.is_connected() {
+            return Err("Not connected to IB Gateway".to_string());
+        }
+        let settings = &CONFIG.sizing;
+        let risk_percent = risk_percent
+            .or_else(|| CONFIG.risk_percent())
+            .ok_or("No risk percent given or configured")?;
+        let entry_price = match entry_price.filter(|p| *p > 0.0) {
+            Some(price) => price,
+            None => self
+                .market_data(ticker)
+                .await
+                .ok_or(format!("No quote for {} to size against", ticker))?,
+        };
+
+        let values = self
+            .account_numbers()
+            .await
+            .ok_or("Could not read account values")?;
+        let equity = match settings.fixed_equity {
+            Some(equity) => equity,
+            None => *values
+                .get(&settings.equity_basis)
+                .ok_or(format!("Account value {} not found", settings.equity_basis))?,
+        };
+        let limits = SizingLimits {
+            lot_size: settings.lot_size,
+            buying_power: values.get("BuyingPower").copied(),
+            max_position_value: settings.max_position_value,
+        };
+        sizing::size_position(equity, entry_price, stop_price, risk_percent, &limits)
+    }
+
+    pub async fn cancel_order(&self, order_id: i32) -> (bool, String) {
+        if !self.is_connected() {
+            return (false, "Not connected to IB Gateway".to_string());
+        }
+        if let Err(e) = self.owned_working_order(order_id).await {
+            return (false, e);
+        }
+        let mut cancellation = match self.ib.as_ref().unwrap().cancel_order(order_id, "").await {
+            Ok(cancellation) => cancellation,
+            Err(e) => {
                 return (
-                    true,
-                    format!(
-                        "Limit order to {} {} shares of {} at ${:.2} submitted.",
-                        action, qty, ticker, entry_price
-                    ),
+                    false,
+                    format!("Error cancelling order {}: {:?}", order_id, e),
                 );
             }
-            OrderType::Stop => {
-                let order = Order {
-                    action: match action.as_str() {
-                        "BUY" => Action::Buy,
-                        "SELL" => Action::Sell,
-                        _ => Action::Buy,
-                    },
-                    order_type: "STOP".to_string(),
-                    total_quantity: qty as f64,
-                    aux_price: Some(stop_price),
-                    ..Default::default()
-                };
-
-                let order_id = self.ib.as_ref().unwrap().next_order_id();
-                let _trade = self
-                    .ib
-                    .as_ref()
-                    .unwrap()
-                    .place_order(order_id, &contract, &order)
-                    .await
-                    .unwrap();
-                return (
+        };
+        match cancellation.next().await {
+            Some(Ok(CancelOrder::OrderStatus(status))) => {
+                ORDER_BOOK.write().await.apply(&(&status).into());
+                (
                     true,
+                    format!("Order {} cancel requested: {}", order_id, status.status),
+                )
+            }
+            // TWS reports a successful cancel as notice 202 "Order Canceled".
+            Some(Ok(CancelOrder::Notice(notice))) if notice.code == 202 => {
+                (true, format!("Order {} cancelled.", order_id))
+            }
+            Some(Ok(CancelOrder::Notice(notice))) => (
+                false,
+                format!("Order {} was not cancelled: {}", order_id, notice.message),
+            ),
+            Some(Err(e)) => (
+                false,
+                format!("Error cancelling order {}: {:?}", order_id, e),
+            ),
+            None => (true, format!("Order {} cancel requested.", order_id)),
+        }
+    }
+
+    pub async fn cancel_all(&self, symbol: Option<&str>) -> (bool, String) {
+        if !self.is_connected() {
+            return (false, "Not connected to IB Gateway".to_string());
+        }
+        let client_id = self.ib.as_ref().unwrap().client_id();
+        let order_ids: Vec<i32> = ORDER_BOOK
+            .read()
+            .await
+            .list()
+            .into_iter()
+            .filter(|o| o.is_working() && o.client_id == client_id)
+            .filter(|o| symbol.is_none_or(|s| o.symbol == s))
+            .map(|o| o.order_id)
+            .collect();
+
+        let mut failures = Vec::new();
+        for order_id in &order_ids {
+            let (ok, message) = self.cancel_order(*order_id).await;
+            if !ok {
+                failures.push(message);
+            }
+        }
+        if failures.is_empty() {
+            (
+                true,
+                format!("Cancel requested for {} orders.", order_ids.len()),
+            )
+        } else {
+            (false, failures.join("; "))
+        }
+    }
+
+    /// Cancels every open order on the account, whichever client id placed it.
+    pub async fn global_cancel(&self) -> Result<(), String> {
+        if !self.is_connected() {
+            return Err("Not connected to IB Gateway".to_string());
+        }
+        self.ib
+            .as_ref()
+            .unwrap()
+            .global_cancel()
+            .await
+            .map_err(|e| format!("Error cancelling all orders: {:?}", e))
+    }
+
+    /// The symbol's price increment, 0.01 when its contract details are unavailable.
+    pub async fn min_tick(&self, ticker: &str) -> f64 {
+        let Some(ib) = self.ib.as_ref() else {
+            return 0.01;
+        };
+        let contract = Contract::stock(ticker).build();
+        match ib.contract_details(&contract).await {
+            Ok(details) => details.first().map(|d| d.min_tick).unwrap_or(0.01),
+            Err(_) => 0.01,
+        }
+    }
+
+    pub async fn modify_order(
+        &self,
+        order_id: i32,
+        quantity: Option<f64>,
+        limit_price: Option<f64>,
+        stop_price: Option<f64>,
+    ) -> (bool, String) {
+        if !self.is_connected() {
+            return (false, "Not connected to IB Gateway".to_string());
+        }
+        let tracked = match self.owned_working_order(order_id).await {
+            Ok(tracked) => tracked,
+            Err(e) => return (false, e),
+        };
+
+        let mut order = tracked.order.clone();
+        if let Some(quantity) = quantity {
+            if quantity <= tracked.filled {
+                return (
+                    false,
                     format!(
-                        "Stop order to {} {} shares of {} at stop ${:.2} submitted.",
-                        action, qty, ticker, stop_price
+                        "Quantity {} is not above the {} already filled",
+                        quantity, tracked.filled
                     ),
                 );
             }
+            order.total_quantity = quantity;
+        }
+        if limit_price.is_some() {
+            order.limit_price = limit_price;
+        }
+        if stop_price.is_some() {
+            order.aux_price = stop_price;
+        }
+
+        match self
+            .ib
+            .as_ref()
+            .unwrap()
+            .place_order(order_id, &tracked.contract, &order)
+            .await
+        {
+            Ok(subscription) => {
+                ORDER_BOOK
+                    .write()
+                    .await
+                    .record_modified(tracked.client_id, order_id, &order);
+                order_book::spawn_tracker(order_id, subscription);
+                (true, format!("Order {} modification submitted.", order_id))
+            }
+            Err(e) => (
+                false,
+                format!("Error modifying order {}: {:?}", order_id, e),
+            ),
+        }
+    }
+}
+
+impl Connector {
+    /// The account's daily P&L stream, which loss limits are enforced from.
+    pub async fn pnl_updates(&self) -> Option<Subscription<PnL>> {
+        let ib = self.ib.as_ref()?;
+        let account = match ib.managed_accounts().await {
+            Ok(accounts) if !accounts.is_empty() => accounts[0].clone(),
             _ => {
-                return (
-                    false,
-                    "Order submission logic not yet implemented.".to_string(),
-                );
+                println!("No managed account found, loss limits are not monitored");
+                return None;
             }
         };
+        match ib.pnl(&AccountId(account), None).await {
+            Ok(subscription) => Some(subscription),
+            Err(e) => {
+                println!("Error subscribing to P&L: {:?}", e);
+                None
+            }
+        }
+    }
+
+    /// Every order message the client receives, including ones for ids whose own stream
+    /// a cancel or modify has taken over.
+    pub async fn order_updates(&self) -> Option<Subscription<OrderUpdate>> {
+        match self.ib.as_ref()?.order_update_stream().await {
+            Ok(subscription) => Some(subscription),
+            Err(e) => {
+                println!("Error subscribing to order updates: {:?}", e);
+                None
+            }
+        }
+    }
+
+    /// Numeric account values keyed by IBKR tag (e.g. `NetLiquidation`).
+    async fn account_numbers(&self) -> Option<HashMap<String, f64>> {
+        let mut updates = self
+            .ib
+            .as_ref()?
+            .account_updates(&AccountId("".into()))
+            .await
+            .ok()?;
+        let mut values = HashMap::new();
+        while let Some(update) = updates.next().await {
+            match update {
+                Ok(AccountUpdate::AccountValue(val)) => {
+                    // Per-currency keys are also reported as BASE, which wins when present.
+                    if let Ok(number) = val.value.parse::<f64>()
+                        && (val.currency == "BASE" || !values.contains_key(&val.key))
+                    {
+                        values.insert(val.key, number);
+                    }
+                }
+                Ok(AccountUpdate::End) => break,
+                Ok(_) => {}
+                Err(e) => {
+                    println!("Error reading account values: {:?}", e);
+                    return None;
+                }
+            }
+        }
+        Some(values)
+    }
+
+    /// Looks up a working order, refusing ones placed by another client id.
+    async fn owned_working_order(&self, order_id: i32) -> Result<TrackedOrder, String> {
+        let client_id = self.ib.as_ref().unwrap().client_id();
+        let book = ORDER_BOOK.read().await;
+        match book.get(client_id, order_id) {
+            Some(tracked) if !tracked.is_working() => Err(format!(
+                "Order {} is no longer working: {}",
+                order_id, tracked.status
+            )),
+            Some(tracked) => Ok(tracked),
+            None => match book.list().into_iter().find(|o| o.order_id == order_id) {
+                Some(other) => Err(format!(
+                    "Order {} belongs to client id {}, not {}",
+                    order_id, other.client_id, client_id
+                )),
+                None => Err(format!("Order {} is not tracked", order_id)),
+            },
+        }
+    }
+    /// Places an order, records it in the order book and keeps tracking its updates.
+    pub async fn place_tracked(&self, contract: &Contract, order: &Order) -> Result<i32, String> {
+        let ib = self.ib.as_ref().unwrap();
+        let order_id = ib.next_order_id();
+        ORDER_BOOK
+            .write()
+            .await
+            .record_placed(order_id, ib.client_id(), contract, order);
+
+        match ib.place_order(order_id, contract, order).await {
+            Ok(subscription) => {
+                order_book::spawn_tracker(order_id, subscription);
+                Ok(order_id)
+            }
+            Err(e) => {
+                let reason = format!("Error placing order: {:?}", e);
+                ORDER_BOOK
+                    .write()
+                    .await
+                    .mark_rejected(ib.client_id(), order_id, &reason);
+                Err(reason)
+            }
+        }
+    }
+}
+
+impl From<&Bar> for broker::Bar {
+    fn from(bar: &Bar) -> Self {
+        broker::Bar {
+            time: bar.date,
+            open: bar.open,
+            high: bar.high,
+            low: bar.low,
+            close: bar.close,
+            volume: bar.volume,
+        }
+    }
+}
+
+/// The IBKR backend: a shared `Connector` behind a lock, cloned into handlers and tasks.
+#[derive(Clone, Default)]
+pub struct IbkrBroker {
+    connector: Arc<RwLock<Connector>>,
+}
+
+impl IbkrBroker {
+    /// Connects without following the account's P&L, for callers that only want quotes.
+    pub async fn connect_market_data(&self, address: &str, port: u16, client_id: i32) -> bool {
+        let mut connector = self.connector.write().await;
+        connector.connect(address, port, client_id).await
+    }
+}
+
+impl Broker for IbkrBroker {
+    async fn connect(&self, address: &str, port: u16, client_id: i32) -> bool {
+        if !self.connect_market_data(address, port, client_id).await {
+            return false;
+        }
+        if let Some(subscription) = self.connector.read().await.pnl_updates().await {
+            tokio::spawn(kill_switch::monitor_pnl(subscription, self.clone()));
+        }
+        if let Some(subscription) = self.connector.read().await.order_updates().await {
+            tokio::spawn(order_book::track_statuses(subscription));
+        }
+        true
+    }
+
+    async fn is_connected(&self) -> bool {
+        self.connector.read().await.is_connected()
+    }
+
+    async fn client_id(&self) -> Option<i32> {
+        let connector = self.connector.read().await;
+        match &connector.ib {
+            Some(ib) if ib.is_connected() => Some(ib.client_id()),
+            _ => None,
+        }
+    }
+
+    async fn disconnect(&self) {
+        quotes::cancel_all().await;
+        self.connector.write().await.disconnect();
+    }
+
+    async fn account_values(&self) -> Option<Vec<String>> {
+        self.connector.read().await.get_account_values().await
+    }
+
+    async fn positions(&self) -> Option<Vec<Position>> {
+        self.connector.read().await.get_positions().await
+    }
+
+    async fn last_price(&self, symbol: &str) -> Option<f64> {
+        self.connector.read().await.market_data(symbol).await
+    }
+
+    async fn quote(&self, symbol: &str) -> Result<Quote, String> {
+        self.connector.read().await.watch_quotes(symbol).await?;
+        quotes::get(symbol)
+            .await
+            .ok_or(format!("No quote for {}", symbol))
+    }
+
+    async fn day_range(&self, symbol: &str) -> (f64, f64) {
+        self.connector.read().await.get_lod_hod(symbol).await
+    }
+
+    async fn min_tick(&self, symbol: &str) -> f64 {
+        self.connector.read().await.min_tick(symbol).await
+    }
+
+    async fn recent_bars(
+        &self,
+        symbol: &str,
+        bar_size: HistoricalBarSize,
+        count: usize,
+    ) -> Result<Vec<broker::Bar>, String> {
+        let connector = self.connector.read().await;
+        connector.recent_bars(symbol, bar_size, count).await
+    }
+
+    async fn size_position(
+        &self,
+        symbol: &str,
+        entry_price: Option<f64>,
+        stop_price: f64,
+        risk_percent: Option<f64>,
+    ) -> Result<PositionSize, String> {
+        let connector = self.connector.read().await;
+        connector
+            .size_position(symbol, entry_price, stop_price, risk_percent)
+            .await
+    }
+
+    async fn place_order(&self, symbol: &str, order: &Order) -> Result<i32, String> {
+        let connector = self.connector.read().await;
+        if !connector.is_connected() {
+            return Err(trading::NOT_CONNECTED.to_string());
+        }
+        let contract = Contract::stock(symbol).build();
+        connector.place_tracked(&contract, order).await
+    }
+
+    async fn preview_order(
+        &self,
+        symbol: &str,
+        qty: i32,
+        action: &str,
+    ) -> Result<OrderPreview, String> {
+        let connector = self.connector.read().await;
+        connector
+            .preview_order(symbol, qty, action.to_string())
+            .await
+    }
+
+    async fn refresh_orders(&self, all: bool) -> bool {
+        self.connector.read().await.refresh_open_orders(all).await
+    }
+
+    async fn executions(&self, query: &ExecutionQuery) -> Result<Vec<ExecutionRecord>, String> {
+        self.connector.read().await.executions(query).await
+    }
+
+    async fn cancel_order(&self, order_id: i32) -> (bool, String) {
+        self.connector.read().await.cancel_order(order_id).await
+    }
+
+    async fn cancel_all(&self, symbol: Option<&str>) -> (bool, String) {
+        self.connector.read().await.cancel_all(symbol).await
+    }
+
+    async fn global_cancel(&self) -> (bool, String) {
+        match self.connector.read().await.global_cancel().await {
+            Ok(()) => (true, "Global cancel requested.".to_string()),
+            Err(e) => (false, e),
+        }
+    }
 
-        (
-            true,
-            "Order submission logic not yet implemented.".to_string(),
-        )
+    async fn modify_order(
+        &self,
+        order_id: i32,
+        quantity: Option<f64>,
+        limit_price: Option<f64>,
+        stop_price: Option<f64>,
+    ) -> (bool, String) {
+        let connector = self.connector.read().await;
+        connector
+            .modify_order(order_id, quantity, limit_price, stop_price)
+            .await
     }
 }
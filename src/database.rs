@@ -0,0 +1,48 @@
+use std::sync::{Mutex, OnceLock};
+
+use rusqlite::Connection;
+
+use crate::auth;
+use crate::config::CONFIG;
+use crate::executions;
+use crate::journal;
+use crate::order_keys;
+use crate::triggers;
+
+/// Opens the local database and creates any missing tables.
+pub fn open(path: &str) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(path)?;
+    auth::create_tables(&conn)?;
+    executions::create_tables(&conn)?;
+    journal::create_tables(&conn)?;
+    order_keys::create_tables(&conn)?;
+    triggers::create_tables(&conn)?;
+    Ok(conn)
+}
+
+/// The file `DATABASE` opens, fixed by whichever comes first: its first use or
+/// `use_simulator_database`.
+static DATABASE_PATH: OnceLock<String> = OnceLock::new();
+
+/// Sends this process's journal and executions to the simulator's own database.
+/// Has to run before anything touches `DATABASE`.
+pub fn use_simulator_database() {
+    let path = CONFIG.simulator_database_path();
+    let chosen = DATABASE_PATH.get_or_init(|| path.clone());
+    if *chosen != path {
+        println!(
+            "Database {} is already open, simulated trades will be recorded there",
+            chosen
+        );
+    }
+}
+
+lazy_static::lazy_static! {
+    pub(crate) static ref DATABASE: Mutex<Connection> = {
+        let path = DATABASE_PATH.get_or_init(|| CONFIG.database_path.clone());
+        Mutex::new(open(path).unwrap_or_else(|e| {
+            println!("Error opening {}: {}, falling back to memory", path, e);
+            open(":memory:").unwrap()
+        }))
+    };
+}
@@ -0,0 +1,273 @@
+use ibapi::orders::{CommissionReport, ExecutionData};
+use rusqlite::{Connection, params};
+use serde::Serialize;
+
+use crate::database::DATABASE;
+use crate::order_book::TrackedOrder;
+
+/// A fill joined with its commission report and the order that produced it.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ExecutionRecord {
+    pub execution_id: String,
+    pub order_id: i32,
+    pub perm_id: i32,
+    pub client_id: i32,
+    /// Entry order id of the bracket this fill belongs to, when it came from this panel.
+    pub trade_id: Option<i32>,
+    pub oca_group: String,
+    /// The order's `order_ref`, which carries its `client_order_key` when one was given.
+    pub order_ref: String,
+    pub symbol: String,
+    pub side: String,
+    pub shares: f64,
+    pub price: f64,
+    pub time: String,
+    pub account: String,
+    pub exchange: String,
+    pub commission: Option<f64>,
+    pub commission_currency: Option<String>,
+    pub realized_pnl: Option<f64>,
+}
+
+/// Filters for `/executions`; every field is optional.
+#[derive(Clone, Debug, Default)]
+pub struct ExecutionQuery {
+    pub symbol: Option<String>,
+    /// BUY or SELL.
+    pub side: Option<String>,
+    pub account: Option<String>,
+    /// Only fills at or after this time, as `yyyymmdd hh:mm:ss`.
+    pub since: Option<String>,
+}
+
+pub fn create_tables(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS executions (
+            execution_id TEXT PRIMARY KEY,
+            order_id INTEGER NOT NULL,
+            perm_id INTEGER NOT NULL,
+            client_id INTEGER NOT NULL,
+            trade_id INTEGER,
+            oca_group TEXT NOT NULL,
+            order_ref TEXT NOT NULL DEFAULT '',
+            symbol TEXT NOT NULL,
+            side TEXT NOT NULL,
+            shares REAL NOT NULL,
+            price REAL NOT NULL,
+            time TEXT NOT NULL,
+            sort_time TEXT NOT NULL,
+            account TEXT NOT NULL,
+            exchange TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS commissions (
+            execution_id TEXT PRIMARY KEY,
+            commission REAL NOT NULL,
+            currency TEXT NOT NULL,
+            realized_pnl REAL
+        );",
+    )
+}
+
+/// Stores a fill, keeping any order link recorded earlier when `order` is unknown.
+pub fn save_execution(
+    conn: &Connection,
+    data: &ExecutionData,
+    order: Option<&TrackedOrder>,
+) -> rusqlite::Result<()> {
+    let execution = &data.execution;
+    conn.execute(
+        "INSERT INTO executions (execution_id, order_id, perm_id, client_id, trade_id, oca_group,
+            order_ref, symbol, side, shares, price, time, sort_time, account, exchange)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)
+         ON CONFLICT(execution_id) DO UPDATE SET
+            trade_id = COALESCE(excluded.trade_id, executions.trade_id),
+            oca_group = CASE WHEN excluded.oca_group = '' THEN executions.oca_group
+                ELSE excluded.oca_group END",
+        params![
+            execution.execution_id,
+            execution.order_id,
+            execution.perm_id,
+            execution.client_id,
+            order.map(|o| o.trade_id()),
+            order.map(|o| o.oca_group.clone()).unwrap_or_default(),
+            execution.order_reference,
+            data.contract.symbol.0,
+            execution.side,
+            execution.shares,
+            execution.price,
+            execution.time,
+            sort_time(&execution.time),
+            execution.account_number,
+            execution.exchange,
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn save_commission(conn: &Connection, report: &CommissionReport) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO commissions (execution_id, commission, currency, realized_pnl)
+         VALUES (?1, ?2, ?3, ?4)",
+        params![
+            report.execution_id,
+            report.commission,
+            report.currency,
+            report.realized_pnl.filter(|p| p.is_finite()),
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn query(conn: &Connection, filter: &ExecutionQuery) -> rusqlite::Result<Vec<ExecutionRecord>> {
+    // IBKR reports sides as BOT/SLD.
+    let side = filter.side.as_deref().map(|s| match s {
+        "BUY" => "BOT",
+        "SELL" => "SLD",
+        other => other,
+    });
+    let mut statement = conn.prepare(
+        "SELECT e.execution_id, e.order_id, e.perm_id, e.client_id, e.trade_id, e.oca_group,
+            e.order_ref, e.symbol, e.side, e.shares, e.price, e.time, e.account, e.exchange,
+            c.commission, c.currency, c.realized_pnl
+         FROM executions e LEFT JOIN commissions c ON c.execution_id = e.execution_id
+         WHERE (?1 IS NULL OR e.symbol = ?1)
+           AND (?2 IS NULL OR e.side = ?2)
+           AND (?3 IS NULL OR e.account = ?3)
+           AND (?4 IS NULL OR e.sort_time >= ?4)
+         ORDER BY e.sort_time, e.execution_id",
+    )?;
+    let rows = statement.query_map(
+        params![
+            filter.symbol,
+            side,
+            filter.account,
+            filter.since.as_deref().map(sort_time),
+        ],
+        row_to_record,
+    )?;
+    rows.collect()
+}
+
+fn row_to_record(row: &rusqlite::Row) -> rusqlite::Result<ExecutionRecord> {
+    Ok(ExecutionRecord {
+        execution_id: row.get(0)?,
+        order_id: row.get(1)?,
+        perm_id: row.get(2)?,
+        client_id: row.get(3)?,
+        trade_id: row.get(4)?,
+        oca_group: row.get(5)?,
+        order_ref: row.get(6)?,
+        symbol: row.get(7)?,
+        side: row.get(8)?,
+        shares: row.get(9)?,
+        price: row.get(10)?,
+        time: row.get(11)?,
+        account: row.get(12)?,
+        exchange: row.get(13)?,
+        commission: row.get(14)?,
+        commission_currency: row.get(15)?,
+        realized_pnl: row.get(16)?,
+    })
+}
+
+/// Reduces IBKR's `yyyymmdd  hh:mm:ss tz` times to sortable `yyyymmddhhmmss` digits.
+fn sort_time(time: &str) -> String {
+    time.chars()
+        .filter(|c| c.is_ascii_digit())
+        .take(14)
+        .collect()
+}
+
+/// Persists a live fill; failures are logged so order tracking carries on.
+pub fn persist_execution(data: &ExecutionData, order: Option<&TrackedOrder>) {
+    let conn = DATABASE.lock().unwrap();
+    if let Err(e) = save_execution(&conn, data, order) {
+        println!(
+            "Error saving execution {}: {}",
+            data.execution.execution_id, e
+        );
+    }
+}
+
+pub fn persist_commission(report: &CommissionReport) {
+    let conn = DATABASE.lock().unwrap();
+    if let Err(e) = save_commission(&conn, report) {
+        println!("Error saving commission for {}: {}", report.execution_id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ibapi::orders::Execution;
+
+    fn execution(id: &str, side: &str, time: &str) -> ExecutionData {
+        let mut data = ExecutionData {
+            execution: Execution {
+                execution_id: id.to_string(),
+                order_id: 7,
+                side: side.to_string(),
+                shares: 10.0,
+                price: 250.5,
+                time: time.to_string(),
+                account_number: "DU123".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        data.contract.symbol = "TSLA".into();
+        data
+    }
+
+    #[test]
+    fn joins_commissions_and_filters() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables(&conn).unwrap();
+        save_execution(
+            &conn,
+            &execution("e1", "BOT", "20250102  09:31:00 US/Eastern"),
+            None,
+        )
+        .unwrap();
+        save_execution(
+            &conn,
+            &execution("e2", "SLD", "20250102  10:15:00 US/Eastern"),
+            None,
+        )
+        .unwrap();
+        save_commission(
+            &conn,
+            &CommissionReport {
+                execution_id: "e2".to_string(),
+                commission: 1.25,
+                currency: "USD".to_string(),
+                realized_pnl: Some(42.0),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let sells = query(
+            &conn,
+            &ExecutionQuery {
+                side: Some("SELL".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(sells.len(), 1);
+        assert_eq!(sells[0].commission, Some(1.25));
+        assert_eq!(sells[0].realized_pnl, Some(42.0));
+
+        let later = query(
+            &conn,
+            &ExecutionQuery {
+                since: Some("20250102 10:00:00".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(later.len(), 1);
+        assert_eq!(later[0].execution_id, "e2");
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::broker::{Bar, BarSize, Broker};
+use crate::ladder::round_to_tick;
+use crate::order_book::{ORDER_BOOK, TrackedOrder};
+use crate::quotes::Quote;
+
+/// How often rules are re-checked when no order update arrives, e.g. to follow new bars.
+const RULE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A server-side rule that moves a trade's fixed stops after entry. Stops only ever tighten.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum ExitRule {
+    /// Move the remaining stops to the entry fill once this tranche's target fills.
+    Breakeven { after_tranche: usize },
+    /// Keep the remaining stops one tick beyond the low (high for shorts) of the prior `bars`
+    /// completed bars, starting once `after_tranche`'s target fills or straight away.
+    BarTrail {
+        bars: usize,
+        bar_size: BarSize,
+        #[serde(default)]
+        after_tranche: Option<usize>,
+    },
+}
+
+impl ExitRule {
+    pub fn validate(&self, tranches: usize) -> Result<(), String> {
+        let after = match self {
+            ExitRule::Breakeven { after_tranche } => Some(*after_tranche),
+            ExitRule::BarTrail {
+                bars,
+                after_tranche,
+                ..
+            } => {
+                if *bars == 0 {
+                    return Err("bar_trail needs at least one bar".to_string());
+                }
+                *after_tranche
+            }
+        };
+        match after {
+            Some(tranche) if tranche == 0 || tranche >= tranches => Err(format!(
+                "Rule waits on tranche {}, but only tranches 1 to {} leave stops behind",
+                tranche,
+                tranches.saturating_sub(1)
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn is_active(&self, filled_targets: &HashSet<usize>) -> bool {
+        match self {
+            ExitRule::Breakeven { after_tranche } => filled_targets.contains(after_tranche),
+            ExitRule::BarTrail { after_tranche, .. } => {
+                after_tranche.is_none_or(|t| filled_targets.contains(&t))
+            }
+        }
+    }
+}
+
+/// A filled entry whose remaining stops are managed by its ladder's rules.
+#[derive(Clone, Debug)]
+pub struct ManagedTrade {
+    /// The entry order id; exits carry it in their OCA group.
+    pub trade_id: i32,
+    pub symbol: String,
+    pub is_long: bool,
+    pub fill: f64,
+    pub min_tick: f64,
+    pub rules: Vec<ExitRule>,
+}
+
+impl ManagedTrade {
+    /// The stop the active rules ask for, given which tranche targets have filled and the
+    /// completed bars so far. The tightest rule wins.
+    pub fn wanted_stop(&self, filled_targets: &HashSet<usize>, bars: &[Bar]) -> Option<f64> {
+        let stops = self
+            .rules
+            .iter()
+            .filter(|rule| rule.is_active(filled_targets))
+            .filter_map(|rule| match rule {
+                ExitRule::Breakeven { .. } => Some(round_to_tick(self.fill, self.min_tick)),
+                ExitRule::BarTrail { bars: count, .. } => {
+                    if bars.len() < *count {
+                        return None;
+                    }
+                    let prior = &bars[bars.len() - count..];
+                    let stop = if self.is_long {
+                        prior.iter().map(|b| b.low).fold(f64::MAX, f64::min) - self.min_tick
+                    } else {
+                        prior.iter().map(|b| b.high).fold(f64::MIN, f64::max) + self.min_tick
+                    };
+                    Some(round_to_tick(stop, self.min_tick))
+                }
+            });
+        if self.is_long {
+            stops.reduce(f64::max)
+        } else {
+            stops.reduce(f64::min)
+        }
+    }
+
+    /// Returns `wanted` if it is tighter than the `current` stop.
+    pub fn tightened(&self, current: Option<f64>, wanted: f64) -> Option<f64> {
+        match current {
+            Some(current) if self.is_long && wanted <= current + 1e-9 => None,
+            Some(current) if !self.is_long && wanted >= current - 1e-9 => None,
+            _ => Some(wanted),
+        }
+    }
+
+    /// Keeps `wanted` at least a tick inside the market, below the bid for a long and above
+    /// the ask for a short, so a moved stop cannot trigger on arrival. Without a quote there
+    /// is nothing to check against, so the stop is left alone.
+    pub fn within_market(&self, wanted: f64, quote: &Quote) -> Option<f64> {
+        if self.is_long {
+            let bid = quote.bid.or(quote.last)?;
+            Some(wanted.min(round_to_tick(bid - self.min_tick, self.min_tick)))
+        } else {
+            let ask = quote.ask.or(quote.last)?;
+            Some(wanted.max(round_to_tick(ask + self.min_tick, self.min_tick)))
+        }
+    }
+
+    fn bar_rule(&self, filled_targets: &HashSet<usize>) -> Option<(usize, BarSize)> {
+        self.rules.iter().find_map(|rule| match rule {
+            ExitRule::BarTrail { bars, bar_size, .. } if rule.is_active(filled_targets) => {
+                Some((*bars, *bar_size))
+            }
+            _ => None,
+        })
+    }
+}
+
+/// Tranche numbers (1-based) whose target has filled.
+pub fn filled_targets(exits: &[TrackedOrder]) -> HashSet<usize> {
+    exits
+        .iter()
+        .filter(|o| o.order_type == "LMT" && o.status == "Filled")
+        .filter_map(|o| o.oca_group.rsplit('-').next()?.parse().ok())
+        .collect()
+}
+
+/// Applies the trade's rules until none of its fixed stops are left working.
+pub async fn manage<B: Broker>(broker: B, trade: ManagedTrade) {
+    let mut updates = ORDER_BOOK.read().await.subscribe();
+    let mut timer = tokio::time::interval(RULE_CHECK_INTERVAL);
+    let mut bars = Vec::new();
+    // The last price each stop refused, so it is not sent again on every update.
+    let mut rejected: HashMap<i32, f64> = HashMap::new();
+    loop {
+        let timed_out = tokio::select! {
+            update = updates.recv() => match update {
+                Ok(order) if order.trade_id() != trade.trade_id => continue,
+                Err(broadcast::error::RecvError::Closed) => return,
+                _ => false,
+            },
+            _ = timer.tick() => true,
+        };
+
+        let exits: Vec<TrackedOrder> = ORDER_BOOK
+            .read()
+            .await
+            .list()
+            .into_iter()
+            .filter(|o| o.trade_id() == trade.trade_id && o.order_id != trade.trade_id)
+            .collect();
+        let stops: Vec<&TrackedOrder> = exits
+            .iter()
+            .filter(|o| o.order_type == "STP" && o.is_working())
+            .collect();
+        if stops.is_empty() {
+            return;
+        }
+        let filled = filled_targets(&exits);
+
+        // Bars only change with time, so refresh them on the timer rather than every update.
+        if let Some((count, bar_size)) = trade.bar_rule(&filled)
+            && timed_out
+        {
+            match broker.recent_bars(&trade.symbol, bar_size, count).await {
+                Ok(recent) => bars = recent,
+                Err(e) => println!("Error reading bars for {}: {}", trade.symbol, e),
+            }
+        }
+
+        let Some(wanted) = trade.wanted_stop(&filled, &bars) else {
+            continue;
+        };
+        if stops
+            .iter()
+            .all(|stop| trade.tightened(stop.stop_price, wanted).is_none())
+        {
+            continue;
+        }
+        let wanted = match broker.quote(&trade.symbol).await {
+            Ok(quote) => match trade.within_market(wanted, &quote) {
+                Some(wanted) => wanted,
+                None => continue,
+            },
+            Err(e) => {
+                println!("Error reading quote for {}: {}", trade.symbol, e);
+                continue;
+            }
+        };
+        for stop in stops {
+            let Some(price) = trade.tightened(stop.stop_price, wanted) else {
+                continue;
+            };
+            if rejected
+                .get(&stop.order_id)
+                .is_some_and(|r| (r - price).abs() < 1e-9)
+            {
+                continue;
+            }
+            let (ok, message) = broker
+                .modify_order(stop.order_id, None, None, Some(price))
+                .await;
+            if !ok {
+                println!("Error moving stop {}: {}", stop.order_id, message);
+                rejected.insert(stop.order_id, price);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::OffsetDateTime;
+
+    fn trade(is_long: bool, rules: Vec<ExitRule>) -> ManagedTrade {
+        ManagedTrade {
+            trade_id: 1,
+            symbol: "TSLA".to_string(),
+            is_long,
+            fill: 100.0,
+            min_tick: 0.01,
+            rules,
+        }
+    }
+
+    fn bar(low: f64, high: f64) -> Bar {
+        Bar {
+            time: OffsetDateTime::UNIX_EPOCH,
+            open: low,
+            high,
+            low,
+            close: high,
+            volume: 0.0,
+        }
+    }
+
+    #[test]
+    fn breakeven_waits_for_tranche_target() {
+        let trade = trade(true, vec![ExitRule::Breakeven { after_tranche: 1 }]);
+
+        assert_eq!(trade.wanted_stop(&HashSet::new(), &[]), None);
+        assert_eq!(trade.wanted_stop(&HashSet::from([1]), &[]), Some(100.0));
+        assert_eq!(trade.tightened(Some(98.0), 100.0), Some(100.0));
+        assert_eq!(trade.tightened(Some(101.0), 100.0), None);
+    }
+
+    #[test]
+    fn bar_trail_uses_prior_extremes() {
+        let rule = |after_tranche| ExitRule::BarTrail {
+            bars: 2,
+            bar_size: BarSize::Min5,
+            after_tranche,
+        };
+        let bars = [bar(97.0, 99.0), bar(101.0, 103.0), bar(102.0, 104.0)];
+
+        let long = trade(true, vec![rule(None)]);
+        assert_eq!(long.wanted_stop(&HashSet::new(), &bars), Some(100.99));
+
+        let short = trade(false, vec![rule(Some(1))]);
+        assert_eq!(short.wanted_stop(&HashSet::new(), &bars), None);
+        assert_eq!(short.wanted_stop(&HashSet::from([1]), &bars), Some(104.01));
+        assert_eq!(short.tightened(Some(106.0), 104.01), Some(104.01));
+    }
+
+    #[test]
+    fn keeps_moved_stops_inside_the_market() {
+        let quote = Quote {
+            bid: Some(99.5),
+            ask: Some(99.6),
+            ..Default::default()
+        };
+        let long = trade(true, vec![]);
+        assert_eq!(long.within_market(100.0, &quote), Some(99.49));
+        assert_eq!(long.within_market(98.0, &quote), Some(98.0));
+        assert_eq!(long.tightened(Some(99.49), 99.49), None);
+
+        let short = trade(false, vec![]);
+        assert_eq!(short.within_market(99.0, &quote), Some(99.61));
+        assert_eq!(short.within_market(101.0, &quote), Some(101.0));
+        assert_eq!(long.within_market(100.0, &Quote::default()), None);
+    }
+
+    #[test]
+    fn rejects_rules_on_the_last_tranche() {
+        assert!(
+            ExitRule::Breakeven { after_tranche: 3 }
+                .validate(3)
+                .is_err()
+        );
+        assert!(ExitRule::Breakeven { after_tranche: 2 }.validate(3).is_ok());
+    }
+}
@@ -0,0 +1,704 @@
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::Serialize;
+
+use crate::database::DATABASE;
+use crate::kill_switch::TradingStatus;
+use crate::order_book::{self, TrackedOrder};
+use crate::risk::RiskConfig;
+
+/// What was asked for when the trade was entered.
+#[derive(Serialize, Clone, Debug)]
+pub struct TradeRequest {
+    pub symbol: String,
+    pub action: String,
+    pub quantity: i32,
+    pub entry_price: Option<f64>,
+    pub stop_price: f64,
+    pub ladder: Option<String>,
+}
+
+/// Account and limit state at the moment the entry was sent.
+#[derive(Serialize, Clone, Debug)]
+pub struct RiskSnapshot {
+    pub trading_status: TradingStatus,
+    pub limits: RiskConfig,
+    pub risk_percent: Option<f64>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct JournalEvent {
+    pub order_id: i32,
+    /// One of `placed`, `status`, `fill`, `stop_moved`, `limit_moved` or `resized`.
+    pub kind: String,
+    pub detail: String,
+    pub at: String,
+}
+
+/// One journaled trade: the entry request, its bracket's history and the result so far.
+#[derive(Serialize, Clone, Debug)]
+pub struct JournalEntry {
+    /// The journal's own key; order ids repeat across client ids and sessions.
+    pub id: i64,
+    /// The entry order id.
+    pub trade_id: i32,
+    pub client_id: i32,
+    pub perm_id: i32,
+    pub opened_at: String,
+    pub symbol: String,
+    pub action: String,
+    pub quantity: i32,
+    pub stop_price: f64,
+    pub ladder: Option<String>,
+    pub request: serde_json::Value,
+    pub risk_snapshot: serde_json::Value,
+    pub notes: String,
+    pub entry_shares: f64,
+    pub entry_price: Option<f64>,
+    pub exit_shares: f64,
+    pub exit_price: Option<f64>,
+    pub gross_pnl: f64,
+    pub commission: f64,
+    /// P&L as reported by IBKR's commission reports, net of commissions.
+    pub realized_pnl: Option<f64>,
+    pub r_multiple: Option<f64>,
+    pub events: Vec<JournalEvent>,
+}
+
+/// Filters for `/journal`; dates are `yyyy-mm-dd` and inclusive.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct JournalQuery {
+    pub symbol: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+/// A fill belonging to a trade, as stored by the executions module.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TradeFill {
+    pub order_id: i32,
+    pub shares: f64,
+    pub price: f64,
+}
+
+pub fn create_tables(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS journal_trades (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            trade_id INTEGER NOT NULL,
+            client_id INTEGER NOT NULL,
+            perm_id INTEGER NOT NULL DEFAULT 0,
+            opened_at TEXT NOT NULL,
+            symbol TEXT NOT NULL,
+            action TEXT NOT NULL,
+            quantity INTEGER NOT NULL,
+            stop_price REAL NOT NULL,
+            ladder TEXT,
+            request TEXT NOT NULL,
+            risk_snapshot TEXT NOT NULL,
+            notes TEXT NOT NULL DEFAULT ''
+        );
+        CREATE TABLE IF NOT EXISTS journal_orders (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            journal_id INTEGER REFERENCES journal_trades (id),
+            order_id INTEGER NOT NULL,
+            client_id INTEGER NOT NULL,
+            perm_id INTEGER NOT NULL,
+            trade_id INTEGER NOT NULL,
+            symbol TEXT NOT NULL,
+            action TEXT NOT NULL,
+            order_type TEXT NOT NULL,
+            quantity REAL NOT NULL,
+            limit_price REAL,
+            stop_price REAL,
+            status TEXT NOT NULL,
+            fills INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS journal_events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            journal_id INTEGER REFERENCES journal_trades (id),
+            trade_id INTEGER NOT NULL,
+            client_id INTEGER NOT NULL,
+            order_id INTEGER NOT NULL,
+            kind TEXT NOT NULL,
+            detail TEXT NOT NULL,
+            at TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS journal_trades_order ON journal_trades (client_id, trade_id);
+        CREATE INDEX IF NOT EXISTS journal_orders_order ON journal_orders (client_id, order_id);
+        CREATE INDEX IF NOT EXISTS journal_orders_journal ON journal_orders (journal_id);
+        CREATE INDEX IF NOT EXISTS journal_events_journal ON journal_events (journal_id);",
+    )
+}
+
+/// Adds a trade to the journal, returning its journal id.
+pub fn save_trade(
+    conn: &Connection,
+    trade_id: i32,
+    client_id: i32,
+    request: &TradeRequest,
+    snapshot: &RiskSnapshot,
+) -> rusqlite::Result<i64> {
+    conn.execute(
+        "INSERT INTO journal_trades (trade_id, client_id, opened_at, symbol, action, quantity,
+            stop_price, ladder, request, risk_snapshot)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            trade_id,
+            client_id,
+            order_book::now(),
+            request.symbol,
+            request.action,
+            request.quantity,
+            request.stop_price,
+            request.ladder,
+            serde_json::to_string(request).unwrap_or_default(),
+            serde_json::to_string(snapshot).unwrap_or_default(),
+        ],
+    )?;
+    let id = conn.last_insert_rowid();
+    // The entry's first updates can be recorded before the trade is.
+    for table in ["journal_orders", "journal_events"] {
+        conn.execute(
+            &format!(
+                "UPDATE {} SET journal_id = ?1
+                 WHERE client_id = ?2 AND trade_id = ?3 AND journal_id IS NULL",
+                table
+            ),
+            params![id, client_id, trade_id],
+        )?;
+    }
+    Ok(id)
+}
+
+/// Stores the latest state of an order and appends an event for each change since the last one.
+///
+/// The order belongs to the latest journaled trade with its client id and trade id. Within
+/// that trade it is matched on order id, and on perm id once IBKR has assigned one, so a
+/// reused order id starts a new row instead of overwriting an old order.
+pub fn save_order(conn: &Connection, order: &TrackedOrder) -> rusqlite::Result<()> {
+    let journal_id: Option<i64> = conn.query_row(
+        "SELECT MAX(id) FROM journal_trades WHERE client_id = ?1 AND trade_id = ?2",
+        params![order.client_id, order.trade_id()],
+        |row| row.get(0),
+    )?;
+    let previous = conn
+        .query_row(
+            "SELECT id, quantity, limit_price, stop_price, status, fills
+             FROM journal_orders
+             WHERE client_id = ?1 AND order_id = ?2 AND journal_id IS ?4
+               AND (perm_id = 0 OR ?3 = 0 OR perm_id = ?3)
+             ORDER BY id DESC LIMIT 1",
+            params![order.client_id, order.order_id, order.perm_id, journal_id],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, f64>(1)?,
+                    row.get::<_, Option<f64>>(2)?,
+                    row.get::<_, Option<f64>>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, usize>(5)?,
+                ))
+            },
+        )
+        .optional()?;
+
+    let mut events = Vec::new();
+    let seen_fills = match &previous {
+        None => {
+            events.push(("placed", describe(order)));
+            0
+        }
+        Some((_, quantity, limit_price, stop_price, status, fills)) => {
+            if *status != order.status {
+                events.push(("status", order.status.clone()));
+            }
+            if *stop_price != order.stop_price {
+                events.push(("stop_moved", moved(*stop_price, order.stop_price)));
+            }
+            if *limit_price != order.limit_price {
+                events.push(("limit_moved", moved(*limit_price, order.limit_price)));
+            }
+            if *quantity != order.quantity {
+                events.push(("resized", format!("{} -> {}", quantity, order.quantity)));
+            }
+            *fills
+        }
+    };
+    for fill in order.fills.iter().skip(seen_fills) {
+        events.push(("fill", format!("{} @ {:.2}", fill.shares, fill.price)));
+    }
+    if events.is_empty() {
+        return Ok(());
+    }
+
+    conn.execute(
+        "INSERT INTO journal_orders (id, order_id, client_id, perm_id, trade_id, symbol, action,
+            order_type, quantity, limit_price, stop_price, status, fills, journal_id)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+         ON CONFLICT(id) DO UPDATE SET
+            perm_id = excluded.perm_id,
+            quantity = excluded.quantity,
+            limit_price = excluded.limit_price,
+            stop_price = excluded.stop_price,
+            status = excluded.status,
+            fills = excluded.fills",
+        params![
+            previous.map(|p| p.0),
+            order.order_id,
+            order.client_id,
+            order.perm_id,
+            order.trade_id(),
+            order.symbol,
+            order.action,
+            order.order_type,
+            order.quantity,
+            order.limit_price,
+            order.stop_price,
+            order.status,
+            order.fills.len(),
+            journal_id,
+        ],
+    )?;
+    if order.perm_id != 0 && order.order_id == order.trade_id() {
+        conn.execute(
+            "UPDATE journal_trades SET perm_id = ?3
+             WHERE id = (SELECT MAX(id) FROM journal_trades
+                         WHERE client_id = ?1 AND trade_id = ?2)
+               AND perm_id = 0",
+            params![order.client_id, order.order_id, order.perm_id],
+        )?;
+    }
+    let at = order_book::now();
+    for (kind, detail) in events {
+        conn.execute(
+            "INSERT INTO journal_events (journal_id, trade_id, client_id, order_id, kind, detail,
+                at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                journal_id,
+                order.trade_id(),
+                order.client_id,
+                order.order_id,
+                kind,
+                detail,
+                at
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn describe(order: &TrackedOrder) -> String {
+    let mut detail = format!("{} {} {}", order.action, order.quantity, order.order_type);
+    if let Some(price) = order.limit_price {
+        detail.push_str(&format!(" limit {:.2}", price));
+    }
+    if let Some(price) = order.stop_price {
+        detail.push_str(&format!(" stop {:.2}", price));
+    }
+    detail
+}
+
+fn moved(from: Option<f64>, to: Option<f64>) -> String {
+    let price = |p: Option<f64>| p.map(|p| format!("{:.2}", p)).unwrap_or("none".to_string());
+    format!("{} -> {}", price(from), price(to))
+}
+
+/// Returns false if there is no trade with journal id `id`.
+pub fn set_notes(conn: &Connection, id: i64, notes: &str) -> rusqlite::Result<bool> {
+    let updated = conn.execute(
+        "UPDATE journal_trades SET notes = ?2 WHERE id = ?1",
+        params![id, notes],
+    )?;
+    Ok(updated > 0)
+}
+
+pub fn query(conn: &Connection, filter: &JournalQuery) -> rusqlite::Result<Vec<JournalEntry>> {
+    let mut statement = conn.prepare(
+        "SELECT id FROM journal_trades
+         WHERE (?1 IS NULL OR symbol = ?1)
+           AND (?2 IS NULL OR substr(opened_at, 1, 10) >= ?2)
+           AND (?3 IS NULL OR substr(opened_at, 1, 10) <= ?3)
+         ORDER BY id",
+    )?;
+    let ids = statement
+        .query_map(params![filter.symbol, filter.from, filter.to], |row| {
+            row.get::<_, i64>(0)
+        })?
+        .collect::<rusqlite::Result<Vec<i64>>>()?;
+    let mut entries = Vec::new();
+    for id in ids {
+        if let Some(entry) = get(conn, id)? {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// The trade with journal id `id`.
+pub fn get(conn: &Connection, id: i64) -> rusqlite::Result<Option<JournalEntry>> {
+    let entry = conn
+        .query_row(
+            "SELECT id, trade_id, client_id, perm_id, opened_at, symbol, action, quantity,
+                stop_price, ladder, request, risk_snapshot, notes
+             FROM journal_trades WHERE id = ?1",
+            params![id],
+            |row| {
+                let json = |text: String| serde_json::from_str(&text).unwrap_or_default();
+                Ok(JournalEntry {
+                    id: row.get(0)?,
+                    trade_id: row.get(1)?,
+                    client_id: row.get(2)?,
+                    perm_id: row.get(3)?,
+                    opened_at: row.get(4)?,
+                    symbol: row.get(5)?,
+                    action: row.get(6)?,
+                    quantity: row.get(7)?,
+                    stop_price: row.get(8)?,
+                    ladder: row.get(9)?,
+                    request: json(row.get(10)?),
+                    risk_snapshot: json(row.get(11)?),
+                    notes: row.get(12)?,
+                    entry_shares: 0.0,
+                    entry_price: None,
+                    exit_shares: 0.0,
+                    exit_price: None,
+                    gross_pnl: 0.0,
+                    commission: 0.0,
+                    realized_pnl: None,
+                    r_multiple: None,
+                    events: Vec::new(),
+                })
+            },
+        )
+        .optional()?;
+    let Some(mut entry) = entry else {
+        return Ok(None);
+    };
+
+    // Perm ids are unique across client ids and sessions, unlike order ids.
+    let mut statement = conn.prepare(
+        "SELECT e.order_id, e.shares, e.price, c.commission, c.realized_pnl
+         FROM executions e LEFT JOIN commissions c ON c.execution_id = e.execution_id
+         WHERE e.perm_id IN (SELECT perm_id FROM journal_orders
+                             WHERE journal_id = ?1 AND perm_id != 0)
+         ORDER BY e.sort_time",
+    )?;
+    let mut fills = Vec::new();
+    let mut rows = statement.query(params![entry.id])?;
+    while let Some(row) = rows.next()? {
+        fills.push(TradeFill {
+            order_id: row.get(0)?,
+            shares: row.get(1)?,
+            price: row.get(2)?,
+        });
+        entry.commission += row.get::<_, Option<f64>>(3)?.unwrap_or(0.0);
+        if let Some(pnl) = row.get::<_, Option<f64>>(4)? {
+            entry.realized_pnl = Some(entry.realized_pnl.unwrap_or(0.0) + pnl);
+        }
+    }
+    apply_fills(&mut entry, &fills);
+
+    let mut statement = conn.prepare(
+        "SELECT order_id, kind, detail, at FROM journal_events
+         WHERE journal_id = ?1 ORDER BY id",
+    )?;
+    entry.events = statement
+        .query_map(params![entry.id], |row| {
+            Ok(JournalEvent {
+                order_id: row.get(0)?,
+                kind: row.get(1)?,
+                detail: row.get(2)?,
+                at: row.get(3)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(Some(entry))
+}
+
+/// Fills in entry and exit averages, gross P&L and the R-multiple from the trade's fills.
+fn apply_fills(entry: &mut JournalEntry, fills: &[TradeFill]) {
+    let average = |fills: &[&TradeFill]| {
+        let shares: f64 = fills.iter().map(|f| f.shares).sum();
+        let value: f64 = fills.iter().map(|f| f.shares * f.price).sum();
+        (shares, (shares > 0.0).then(|| value / shares))
+    };
+    let (entries, exits): (Vec<&TradeFill>, Vec<&TradeFill>) =
+        fills.iter().partition(|f| f.order_id == entry.trade_id);
+    (entry.entry_shares, entry.entry_price) = average(&entries);
+    (entry.exit_shares, entry.exit_price) = average(&exits);
+
+    let Some(entry_price) = entry.entry_price else {
+        return;
+    };
+    let direction = if entry.action == "BUY" { 1.0 } else { -1.0 };
+    entry.gross_pnl = exits
+        .iter()
+        .map(|f| (f.price - entry_price) * f.shares * direction)
+        .sum();
+    let risk = (entry_price - entry.stop_price).abs() * entry.entry_shares;
+    if risk > 0.0 && entry.exit_shares > 0.0 {
+        entry.r_multiple = Some(entry.gross_pnl / risk);
+    }
+}
+
+/// Renders journal entries as CSV, one row per trade.
+pub fn to_csv(entries: &[JournalEntry]) -> String {
+    let mut csv = String::from(
+        "id,trade_id,client_id,perm_id,opened_at,symbol,action,quantity,entry_price,stop_price,ladder,exit_shares,\
+         exit_price,gross_pnl,commission,realized_pnl,r_multiple,notes\n",
+    );
+    let number = |value: Option<f64>| value.map(|v| format!("{:.4}", v)).unwrap_or_default();
+    for entry in entries {
+        let row = [
+            entry.id.to_string(),
+            entry.trade_id.to_string(),
+            entry.client_id.to_string(),
+            entry.perm_id.to_string(),
+            entry.opened_at.clone(),
+            entry.symbol.clone(),
+            entry.action.clone(),
+            entry.quantity.to_string(),
+            number(entry.entry_price),
+            number(Some(entry.stop_price)),
+            entry.ladder.clone().unwrap_or_default(),
+            entry.exit_shares.to_string(),
+            number(entry.exit_price),
+            number(Some(entry.gross_pnl)),
+            number(Some(entry.commission)),
+            number(entry.realized_pnl),
+            number(entry.r_multiple),
+            entry.notes.clone(),
+        ];
+        let fields: Vec<String> = row.iter().map(|field| escape(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Journals a newly sent entry; failures are logged so the order goes ahead.
+pub fn open_trade(trade_id: i32, client_id: i32, request: &TradeRequest, snapshot: &RiskSnapshot) {
+    let conn = DATABASE.lock().unwrap();
+    if let Err(e) = save_trade(&conn, trade_id, client_id, request, snapshot) {
+        println!("Error journaling trade {}: {}", trade_id, e);
+    }
+}
+
+/// Records every order book change until the book goes away.
+pub async fn record(mut updates: tokio::sync::broadcast::Receiver<TrackedOrder>) {
+    loop {
+        match updates.recv().await {
+            Ok(order) => {
+                let conn = DATABASE.lock().unwrap();
+                if let Err(e) = save_order(&conn, &order) {
+                    println!("Error journaling order {}: {}", order.order_id, e);
+                }
+            }
+            // Each update is a full snapshot, so the next one catches us up.
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executions;
+
+    fn entry(action: &str, stop_price: f64) -> JournalEntry {
+        JournalEntry {
+            id: 1,
+            trade_id: 1,
+            client_id: 0,
+            perm_id: 0,
+            opened_at: String::new(),
+            symbol: "TSLA".to_string(),
+            action: action.to_string(),
+            quantity: 100,
+            stop_price,
+            ladder: None,
+            request: serde_json::Value::Null,
+            risk_snapshot: serde_json::Value::Null,
+            notes: String::new(),
+            entry_shares: 0.0,
+            entry_price: None,
+            exit_shares: 0.0,
+            exit_price: None,
+            gross_pnl: 0.0,
+            commission: 0.0,
+            realized_pnl: None,
+            r_multiple: None,
+            events: Vec::new(),
+        }
+    }
+
+    fn fill(order_id: i32, shares: f64, price: f64) -> TradeFill {
+        TradeFill {
+            order_id,
+            shares,
+            price,
+        }
+    }
+
+    #[test]
+    fn computes_r_multiple_from_fills() {
+        let mut long = entry("BUY", 95.0);
+        apply_fills(
+            &mut long,
+            &[
+                fill(1, 100.0, 100.0),
+                fill(2, 50.0, 110.0),
+                fill(3, 50.0, 95.0),
+            ],
+        );
+        assert_eq!(long.exit_price, Some(102.5));
+        assert_eq!(long.gross_pnl, 250.0);
+        assert_eq!(long.r_multiple, Some(0.5));
+
+        let mut short = entry("SELL", 105.0);
+        apply_fills(&mut short, &[fill(1, 100.0, 100.0), fill(2, 100.0, 90.0)]);
+        assert_eq!(short.r_multiple, Some(2.0));
+    }
+
+    #[test]
+    fn records_order_changes_as_events() {
+        let conn = Connection::open_in_memory().unwrap();
+        executions::create_tables(&conn).unwrap();
+        create_tables(&conn).unwrap();
+
+        let mut book = order_book::OrderBook::new();
+        let order = ibapi::orders::Order {
+            action: ibapi::orders::Action::Sell,
+            order_type: "STP".to_string(),
+            total_quantity: 10.0,
+            aux_price: Some(95.0),
+            oca_group: "scale-out-1-1".to_string(),
+            ..Default::default()
+        };
+        book.record_placed(
+            2,
+            0,
+            &ibapi::contracts::Contract::stock("TSLA").build(),
+            &order,
+        );
+        save_order(&conn, &book.get(0, 2).unwrap()).unwrap();
+        save_order(&conn, &book.get(0, 2).unwrap()).unwrap();
+        book.record_modified(
+            0,
+            2,
+            &ibapi::orders::Order {
+                aux_price: Some(100.0),
+                ..order
+            },
+        );
+        save_order(&conn, &book.get(0, 2).unwrap()).unwrap();
+
+        let request = TradeRequest {
+            symbol: "TSLA".to_string(),
+            action: "BUY".to_string(),
+            quantity: 10,
+            entry_price: None,
+            stop_price: 95.0,
+            ladder: None,
+        };
+        let snapshot = RiskSnapshot {
+            trading_status: TradingStatus::default(),
+            limits: RiskConfig::default(),
+            risk_percent: Some(0.25),
+        };
+        let id = save_trade(&conn, 1, 0, &request, &snapshot).unwrap();
+        assert!(set_notes(&conn, id, "clean breakout").unwrap());
+
+        let journaled = get(&conn, id).unwrap().unwrap();
+        let kinds: Vec<&str> = journaled.events.iter().map(|e| e.kind.as_str()).collect();
+        assert_eq!(kinds, vec!["placed", "stop_moved"]);
+        assert_eq!(journaled.events[1].detail, "95.00 -> 100.00");
+        assert_eq!(journaled.notes, "clean breakout");
+        assert_eq!(journaled.request["stop_price"], 95.0);
+    }
+
+    #[test]
+    fn keeps_trades_that_reuse_an_order_id() {
+        let conn = Connection::open_in_memory().unwrap();
+        executions::create_tables(&conn).unwrap();
+        create_tables(&conn).unwrap();
+
+        let request = TradeRequest {
+            symbol: "TSLA".to_string(),
+            action: "BUY".to_string(),
+            quantity: 10,
+            entry_price: None,
+            stop_price: 95.0,
+            ladder: None,
+        };
+        let snapshot = RiskSnapshot {
+            trading_status: TradingStatus::default(),
+            limits: RiskConfig::default(),
+            risk_percent: None,
+        };
+        let contract = ibapi::contracts::Contract::stock("TSLA").build();
+        let order = ibapi::orders::Order {
+            action: ibapi::orders::Action::Buy,
+            order_type: "MKT".to_string(),
+            total_quantity: 10.0,
+            ..Default::default()
+        };
+        // Client 100's order id 5 comes round again in a later session.
+        let trades = [(100, 501, 100.0), (90, 502, 200.0), (100, 503, 300.0)];
+        let mut ids = Vec::new();
+        for (client_id, perm_id, price) in trades {
+            ids.push(save_trade(&conn, 5, client_id, &request, &snapshot).unwrap());
+            let mut book = order_book::OrderBook::new();
+            book.record_placed(5, client_id, &contract, &order);
+            book.apply(&crate::broker::OrderEvent::Status {
+                order_id: 5,
+                client_id,
+                status: "Filled".to_string(),
+                filled: 10.0,
+                remaining: 0.0,
+                average_fill_price: price,
+                perm_id,
+            });
+            save_order(&conn, &book.get(client_id, 5).unwrap()).unwrap();
+            conn.execute(
+                "INSERT INTO executions (execution_id, order_id, perm_id, client_id, trade_id,
+                    oca_group, symbol, side, shares, price, time, sort_time, account, exchange)
+                 VALUES (?1, 5, ?2, ?3, 5, '', 'TSLA', 'BOT', 10, ?4, '', '', 'DU1', 'SMART')",
+                params![format!("e{}", perm_id), perm_id, client_id, price],
+            )
+            .unwrap();
+        }
+
+        let journaled = query(&conn, &JournalQuery::default()).unwrap();
+        assert_eq!(journaled.len(), 3);
+        assert_eq!(journaled.iter().map(|t| t.id).collect::<Vec<_>>(), ids);
+        for (trade, (client_id, _, price)) in journaled.iter().zip(trades) {
+            assert_eq!((trade.trade_id, trade.client_id), (5, client_id));
+            assert_eq!(trade.events.len(), 1, "{:?}", trade.events);
+            assert_eq!((trade.entry_shares, trade.entry_price), (10.0, Some(price)));
+        }
+    }
+
+    #[test]
+    fn escapes_csv_fields() {
+        let mut journaled = entry("BUY", 95.0);
+        journaled.notes = "late, \"chased\"".to_string();
+        let csv = to_csv(&[journaled]);
+        assert!(
+            csv.lines()
+                .nth(1)
+                .unwrap()
+                .ends_with(",\"late, \"\"chased\"\"\"")
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use ibapi::{accounts::PnL, subscriptions::Subscription};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::sync::RwLock;
+
+use crate::broker::Broker;
+use crate::config::CONFIG;
+use crate::market_day;
+use crate::order_book::ORDER_BOOK;
+
+/// Daily limits from the `[loss_limits]` section of the config.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct LossLimits {
+    /// Largest daily loss allowed, as a positive amount.
+    pub max_daily_loss: Option<f64>,
+    /// Losing trades allowed per New York trading day.
+    pub max_losing_trades: Option<usize>,
+    /// Close every position when a limit is breached, not just lock out entries.
+    pub flatten_on_breach: bool,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct TradingStatus {
+    pub entries_locked: bool,
+    pub reason: Option<String>,
+    pub daily_pnl: Option<f64>,
+    pub realized_pnl: Option<f64>,
+    pub unrealized_pnl: Option<f64>,
+    pub losing_trades: usize,
+}
+
+lazy_static::lazy_static! {
+    pub(crate) static ref TRADING_STATUS: RwLock<TradingStatus> = RwLock::new(TradingStatus::default());
+}
+
+impl TradingStatus {
+    /// Locks out new entries; returns false if they were already locked.
+    pub fn lock(&mut self, reason: String) -> bool {
+        if self.entries_locked {
+            return false;
+        }
+        println!("Order entry locked: {}", reason);
+        self.entries_locked = true;
+        self.reason = Some(reason);
+        true
+    }
+
+    pub fn reset(&mut self) {
+        self.entries_locked = false;
+        self.reason = None;
+    }
+
+    /// Locks entries if a limit is breached; returns true only on a new breach.
+    pub fn check_limits(&mut self, limits: &LossLimits) -> bool {
+        if let (Some(limit), Some(daily)) = (limits.max_daily_loss, self.daily_pnl)
+            && daily <= -limit.abs()
+        {
+            return self.lock(format!(
+                "daily loss {:.2} reached the {:.2} limit",
+                daily, limit
+            ));
+        }
+        if let Some(limit) = limits.max_losing_trades
+            && self.losing_trades >= limit
+        {
+            return self.lock(format!(
+                "{} losing trades reached the limit of {}",
+                self.losing_trades, limit
+            ));
+        }
+        false
+    }
+}
+
+/// Returns why new entries are refused, if they are. Exits are never blocked here.
+pub async fn entry_block() -> Option<String> {
+    let losing_trades = ORDER_BOOK
+        .read()
+        .await
+        .losing_trades(market_day::start(OffsetDateTime::now_utc()));
+    let mut status = TRADING_STATUS.write().await;
+    status.losing_trades = losing_trades;
+    status.check_limits(&CONFIG.loss_limits);
+    if status.entries_locked {
+        status.reason.clone().or(Some("entries locked".to_string()))
+    } else {
+        None
+    }
+}
+
+/// Follows IBKR's account P&L and enforces the daily limits until the stream ends.
+pub async fn monitor_pnl<B: Broker>(mut subscription: Subscription<PnL>, broker: B) {
+    while let Some(update) = subscription.next().await {
+        let pnl = match update {
+            Ok(pnl) => pnl,
+            Err(e) => {
+                println!("Error reading P&L: {:?}", e);
+                break;
+            }
+        };
+        record_pnl(&broker, pnl.daily_pnl, pnl.realized_pnl, pnl.unrealized_pnl).await;
+    }
+}
+
+/// Stores the account's P&L and enforces the daily limits against it.
+pub async fn record_pnl<B: Broker>(
+    broker: &B,
+    daily_pnl: f64,
+    realized_pnl: Option<f64>,
+    unrealized_pnl: Option<f64>,
+) {
+    let losing_trades = ORDER_BOOK
+        .read()
+        .await
+        .losing_trades(market_day::start(OffsetDateTime::now_utc()));
+    let breached = {
+        let mut status = TRADING_STATUS.write().await;
+        status.daily_pnl = Some(daily_pnl);
+        status.realized_pnl = realized_pnl;
+        status.unrealized_pnl = unrealized_pnl;
+        status.losing_trades = losing_trades;
+        status.check_limits(&CONFIG.loss_limits)
+    };
+    if breached && CONFIG.loss_limits.flatten_on_breach {
+        let (_, message) = broker.flatten_all().await;
+        println!("Flattened after loss limit breach: {}", message);
+    }
+}
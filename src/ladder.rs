@@ -0,0 +1,264 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::CONFIG;
+use crate::exit_rules::ExitRule;
+
+/// Name of the built-in ladder used when neither the order nor the config picks one.
+pub const DEFAULT_LADDER: &str = "thirds";
+
+/// A price level relative to the fill.
+///
+/// `R` is a multiple of the fill-to-stop distance, `Ticks` a number of min ticks,
+/// `Percent` a percentage of the fill price and `Price` an absolute price.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Level {
+    R(f64),
+    Ticks(f64),
+    Percent(f64),
+    Price(f64),
+}
+
+impl Level {
+    /// Resolves the level to a price `direction` away from the fill (+1.0 above, -1.0 below),
+    /// rounded to the contract's min tick.
+    pub fn resolve(&self, fill: f64, risk: f64, direction: f64, min_tick: f64) -> f64 {
+        let price = match self {
+            Level::R(r) => fill + direction * r * risk,
+            Level::Ticks(t) => fill + direction * t * min_tick,
+            Level::Percent(p) => fill + direction * fill * p / 100.0,
+            Level::Price(p) => *p,
+        };
+        round_to_tick(price, min_tick)
+    }
+
+    fn value(&self) -> f64 {
+        match self {
+            Level::R(v) | Level::Ticks(v) | Level::Percent(v) | Level::Price(v) => *v,
+        }
+    }
+}
+
+/// How far a broker-side trailing stop follows the best price: a fixed amount or a percentage.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Trail {
+    Amount(f64),
+    Percent(f64),
+}
+
+/// One slice of the position with its own stop and optional target.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Tranche {
+    pub size_percent: f64,
+    pub stop: Level,
+    #[serde(default)]
+    pub target: Option<Level>,
+    /// Send the stop as a TRAIL order starting at `stop` instead of a fixed stop.
+    #[serde(default)]
+    pub trail: Option<Trail>,
+}
+
+/// A scale-out ladder: the position is split into tranches that exit independently.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Ladder {
+    pub tranches: Vec<Tranche>,
+    /// Rules that move the fixed stops once the trade is on.
+    #[serde(default)]
+    pub rules: Vec<ExitRule>,
+}
+
+impl Ladder {
+    /// The original scheme: thirds stopped at 1/3R, 2/3R and 1R, targeted at 1R, 2R and 3R.
+    pub fn thirds() -> Self {
+        let tranche = |size_percent: f64, stop: f64, target: f64| Tranche {
+            size_percent,
+            stop: Level::R(stop),
+            target: Some(Level::R(target)),
+            trail: None,
+        };
+        Ladder {
+            tranches: vec![
+                tranche(100.0 / 3.0, 1.0 / 3.0, 1.0),
+                tranche(100.0 / 3.0, 2.0 / 3.0, 2.0),
+                tranche(100.0 / 3.0, 1.0, 3.0),
+            ],
+            rules: Vec::new(),
+        }
+    }
+
+    /// Looks up a ladder preset from the config, falling back to the configured default.
+    pub fn preset(name: Option<&str>) -> Result<Ladder, String> {
+        let name = name
+            .or(CONFIG.default_ladder.as_deref())
+            .unwrap_or(DEFAULT_LADDER);
+        let ladder = match CONFIG.ladders.get(name) {
+            Some(ladder) => ladder.clone(),
+            None if name == DEFAULT_LADDER => Ladder::thirds(),
+            None => return Err(format!("Unknown ladder preset: {}", name)),
+        };
+        ladder.validate()?;
+        Ok(ladder)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.tranches.is_empty() {
+            return Err("Ladder has no tranches".to_string());
+        }
+        for (i, tranche) in self.tranches.iter().enumerate() {
+            if !tranche.size_percent.is_finite() || tranche.size_percent <= 0.0 {
+                return Err(format!("Tranche {} has a non-positive size", i + 1));
+            }
+            let levels = std::iter::once(&tranche.stop).chain(tranche.target.as_ref());
+            for level in levels {
+                if !level.value().is_finite() || level.value() <= 0.0 {
+                    return Err(format!(
+                        "Tranche {} has an invalid level {:?}",
+                        i + 1,
+                        level
+                    ));
+                }
+            }
+            // Short targets this far away would be zero or negative prices.
+            if let Some(Level::Percent(p)) = tranche.target
+                && p >= 100.0
+            {
+                return Err(format!("Tranche {} has a target of {}%", i + 1, p));
+            }
+            if let Some(Trail::Amount(v) | Trail::Percent(v)) = tranche.trail
+                && !(v.is_finite() && v > 0.0)
+            {
+                return Err(format!("Tranche {} has an invalid trail {}", i + 1, v));
+            }
+        }
+        for rule in &self.rules {
+            rule.validate(self.tranches.len())?;
+        }
+        let total: f64 = self.tranches.iter().map(|t| t.size_percent).sum();
+        if (total - 100.0).abs() > 0.01 {
+            return Err(format!("Tranche sizes sum to {:.2}%, expected 100%", total));
+        }
+        Ok(())
+    }
+
+    /// Splits `qty` into tranche sizes; the last tranche takes the rounding remainder.
+    pub fn sizes(&self, qty: i32) -> Result<Vec<i32>, String> {
+        let mut sizes: Vec<i32> = self
+            .tranches
+            .iter()
+            .map(|t| (qty as f64 * t.size_percent / 100.0).round() as i32)
+            .collect();
+        let allocated: i32 = sizes[..sizes.len() - 1].iter().sum();
+        *sizes.last_mut().unwrap() = qty - allocated;
+
+        if sizes.iter().any(|s| *s <= 0) {
+            return Err(format!(
+                "Quantity {} is too small for a {}-tranche ladder",
+                qty,
+                sizes.len()
+            ));
+        }
+        debug_assert_eq!(sizes.iter().sum::<i32>(), qty);
+        Ok(sizes)
+    }
+}
+
+/// Rounds a price to the nearest multiple of `min_tick`.
+pub fn round_to_tick(price: f64, min_tick: f64) -> f64 {
+    let tick = if min_tick > 0.0 { min_tick } else { 0.01 };
+    // The second rounding strips float noise such as 240.32000000000002.
+    ((price / tick).round() * tick * 1e8).round() / 1e8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::stop_plan::StopPlan;
+    use ibapi::orders::Action;
+
+    fn ladder(sizes: &[f64]) -> Ladder {
+        Ladder {
+            tranches: sizes
+                .iter()
+                .map(|size_percent| Tranche {
+                    size_percent: *size_percent,
+                    stop: Level::R(1.0),
+                    target: Some(Level::R(2.0)),
+                    trail: None,
+                })
+                .collect(),
+            rules: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn sizes_give_the_rounding_remainder_to_the_last_tranche() {
+        assert_eq!(Ladder::thirds().sizes(10).unwrap(), vec![3, 3, 4]);
+        assert_eq!(Ladder::thirds().sizes(101).unwrap(), vec![34, 34, 33]);
+        assert_eq!(ladder(&[50.0, 50.0]).sizes(7).unwrap(), vec![4, 3]);
+        assert_eq!(ladder(&[12.5, 87.5]).sizes(4).unwrap(), vec![1, 3]);
+        assert!(Ladder::thirds().sizes(2).is_err());
+    }
+
+    #[test]
+    fn empty_and_uneven_ladders_are_rejected() {
+        assert_eq!(
+            ladder(&[]).validate(),
+            Err("Ladder has no tranches".to_string())
+        );
+        assert!(ladder(&[50.0, 40.0]).validate().is_err());
+        assert!(ladder(&[100.0, 0.0]).validate().is_err());
+        assert!(ladder(&[50.0, 50.0]).validate().is_ok());
+    }
+
+    #[test]
+    fn duplicate_presets_do_not_load() {
+        let contents = "
+            [ladders.halves]
+            tranches = [{ size_percent = 100, stop = { r = 1 } }]
+
+            [ladders.halves]
+            tranches = [{ size_percent = 100, stop = { r = 0.5 } }]
+        ";
+        let error = toml::from_str::<Config>(contents).unwrap_err();
+        assert!(error.to_string().contains("duplicate"), "{}", error);
+    }
+
+    #[test]
+    fn unknown_presets_are_refused() {
+        assert_eq!(
+            Ladder::preset(Some("no-such-ladder")),
+            Err("Unknown ladder preset: no-such-ladder".to_string())
+        );
+    }
+
+    #[test]
+    fn short_targets_stay_above_zero() {
+        let mut far = ladder(&[100.0]);
+        far.tranches[0].target = Some(Level::Percent(100.0));
+        assert!(far.validate().is_err());
+
+        // 2R below a 4.00 short stopped at 6.00 is zero.
+        let error =
+            StopPlan::build(Action::Sell, 30, 4.0, 6.0, &Ladder::thirds(), 0.01).unwrap_err();
+        assert_eq!(error, "Tranche 2 target 0.00 is not a positive price");
+        assert!(StopPlan::build(Action::Sell, 30, 4.0, 5.0, &Ladder::thirds(), 0.01).is_ok());
+    }
+
+    #[test]
+    fn targets_on_the_stop_side_are_rejected() {
+        let mut fixed = ladder(&[100.0]);
+        fixed.tranches[0].target = Some(Level::Price(95.0));
+        assert!(fixed.validate().is_ok());
+
+        let error = StopPlan::build(Action::Buy, 10, 100.0, 97.0, &fixed, 0.01).unwrap_err();
+        assert_eq!(
+            error,
+            "Tranche 1 target 95.00 is not on the profit side of the fill 100.00"
+        );
+        fixed.tranches[0].target = Some(Level::Price(100.0));
+        assert!(StopPlan::build(Action::Buy, 10, 100.0, 97.0, &fixed, 0.01).is_err());
+        assert!(StopPlan::build(Action::Sell, 10, 90.0, 93.0, &fixed, 0.01).is_err());
+    }
+}
@@ -0,0 +1,29 @@
+pub mod auth;
+pub mod backtest;
+pub mod broker;
+pub mod cli;
+pub mod config;
+pub mod connector;
+pub mod database;
+pub mod executions;
+pub mod exit_rules;
+pub mod journal;
+pub mod kill_switch;
+pub mod ladder;
+pub mod market_day;
+pub mod mock_gateway;
+pub mod order_book;
+pub mod order_keys;
+pub mod positions;
+pub mod preview;
+pub mod quotes;
+pub mod replay;
+pub mod risk;
+pub mod router;
+pub mod server;
+pub mod simulator;
+pub mod sizing;
+pub mod stop_plan;
+pub mod trading;
+pub mod triggers;
+pub mod tui;
@@ -1,19 +1,74 @@
-use axum::Router;
-use utoipa::OpenApi;
-use utoipa_swagger_ui::SwaggerUi;
-
-mod connector;
-mod router;
-
-use router::ApiDoc;
+use rust::cli::{self, Command, USAGE};
+use rust::config::CONFIG;
+use rust::connector::IbkrBroker;
+use rust::journal;
+use rust::order_book::ORDER_BOOK;
+use rust::replay;
+use rust::server;
+use rust::simulator::{SimBroker, SimulatorConfig};
 
 #[tokio::main]
 async fn main() {
-    let app = Router::new()
-        .merge(router::app())
-        .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", ApiDoc::openapi()));
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = match cli::parse(&args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+    match command {
+        Command::Help => println!("{}", USAGE),
+        Command::Serve => {
+            tokio::spawn(journal::record(ORDER_BOOK.read().await.subscribe()));
+            if CONFIG.simulator.enabled {
+                let broker =
+                    SimBroker::from_config().unwrap_or_else(|e| panic!("Simulator: {}", e));
+                exit_on_error(server::serve(broker).await);
+            } else {
+                exit_on_error(server::serve(IbkrBroker::default()).await);
+            }
+        }
+        Command::Play { file, speed } => {
+            let ticks = replay::resolve(&file)
+                .and_then(|path| replay::load(&path))
+                .unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                });
+            let settings = SimulatorConfig {
+                replay_file: Some(file),
+                replay_speed: speed,
+                ..CONFIG.simulator.clone()
+            };
+            println!("Replaying {} ticks; POST /connect to start.", ticks.len());
+            tokio::spawn(journal::record(ORDER_BOOK.read().await.subscribe()));
+            exit_on_error(server::serve(SimBroker::replay(settings, ticks)).await);
+        }
+        command => {
+            let result = if CONFIG.simulator.enabled {
+                match SimBroker::from_config() {
+                    Ok(broker) => cli::run(broker, command).await,
+                    Err(e) => Err(format!("Simulator: {}", e)),
+                }
+            } else {
+                cli::run(IbkrBroker::default(), command).await
+            };
+            match result {
+                Ok(output) => print!("{}", output),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+}
 
-    axum::serve(listener, app).await.unwrap();
+fn exit_on_error(result: Result<(), String>) {
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,83 @@
+//! The US stock market's calendar day, which idempotency keys and daily loss limits reset on.
+//!
+//! Days run midnight to midnight New York time, so an evening session in Europe or Asia
+//! does not roll over at UTC midnight halfway through.
+
+use time::{Date, Duration, Month, OffsetDateTime, Time, UtcOffset};
+
+/// New York's offset from UTC at `at`: EDT from 2:00 on the second Sunday in March to
+/// 2:00 on the first Sunday in November, EST otherwise.
+pub fn offset(at: OffsetDateTime) -> UtcOffset {
+    let utc = at.to_offset(UtcOffset::UTC);
+    let year = utc.year();
+    // 2:00 EST and 2:00 EDT, in UTC.
+    let summer_starts = sunday(year, Month::March, 2).with_hms(7, 0, 0).unwrap();
+    let summer_ends = sunday(year, Month::November, 1).with_hms(6, 0, 0).unwrap();
+    let now = utc.date().with_time(utc.time());
+    let hours = if now >= summer_starts && now < summer_ends {
+        -4
+    } else {
+        -5
+    };
+    UtcOffset::from_hms(hours, 0, 0).unwrap()
+}
+
+/// The market's date at `at`.
+pub fn date(at: OffsetDateTime) -> Date {
+    at.to_offset(offset(at)).date()
+}
+
+/// The market's date now.
+pub fn today() -> Date {
+    date(OffsetDateTime::now_utc())
+}
+
+/// When the market day holding `at` began.
+pub fn start(at: OffsetDateTime) -> OffsetDateTime {
+    let midnight = date(at).with_time(Time::MIDNIGHT);
+    // Clocks change at 2:00, so midnight always has the offset of the hours before the change.
+    let offset = offset(midnight.assume_offset(offset(at)));
+    midnight.assume_offset(offset)
+}
+
+/// The `nth` Sunday of `month`.
+fn sunday(year: i32, month: Month, nth: i64) -> Date {
+    let first = Date::from_calendar_date(year, month, 1).unwrap();
+    let to_sunday = (7 - first.weekday().number_days_from_sunday() as i64) % 7;
+    first + Duration::days(to_sunday + 7 * (nth - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::{date, datetime};
+
+    #[test]
+    fn follows_new_york_daylight_saving() {
+        assert_eq!(offset(datetime!(2026-01-15 12:00 UTC)).whole_hours(), -5);
+        assert_eq!(offset(datetime!(2026-03-08 06:59 UTC)).whole_hours(), -5);
+        assert_eq!(offset(datetime!(2026-03-08 07:00 UTC)).whole_hours(), -4);
+        assert_eq!(offset(datetime!(2026-11-01 05:59 UTC)).whole_hours(), -4);
+        assert_eq!(offset(datetime!(2026-11-01 06:00 UTC)).whole_hours(), -5);
+    }
+
+    #[test]
+    fn rolls_over_at_new_york_midnight() {
+        assert_eq!(date(datetime!(2026-10-19 03:59 UTC)), date!(2026 - 10 - 18));
+        assert_eq!(date(datetime!(2026-10-19 04:00 UTC)), date!(2026 - 10 - 19));
+        assert_eq!(date(datetime!(2026-01-16 04:59 UTC)), date!(2026 - 01 - 15));
+        assert_eq!(
+            start(datetime!(2026-10-19 03:59 UTC)),
+            datetime!(2026-10-18 04:00 UTC)
+        );
+        assert_eq!(
+            start(datetime!(2026-01-15 23:00 UTC)),
+            datetime!(2026-01-15 05:00 UTC)
+        );
+        // The day the clocks go back starts on summer time.
+        assert_eq!(
+            start(datetime!(2026-11-01 20:00 UTC)),
+            datetime!(2026-11-01 04:00 UTC)
+        );
+    }
+}
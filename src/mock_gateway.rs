@@ -0,0 +1,1005 @@
+//! A fake IB Gateway for offline tests.
+//!
+//! Speaks enough of the TWS socket protocol at server version 164 for
+//! `ibapi::Client::connect` to handshake, then answers contract details,
+//! market data, historical bars, account updates, positions, P&L and orders
+//! from a [`Script`]. Market orders fill at the quote unless the symbol is
+//! halted, marketable limits fill
+//! at their price, everything else works until [`MockGateway::tick`] moves
+//! the last price through it.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use time::OffsetDateTime;
+use time::macros::format_description;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// The version we claim to be; ibapi's own message samples are written against it.
+pub const SERVER_VERSION: i32 = 164;
+
+/// What IBKR sends for "no value" in double fields.
+const UNSET_DOUBLE: &str = "1.7976931348623157E308";
+
+/// A real open order message at server version 164, used as the layout for ours.
+const OPEN_ORDER_TEMPLATE: &str = "5|13|76792991|TSLA|STK||0|?||SMART|USD|TSLA|NMS|BUY|100|MKT|0.0|0.0|DAY||DU1234567||0||100|1376327563|0|0|0||1376327563.0/DU1234567/100||||||||||0||-1|0||||||2147483647|0|0|0||3|0|0||0|0||0|None||0||||?|0|0||0|0||||||0|0|0|2147483647|2147483647|||0||IB|0|0||0|0|PreSubmitted|1.7976931348623157E308|1.7976931348623157E308|1.7976931348623157E308|1.7976931348623157E308|1.7976931348623157E308|1.7976931348623157E308|1.7976931348623157E308|1.7976931348623157E308|1.7976931348623157E308||||||0|0|0|None|1.7976931348623157E308|1.7976931348623157E308|1.7976931348623157E308|1.7976931348623157E308|1.7976931348623157E308|1.7976931348623157E308|0||||0|1|0|0|0|||0||";
+/// Field positions in [`OPEN_ORDER_TEMPLATE`] after the ones we copy from the placed order.
+const OPEN_ORDER_WHAT_IF: usize = 93;
+const OPEN_ORDER_STATUS: usize = 94;
+/// `place_order` sends this many fields after the what-if flag for a plain stock order.
+const PLACE_ORDER_FIELDS_AFTER_WHAT_IF: usize = 27;
+
+/// Order types the gateway accepts; anything else is rejected like TWS does.
+const ORDER_TYPES: [&str; 4] = ["MKT", "LMT", "STP", "TRAIL"];
+
+/// Order ids handed out across every mock in the process, so the shared order
+/// book never sees an id twice, just as TWS never reuses one.
+static NEXT_ORDER_ID: AtomicI32 = AtomicI32::new(1);
+/// Execution ids are unique across sessions too.
+static NEXT_EXECUTION_ID: AtomicI32 = AtomicI32::new(1);
+
+/// One scripted stock.
+#[derive(Clone, Debug)]
+pub struct MockSymbol {
+    pub symbol: String,
+    pub contract_id: i32,
+    pub min_tick: f64,
+    pub bid: f64,
+    pub ask: f64,
+    pub last: f64,
+    pub volume: f64,
+    /// Today's one-minute bars, oldest first; the last one is still forming.
+    pub bars: Vec<MockBar>,
+    /// Market orders rest unfilled, as in a trading halt.
+    pub halted: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct MockBar {
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+}
+
+impl MockSymbol {
+    /// A stock trading at `last` with a cent-wide spread and a morning of bars
+    /// that swing two percent either side of it.
+    pub fn new(symbol: &str, contract_id: i32, last: f64) -> Self {
+        let bars = (0..30)
+            .map(|i| {
+                let swing = last * 0.02 * ((i as f64) * 0.7).sin();
+                let open = round_cents(last + swing);
+                let close = round_cents(last + last * 0.02 * ((i as f64 + 1.0) * 0.7).sin());
+                MockBar {
+                    open,
+                    high: round_cents(open.max(close) + 0.05),
+                    low: round_cents(open.min(close) - 0.05),
+                    close,
+                    volume: 1000.0 + 100.0 * i as f64,
+                }
+            })
+            .collect();
+        MockSymbol {
+            symbol: symbol.to_string(),
+            contract_id,
+            min_tick: 0.01,
+            bid: round_cents(last - 0.01),
+            ask: round_cents(last + 0.01),
+            last,
+            volume: 1_500_000.0,
+            bars,
+            halted: false,
+        }
+    }
+
+    fn high(&self) -> f64 {
+        self.bars.iter().map(|b| b.high).fold(self.last, f64::max)
+    }
+
+    fn low(&self) -> f64 {
+        self.bars.iter().map(|b| b.low).fold(self.last, f64::min)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MockPosition {
+    pub symbol: String,
+    pub quantity: f64,
+    pub average_cost: f64,
+}
+
+/// Everything the gateway knows at startup.
+#[derive(Clone, Debug)]
+pub struct Script {
+    pub account: String,
+    /// `(key, value, currency)` account values.
+    pub account_values: Vec<(String, String, String)>,
+    pub symbols: Vec<MockSymbol>,
+    pub positions: Vec<MockPosition>,
+    pub daily_pnl: f64,
+    pub commission_per_share: f64,
+}
+
+impl Default for Script {
+    fn default() -> Self {
+        let value =
+            |key: &str, value: &str| (key.to_string(), value.to_string(), "USD".to_string());
+        Script {
+            account: "DU1234567".to_string(),
+            account_values: vec![
+                value("NetLiquidation", "100000.00"),
+                value("TotalCashValue", "100000.00"),
+                value("BuyingPower", "400000.00"),
+                value("AvailableFunds", "100000.00"),
+            ],
+            symbols: vec![
+                MockSymbol::new("AAPL", 265598, 190.0),
+                MockSymbol::new("TSLA", 76792991, 250.0),
+            ],
+            positions: Vec::new(),
+            daily_pnl: 0.0,
+            commission_per_share: 0.005,
+        }
+    }
+}
+
+/// An order as the gateway sees it.
+#[derive(Clone, Debug)]
+pub struct MockOrder {
+    pub order_id: i32,
+    pub client_id: i32,
+    pub perm_id: i32,
+    pub contract_id: i32,
+    pub symbol: String,
+    pub action: String,
+    pub quantity: f64,
+    pub order_type: String,
+    /// Prices exactly as the client sent them, empty when unset.
+    pub limit_price: String,
+    pub aux_price: String,
+    pub tif: String,
+    pub oca_group: String,
+    pub order_ref: String,
+    pub status: String,
+    pub filled: f64,
+    pub average_fill_price: f64,
+}
+
+impl MockOrder {
+    fn is_working(&self) -> bool {
+        !matches!(self.status.as_str(), "Filled" | "Cancelled" | "Inactive")
+    }
+
+    fn is_buy(&self) -> bool {
+        self.action == "BUY"
+    }
+
+    fn remaining(&self) -> f64 {
+        self.quantity - self.filled
+    }
+
+    fn limit(&self) -> Option<f64> {
+        self.limit_price.parse().ok()
+    }
+
+    fn stop(&self) -> Option<f64> {
+        self.aux_price.parse().ok()
+    }
+}
+
+#[derive(Clone, Debug)]
+struct MockExecution {
+    execution_id: String,
+    order_id: i32,
+    client_id: i32,
+    perm_id: i32,
+    contract_id: i32,
+    symbol: String,
+    side: String,
+    shares: f64,
+    price: f64,
+    cumulative: f64,
+    average_price: f64,
+    order_ref: String,
+    time: String,
+    commission: f64,
+    realized_pnl: Option<f64>,
+}
+
+struct State {
+    symbols: HashMap<String, MockSymbol>,
+    /// Signed quantity and average cost by symbol.
+    positions: BTreeMap<String, (f64, f64)>,
+    orders: BTreeMap<i32, MockOrder>,
+    executions: Vec<MockExecution>,
+    /// Market data request ids and their symbols.
+    market_data: Vec<(i32, String)>,
+    /// Every message received, fields joined with `|`.
+    requests: Vec<String>,
+    client_id: i32,
+    /// Messages for the connected client, if any.
+    outbox: Option<mpsc::UnboundedSender<String>>,
+}
+
+struct Gateway {
+    script: Script,
+    state: Mutex<State>,
+}
+
+/// A running fake gateway; it stops when dropped.
+pub struct MockGateway {
+    address: SocketAddr,
+    gateway: Arc<Gateway>,
+    listener: JoinHandle<()>,
+}
+
+impl MockGateway {
+    /// Starts listening on a free localhost port.
+    pub async fn start(script: Script) -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let state = State {
+            symbols: script
+                .symbols
+                .iter()
+                .map(|s| (s.symbol.clone(), s.clone()))
+                .collect(),
+            positions: script
+                .positions
+                .iter()
+                .map(|p| (p.symbol.clone(), (p.quantity, p.average_cost)))
+                .collect(),
+            orders: BTreeMap::new(),
+            executions: Vec::new(),
+            market_data: Vec::new(),
+            requests: Vec::new(),
+            client_id: 0,
+            outbox: None,
+        };
+        let gateway = Arc::new(Gateway {
+            script,
+            state: Mutex::new(state),
+        });
+        let accepting = gateway.clone();
+        let listener = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(accepting.clone(), stream));
+            }
+        });
+        Ok(MockGateway {
+            address,
+            gateway,
+            listener,
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.address.port()
+    }
+
+    /// Every message received so far, fields joined with `|`.
+    pub fn requests(&self) -> Vec<String> {
+        self.gateway.state.lock().unwrap().requests.clone()
+    }
+
+    pub fn orders(&self) -> Vec<MockOrder> {
+        self.gateway
+            .state
+            .lock()
+            .unwrap()
+            .orders
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Signed position in `symbol`, zero when flat.
+    pub fn position(&self, symbol: &str) -> f64 {
+        let state = self.gateway.state.lock().unwrap();
+        state.positions.get(symbol).map_or(0.0, |p| p.0)
+    }
+
+    /// Trades `symbol` at `price`: streams the tick to subscribers and fills
+    /// any working limit or stop order the price reaches.
+    pub fn tick(&self, symbol: &str, price: f64) {
+        let mut state = self.gateway.state.lock().unwrap();
+        let Some(quote) = state.symbols.get_mut(symbol) else {
+            return;
+        };
+        quote.last = price;
+        quote.bid = round_cents(price - 0.01);
+        quote.ask = round_cents(price + 0.01);
+        let subscribers: Vec<i32> = state
+            .market_data
+            .iter()
+            .filter(|(_, s)| s == symbol)
+            .map(|(id, _)| *id)
+            .collect();
+        for request_id in subscribers {
+            state.send(format!("1|6|{}|4|{}|100|0|", request_id, price));
+        }
+        let triggered: Vec<i32> = state
+            .orders
+            .values()
+            .filter(|o| o.symbol == symbol && o.is_working() && reaches(o, price))
+            .map(|o| o.order_id)
+            .collect();
+        for order_id in triggered {
+            let fill_price = match state.orders[&order_id].order_type.as_str() {
+                "LMT" => state.orders[&order_id].limit().unwrap_or(price),
+                _ => price,
+            };
+            state.fill(&self.gateway.script, order_id, fill_price);
+        }
+    }
+}
+
+impl Drop for MockGateway {
+    fn drop(&mut self) {
+        self.listener.abort();
+    }
+}
+
+/// Whether `price` trading reaches a resting limit or triggers a stop.
+fn reaches(order: &MockOrder, price: f64) -> bool {
+    match (order.order_type.as_str(), order.is_buy()) {
+        ("LMT", true) => order.limit().is_some_and(|l| price <= l),
+        ("LMT", false) => order.limit().is_some_and(|l| price >= l),
+        ("STP", true) => order.stop().is_some_and(|s| price >= s),
+        ("STP", false) => order.stop().is_some_and(|s| price <= s),
+        _ => false,
+    }
+}
+
+async fn serve(gateway: Arc<Gateway>, stream: TcpStream) {
+    let (mut reader, mut writer) = stream.into_split();
+
+    let mut prefix = [0u8; 4];
+    if reader.read_exact(&mut prefix).await.is_err() || &prefix != b"API\0" {
+        return;
+    }
+    // The client's supported version range; we answer with ours regardless.
+    if read_message(&mut reader).await.is_err() {
+        return;
+    }
+    let handshake = format!("{}|{}|", SERVER_VERSION, connection_time());
+    if writer.write_all(&encode(&handshake)).await.is_err() {
+        return;
+    }
+
+    let (outbox, mut pending) = mpsc::unbounded_channel::<String>();
+    gateway.state.lock().unwrap().outbox = Some(outbox);
+    let sender = tokio::spawn(async move {
+        while let Some(message) = pending.recv().await {
+            if writer.write_all(&encode(&message)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    while let Ok(fields) = read_message(&mut reader).await {
+        gateway.handle(&fields);
+    }
+    sender.abort();
+}
+
+/// Reads one length-prefixed message and splits it into fields.
+async fn read_message(reader: &mut OwnedReadHalf) -> std::io::Result<Vec<String>> {
+    let length = reader.read_u32().await? as usize;
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body).await?;
+    let text = String::from_utf8_lossy(&body);
+    let mut fields: Vec<String> = text.split('\0').map(str::to_string).collect();
+    if fields.last().is_some_and(|f| f.is_empty()) {
+        fields.pop();
+    }
+    Ok(fields)
+}
+
+/// Frames a `|`-separated message the way TWS sends it.
+fn encode(message: &str) -> Vec<u8> {
+    let body = message.replace('|', "\0");
+    let mut framed = (body.len() as u32).to_be_bytes().to_vec();
+    framed.extend_from_slice(body.as_bytes());
+    framed
+}
+
+fn connection_time() -> String {
+    let format = format_description!("[year][month][day] [hour]:[minute]:[second]");
+    let now = OffsetDateTime::now_utc()
+        .format(&format)
+        .unwrap_or_default();
+    format!("{} UTC", now)
+}
+
+fn execution_time() -> String {
+    let format = format_description!("[year][month][day]  [hour]:[minute]:[second]");
+    OffsetDateTime::now_utc()
+        .format(&format)
+        .unwrap_or_default()
+}
+
+fn bar_time(unix: i64) -> String {
+    let format = format_description!("[year][month][day]  [hour]:[minute]:[second]");
+    OffsetDateTime::from_unix_timestamp(unix)
+        .ok()
+        .and_then(|t| t.format(&format).ok())
+        .unwrap_or_default()
+}
+
+fn round_cents(price: f64) -> f64 {
+    (price * 100.0).round() / 100.0
+}
+
+fn field(fields: &[String], index: usize) -> &str {
+    fields.get(index).map_or("", String::as_str)
+}
+
+fn int_field(fields: &[String], index: usize) -> i32 {
+    field(fields, index).parse().unwrap_or(0)
+}
+
+impl Gateway {
+    fn handle(&self, fields: &[String]) {
+        let mut state = self.state.lock().unwrap();
+        state.requests.push(fields.join("|"));
+        match field(fields, 0) {
+            // StartApi
+            "71" => {
+                state.client_id = int_field(fields, 2);
+                let next_id = NEXT_ORDER_ID.load(Ordering::SeqCst);
+                state.send(format!("9|1|{}|", next_id));
+                state.send(format!("15|1|{}|", self.script.account));
+            }
+            // RequestIds
+            "8" => {
+                let next_id = NEXT_ORDER_ID.load(Ordering::SeqCst);
+                state.send(format!("9|1|{}|", next_id));
+            }
+            // RequestManagedAccounts
+            "17" => state.send(format!("15|1|{}|", self.script.account)),
+            // RequestContractData
+            "9" => state.contract_details(int_field(fields, 2), field(fields, 4)),
+            // RequestMarketData / CancelMarketData
+            "1" => state.market_data(int_field(fields, 2), field(fields, 4)),
+            "2" => {
+                let request_id = int_field(fields, 2);
+                state.market_data.retain(|(id, _)| *id != request_id);
+            }
+            // RequestHistoricalData
+            "20" => state.historical_data(int_field(fields, 1), field(fields, 3)),
+            // RequestAccountData; a 0 in the subscribe field cancels it
+            "6" if field(fields, 2) == "1" => self.account_updates(&state),
+            // RequestPositions
+            "61" => self.positions(&state),
+            // RequestPnL
+            "92" => self.pnl(&state, int_field(fields, 1)),
+            // PlaceOrder
+            "3" => self.place_order(&mut state, fields),
+            // CancelOrder
+            "4" => state.cancel(int_field(fields, 2)),
+            // RequestGlobalCancel
+            "58" => {
+                let working: Vec<i32> = state
+                    .orders
+                    .values()
+                    .filter(|o| o.is_working())
+                    .map(|o| o.order_id)
+                    .collect();
+                for order_id in working {
+                    state.cancel(order_id);
+                }
+            }
+            // RequestOpenOrders / RequestAllOpenOrders
+            "5" | "16" => {
+                let working: Vec<MockOrder> = state
+                    .orders
+                    .values()
+                    .filter(|o| o.is_working())
+                    .cloned()
+                    .collect();
+                for order in &working {
+                    state.send(open_order(&self.script.account, order, None));
+                    state.send(order_status(order, 0.0));
+                }
+                state.send("53|1|".to_string());
+            }
+            // RequestExecutions
+            "7" => state.executions(int_field(fields, 2), field(fields, 6), &self.script.account),
+            _ => {}
+        }
+    }
+
+    fn account_updates(&self, state: &State) {
+        let account = &self.script.account;
+        for (key, value, currency) in &self.script.account_values {
+            state.send(format!("6|2|{}|{}|{}|{}|", key, value, currency, account));
+        }
+        state.send("8|1|09:30|".to_string());
+        state.send(format!("54|1|{}|", account));
+    }
+
+    fn positions(&self, state: &State) {
+        for (symbol, (quantity, average_cost)) in &state.positions {
+            let contract_id = state.symbols.get(symbol).map_or(0, |s| s.contract_id);
+            state.send(format!(
+                "61|3|{}|{}|{}|STK||0.0|||NASDAQ|USD|{}|NMS|{}|{}|",
+                self.script.account, contract_id, symbol, symbol, quantity, average_cost
+            ));
+        }
+        state.send("62|1|".to_string());
+    }
+
+    fn pnl(&self, state: &State, request_id: i32) {
+        let realized: f64 = state.executions.iter().filter_map(|e| e.realized_pnl).sum();
+        let unrealized: f64 = state
+            .positions
+            .iter()
+            .map(|(symbol, (quantity, cost))| {
+                let last = state.symbols.get(symbol).map_or(*cost, |s| s.last);
+                (last - cost) * quantity
+            })
+            .sum();
+        state.send(format!(
+            "94|{}|{}|{}|{}|",
+            request_id,
+            self.script.daily_pnl + realized + unrealized,
+            unrealized,
+            realized
+        ));
+    }
+
+    fn place_order(&self, state: &mut State, fields: &[String]) {
+        let order_id = int_field(fields, 1);
+        NEXT_ORDER_ID.fetch_max(order_id + 1, Ordering::SeqCst);
+        let what_if_index = fields
+            .len()
+            .saturating_sub(PLACE_ORDER_FIELDS_AFTER_WHAT_IF + 1);
+        let what_if = field(fields, what_if_index) == "1";
+        let symbol = field(fields, 3).to_string();
+        let contract_id = state.symbols.get(&symbol).map_or(0, |s| s.contract_id);
+        let existing = state.orders.get(&order_id).cloned();
+        let order = MockOrder {
+            order_id,
+            client_id: state.client_id,
+            perm_id: 1_000_000 + order_id,
+            contract_id,
+            symbol,
+            action: field(fields, 16).to_string(),
+            quantity: field(fields, 17).parse().unwrap_or(0.0),
+            order_type: field(fields, 18).to_string(),
+            limit_price: field(fields, 19).to_string(),
+            aux_price: field(fields, 20).to_string(),
+            tif: field(fields, 21).to_string(),
+            oca_group: field(fields, 22).to_string(),
+            order_ref: field(fields, 26).to_string(),
+            status: existing
+                .as_ref()
+                .map_or("PreSubmitted".to_string(), |o| o.status.clone()),
+            filled: existing.as_ref().map_or(0.0, |o| o.filled),
+            average_fill_price: existing.as_ref().map_or(0.0, |o| o.average_fill_price),
+        };
+
+        let Some(quote) = state.symbols.get(&order.symbol).cloned() else {
+            state.send(format!(
+                "4|2|{}|200|No security definition has been found for the request|",
+                order_id
+            ));
+            return;
+        };
+        if !ORDER_TYPES.contains(&order.order_type.as_str()) {
+            state.send(format!(
+                "4|2|{}|321|Error validating request:-'bN' : cause - Invalid order type: '{}'|",
+                order_id, order.order_type
+            ));
+            return;
+        }
+        if what_if {
+            let preview = Preview::of(&order, &quote, &self.script);
+            state.send(open_order(&self.script.account, &order, Some(&preview)));
+            return;
+        }
+        if existing.as_ref().is_some_and(|o| !o.is_working()) {
+            state.send(format!(
+                "4|2|{}|104|Cannot modify a filled order.|",
+                order_id
+            ));
+            return;
+        }
+
+        let mut order = order;
+        if existing.is_none() {
+            order.status = "Submitted".to_string();
+        }
+        state.send(open_order(&self.script.account, &order, None));
+        state.send(order_status(&order, 0.0));
+        state.orders.insert(order_id, order.clone());
+
+        let marketable = match order.order_type.as_str() {
+            "MKT" if quote.halted => None,
+            "MKT" => Some(if order.is_buy() { quote.ask } else { quote.bid }),
+            "LMT" => order.limit().filter(|limit| {
+                if order.is_buy() {
+                    *limit >= quote.ask
+                } else {
+                    *limit <= quote.bid
+                }
+            }),
+            _ => None,
+        };
+        match marketable {
+            Some(price) => state.fill(&self.script, order_id, price),
+            None if order.tif == "IOC" => state.cancel(order_id),
+            None => {}
+        }
+    }
+}
+
+impl State {
+    fn send(&self, message: String) {
+        if let Some(outbox) = &self.outbox {
+            let _ = outbox.send(message);
+        }
+    }
+
+    fn contract_details(&self, request_id: i32, symbol: &str) {
+        let Some(quote) = self.symbols.get(symbol) else {
+            self.send(format!(
+                "4|2|{}|200|No security definition has been found for the request|",
+                request_id
+            ));
+            return;
+        };
+        self.send(format!(
+            "10|{request_id}|{symbol}|STK||0||SMART|USD|{symbol}|NMS|NMS|{conid}|{tick}||LMT,MKT,STP,TRAIL,OCA,WHATIF|SMART,NASDAQ,NYSE,ARCA|1|0|{symbol} INC|NASDAQ||Technology|Computers|Computers|US/Eastern|{hours}|{liquid}|||1|ISIN|US{conid}|1|||26||COMMON|1|1|100|",
+            conid = quote.contract_id,
+            tick = quote.min_tick,
+            hours = "20261019:0400-20261019:2000",
+            liquid = "20261019:0930-20261019:1600",
+        ));
+        self.send(format!("52|1|{}|", request_id));
+    }
+
+    fn market_data(&mut self, request_id: i32, symbol: &str) {
+        let Some(quote) = self.symbols.get(symbol).cloned() else {
+            self.send(format!(
+                "4|2|{}|200|No security definition has been found for the request|",
+                request_id
+            ));
+            return;
+        };
+        self.market_data.push((request_id, symbol.to_string()));
+        self.send(format!("1|6|{}|1|{}|500|1|", request_id, quote.bid));
+        self.send(format!("1|6|{}|2|{}|300|1|", request_id, quote.ask));
+        self.send(format!("1|6|{}|4|{}|100|0|", request_id, quote.last));
+        self.send(format!("1|6|{}|6|{}|0|0|", request_id, quote.high()));
+        self.send(format!("1|6|{}|7|{}|0|0|", request_id, quote.low()));
+        self.send(format!("2|6|{}|8|{}|", request_id, quote.volume));
+    }
+
+    fn historical_data(&self, request_id: i32, symbol: &str) {
+        let Some(quote) = self.symbols.get(symbol) else {
+            self.send(format!(
+                "4|2|{}|162|Historical Market Data Service error message:No data for {}|",
+                request_id, symbol
+            ));
+            return;
+        };
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let first = now - now % 60 - 60 * (quote.bars.len() as i64 - 1);
+        let mut message = format!(
+            "17|{}|{}|{}|{}|",
+            request_id,
+            bar_time(first),
+            bar_time(now),
+            quote.bars.len()
+        );
+        for (i, bar) in quote.bars.iter().enumerate() {
+            message.push_str(&format!(
+                "{}|{}|{}|{}|{}|{}|{}|{}|",
+                first + 60 * i as i64,
+                bar.open,
+                bar.high,
+                bar.low,
+                bar.close,
+                bar.volume,
+                round_cents((bar.open + bar.close) / 2.0),
+                (bar.volume / 100.0) as i64
+            ));
+        }
+        self.send(message);
+    }
+
+    fn cancel(&mut self, order_id: i32) {
+        let Some(order) = self.orders.get_mut(&order_id) else {
+            self.send(format!(
+                "4|2|{}|135|Can't find order with id = {}|",
+                order_id, order_id
+            ));
+            return;
+        };
+        if !order.is_working() {
+            let message = format!(
+                "4|2|{}|10148|OrderId {} that needs to be cancelled cannot be cancelled, state: {}.|",
+                order_id, order_id, order.status
+            );
+            self.send(message);
+            return;
+        }
+        order.status = "Cancelled".to_string();
+        let order = order.clone();
+        self.send(order_status(&order, 0.0));
+        self.send(format!("4|2|{}|202|Order Canceled - reason:|", order_id));
+    }
+
+    /// Fills what is left of `order_id` at `price` and cancels the rest of its OCA group.
+    fn fill(&mut self, script: &Script, order_id: i32, price: f64) {
+        let Some(order) = self.orders.get_mut(&order_id) else {
+            return;
+        };
+        let shares = order.remaining();
+        if shares <= 0.0 {
+            return;
+        }
+        order.average_fill_price =
+            (order.average_fill_price * order.filled + price * shares) / (order.filled + shares);
+        order.filled += shares;
+        order.status = "Filled".to_string();
+        let order = order.clone();
+
+        let signed = if order.is_buy() { shares } else { -shares };
+        let realized_pnl = self.apply_fill(&order.symbol, signed, price);
+        let execution = MockExecution {
+            execution_id: format!(
+                "0000e0d5.{:08x}.01.01",
+                NEXT_EXECUTION_ID.fetch_add(1, Ordering::SeqCst)
+            ),
+            order_id,
+            client_id: order.client_id,
+            perm_id: order.perm_id,
+            contract_id: order.contract_id,
+            symbol: order.symbol.clone(),
+            side: if order.is_buy() { "BOT" } else { "SLD" }.to_string(),
+            shares,
+            price,
+            cumulative: order.filled,
+            average_price: order.average_fill_price,
+            order_ref: order.order_ref.clone(),
+            time: execution_time(),
+            commission: (shares * script.commission_per_share).max(1.0),
+            realized_pnl,
+        };
+        self.send(execution_data(-1, &execution, &script.account));
+        self.send(order_status(&order, price));
+        self.send(commission_report(&execution));
+        self.executions.push(execution);
+
+        let siblings: Vec<i32> = self
+            .orders
+            .values()
+            .filter(|o| {
+                !order.oca_group.is_empty()
+                    && o.oca_group == order.oca_group
+                    && o.order_id != order_id
+                    && o.is_working()
+            })
+            .map(|o| o.order_id)
+            .collect();
+        for sibling in siblings {
+            self.cancel(sibling);
+        }
+    }
+
+    /// Moves the position by `signed` shares, returning P&L realized by any reduction.
+    fn apply_fill(&mut self, symbol: &str, signed: f64, price: f64) -> Option<f64> {
+        let (quantity, cost) = self.positions.get(symbol).copied().unwrap_or((0.0, 0.0));
+        let after = quantity + signed;
+        let reducing = quantity != 0.0 && quantity.signum() != signed.signum();
+        let realized = reducing.then(|| {
+            let closed = signed.abs().min(quantity.abs());
+            (price - cost) * closed * quantity.signum()
+        });
+        let cost = if after == 0.0 {
+            0.0
+        } else if !reducing {
+            (quantity * cost + signed * price) / after
+        } else if after.signum() != quantity.signum() {
+            price
+        } else {
+            cost
+        };
+        if after == 0.0 {
+            self.positions.remove(symbol);
+        } else {
+            self.positions.insert(symbol.to_string(), (after, cost));
+        }
+        realized
+    }
+
+    fn executions(&self, request_id: i32, symbol: &str, account: &str) {
+        for execution in self
+            .executions
+            .iter()
+            .filter(|e| symbol.is_empty() || e.symbol == symbol)
+        {
+            self.send(execution_data(request_id, execution, account));
+            self.send(commission_report(execution));
+        }
+        self.send(format!("55|1|{}|", request_id));
+    }
+}
+
+/// Margin and commission numbers for a what-if order.
+struct Preview {
+    initial_margin_change: f64,
+    maintenance_margin_change: f64,
+    equity_with_loan: f64,
+    commission: f64,
+}
+
+impl Preview {
+    fn of(order: &MockOrder, quote: &MockSymbol, script: &Script) -> Self {
+        let notional = order.quantity * quote.last;
+        let equity_with_loan = script
+            .account_values
+            .iter()
+            .find(|(key, _, _)| key == "NetLiquidation")
+            .and_then(|(_, value, _)| value.parse().ok())
+            .unwrap_or(0.0);
+        Preview {
+            initial_margin_change: round_cents(notional * 0.25),
+            maintenance_margin_change: round_cents(notional * 0.25),
+            equity_with_loan,
+            commission: (order.quantity * script.commission_per_share).max(1.0),
+        }
+    }
+}
+
+fn open_order(account: &str, order: &MockOrder, preview: Option<&Preview>) -> String {
+    let mut fields: Vec<String> = OPEN_ORDER_TEMPLATE.split('|').map(str::to_string).collect();
+    let mut set = |index: usize, value: String| fields[index] = value;
+    set(1, order.order_id.to_string());
+    set(2, order.contract_id.to_string());
+    set(3, order.symbol.clone());
+    set(11, order.symbol.clone());
+    set(13, order.action.clone());
+    set(14, order.quantity.to_string());
+    set(15, order.order_type.clone());
+    set(16, order.limit_price.clone());
+    set(17, order.aux_price.clone());
+    set(18, order.tif.clone());
+    set(19, order.oca_group.clone());
+    set(20, account.to_string());
+    set(23, order.order_ref.clone());
+    set(24, order.client_id.to_string());
+    set(25, order.perm_id.to_string());
+    set(OPEN_ORDER_STATUS, order.status.clone());
+    if let Some(preview) = preview {
+        let before = |value: f64| value.to_string();
+        set(OPEN_ORDER_WHAT_IF, "1".to_string());
+        // Margins before, change and after, then commission, min, max and currency.
+        let margins = [
+            before(0.0),
+            before(0.0),
+            before(preview.equity_with_loan),
+            before(preview.initial_margin_change),
+            before(preview.maintenance_margin_change),
+            before(0.0),
+            before(preview.initial_margin_change),
+            before(preview.maintenance_margin_change),
+            before(preview.equity_with_loan),
+            before(preview.commission),
+            UNSET_DOUBLE.to_string(),
+            UNSET_DOUBLE.to_string(),
+            "USD".to_string(),
+        ];
+        for (offset, value) in margins.into_iter().enumerate() {
+            set(OPEN_ORDER_STATUS + 1 + offset, value);
+        }
+    }
+    fields.join("|")
+}
+
+fn order_status(order: &MockOrder, last_fill_price: f64) -> String {
+    format!(
+        "3|{}|{}|{}|{}|{}|{}|0|{}|{}||0|",
+        order.order_id,
+        order.status,
+        order.filled,
+        order.remaining(),
+        order.average_fill_price,
+        order.perm_id,
+        last_fill_price,
+        order.client_id
+    )
+}
+
+fn execution_data(request_id: i32, e: &MockExecution, account: &str) -> String {
+    format!(
+        "11|{}|{}|{}|{}|STK||0.0|||SMART|USD|{}|NMS|{}|{}|{}|ISLAND|{}|{}|{}|{}|{}|0|{}|{}|{}||||2|",
+        request_id,
+        e.order_id,
+        e.contract_id,
+        e.symbol,
+        e.symbol,
+        e.execution_id,
+        e.time,
+        account,
+        e.side,
+        e.shares,
+        e.price,
+        e.perm_id,
+        e.client_id,
+        e.cumulative,
+        e.average_price,
+        e.order_ref
+    )
+}
+
+fn commission_report(e: &MockExecution) -> String {
+    let realized = e
+        .realized_pnl
+        .map_or(UNSET_DOUBLE.to_string(), |pnl| pnl.to_string());
+    format!(
+        "59|1|{}|{}|USD|{}|{}||",
+        e.execution_id, e.commission, realized, UNSET_DOUBLE
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(order_type: &str, action: &str, limit: &str, aux: &str) -> MockOrder {
+        MockOrder {
+            order_id: 1,
+            client_id: 100,
+            perm_id: 1_000_001,
+            contract_id: 265598,
+            symbol: "AAPL".to_string(),
+            action: action.to_string(),
+            quantity: 100.0,
+            order_type: order_type.to_string(),
+            limit_price: limit.to_string(),
+            aux_price: aux.to_string(),
+            tif: "DAY".to_string(),
+            oca_group: String::new(),
+            order_ref: String::new(),
+            status: "Submitted".to_string(),
+            filled: 0.0,
+            average_fill_price: 0.0,
+        }
+    }
+
+    #[test]
+    fn frames_messages_with_length_prefix() {
+        assert_eq!(encode("9|1|5|"), b"\0\0\0\x069\x001\x005\0".to_vec());
+    }
+
+    #[test]
+    fn open_order_keeps_template_layout() {
+        let message = open_order("DU1234567", &order("LMT", "SELL", "195.5", ""), None);
+        let fields: Vec<&str> = message.split('|').collect();
+        assert_eq!(fields.len(), OPEN_ORDER_TEMPLATE.split('|').count());
+        assert_eq!(fields[OPEN_ORDER_STATUS], "Submitted");
+        assert_eq!(fields[16], "195.5");
+    }
+
+    #[test]
+    fn resting_orders_trigger_on_price() {
+        assert!(reaches(&order("LMT", "SELL", "195.5", ""), 195.5));
+        assert!(!reaches(&order("LMT", "SELL", "195.5", ""), 195.0));
+        assert!(reaches(&order("STP", "SELL", "", "185"), 184.9));
+        assert!(!reaches(&order("MKT", "BUY", "", ""), 190.0));
+        assert!(!reaches(&order("STOP", "SELL", "", "185"), 184.9));
+        assert!(!ORDER_TYPES.contains(&"STOP"));
+    }
+}
@@ -0,0 +1,635 @@
+use std::collections::HashMap;
+
+use ibapi::{
+    contracts::Contract,
+    orders::{
+        CommissionReport, ExecutionData, Order, OrderData, OrderStatus, OrderUpdate, PlaceOrder,
+    },
+    subscriptions::Subscription,
+};
+use serde::Serialize;
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use tokio::sync::{RwLock, broadcast};
+use tokio::task::AbortHandle;
+
+use crate::broker::OrderEvent;
+use crate::executions;
+
+/// Statuses after which IBKR sends no further updates for an order.
+const TERMINAL_STATUSES: [&str; 4] = ["Filled", "Cancelled", "ApiCancelled", "Inactive"];
+
+#[derive(Serialize, Clone, Debug)]
+pub struct StatusChange {
+    pub status: String,
+    pub filled: f64,
+    pub remaining: f64,
+    pub at: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Fill {
+    pub execution_id: String,
+    pub shares: f64,
+    pub price: f64,
+    pub time: String,
+}
+
+/// Everything we know about one order, kept current from IBKR's status and execution messages.
+#[derive(Serialize, Clone, Debug)]
+pub struct TrackedOrder {
+    pub order_id: i32,
+    pub client_id: i32,
+    pub perm_id: i32,
+    pub symbol: String,
+    pub action: String,
+    pub order_type: String,
+    pub quantity: f64,
+    pub limit_price: Option<f64>,
+    pub stop_price: Option<f64>,
+    pub oca_group: String,
+    pub status: String,
+    pub filled: f64,
+    pub remaining: f64,
+    pub average_fill_price: f64,
+    pub commission: f64,
+    pub realized_pnl: f64,
+    pub history: Vec<StatusChange>,
+    pub fills: Vec<Fill>,
+    /// What was sent to IBKR, kept so the order can be amended under the same id.
+    #[serde(skip)]
+    pub contract: Contract,
+    #[serde(skip)]
+    pub order: Order,
+}
+
+impl TrackedOrder {
+    fn new(order_id: i32, client_id: i32, contract: &Contract, order: &Order) -> Self {
+        TrackedOrder {
+            order_id,
+            client_id,
+            perm_id: order.perm_id,
+            symbol: contract.symbol.0.clone(),
+            action: order.action.to_string(),
+            order_type: order.order_type.clone(),
+            quantity: order.total_quantity,
+            limit_price: order.limit_price,
+            stop_price: order.aux_price,
+            oca_group: order.oca_group.clone(),
+            status: "PendingSubmit".to_string(),
+            filled: 0.0,
+            remaining: order.total_quantity,
+            average_fill_price: 0.0,
+            commission: 0.0,
+            realized_pnl: 0.0,
+            history: Vec::new(),
+            fills: Vec::new(),
+            contract: contract.clone(),
+            order: order.clone(),
+        }
+    }
+
+    pub fn is_working(&self) -> bool {
+        !TERMINAL_STATUSES.contains(&self.status.as_str())
+    }
+
+    pub fn trade_id(&self) -> i32 {
+        trade_id(self.order_id, &self.oca_group)
+    }
+}
+
+/// The entry order id an order belongs to: exits carry it in their
+/// `scale-out-{entry}-{n}` OCA group, entries are their own trade.
+pub fn trade_id(order_id: i32, oca_group: &str) -> i32 {
+    oca_group
+        .strip_prefix("scale-out-")
+        .and_then(|rest| rest.split('-').next())
+        .and_then(|id| id.parse().ok())
+        .unwrap_or(order_id)
+}
+
+pub struct OrderBook {
+    /// Keyed by `(client_id, order_id)`: IBKR order ids are only unique per client id.
+    orders: HashMap<(i32, i32), TrackedOrder>,
+    events: broadcast::Sender<TrackedOrder>,
+}
+
+impl Default for OrderBook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+lazy_static::lazy_static! {
+    pub static ref ORDER_BOOK: RwLock<OrderBook> = RwLock::new(OrderBook::new());
+    /// The task draining each order's `place_order` stream, so a modify replaces it.
+    static ref TRACKERS: std::sync::Mutex<HashMap<i32, AbortHandle>> =
+        std::sync::Mutex::new(HashMap::new());
+}
+
+impl OrderBook {
+    pub fn new() -> Self {
+        let (events, _) = broadcast::channel(256);
+        OrderBook {
+            orders: HashMap::new(),
+            events,
+        }
+    }
+
+    /// Receives a snapshot of every order after each change.
+    pub fn subscribe(&self) -> broadcast::Receiver<TrackedOrder> {
+        self.events.subscribe()
+    }
+
+    pub fn list(&self) -> Vec<TrackedOrder> {
+        let mut orders: Vec<TrackedOrder> = self.orders.values().cloned().collect();
+        orders.sort_by_key(|o| (o.order_id, o.client_id));
+        orders
+    }
+
+    /// The lowest order id above every tracked order.
+    pub fn next_order_id(&self) -> i32 {
+        self.orders
+            .keys()
+            .map(|(_, order_id)| *order_id)
+            .max()
+            .map_or(1, |id| id + 1)
+    }
+
+    pub fn get(&self, client_id: i32, order_id: i32) -> Option<TrackedOrder> {
+        self.orders.get(&(client_id, order_id)).cloned()
+    }
+
+    pub fn record_placed(
+        &mut self,
+        order_id: i32,
+        client_id: i32,
+        contract: &Contract,
+        order: &Order,
+    ) {
+        let tracked = TrackedOrder::new(order_id, client_id, contract, order);
+        self.orders.insert((client_id, order_id), tracked);
+        self.publish((client_id, order_id));
+    }
+
+    /// Replaces the order details after it was re-placed under the same id.
+    pub fn record_modified(&mut self, client_id: i32, order_id: i32, order: &Order) {
+        if let Some(tracked) = self.orders.get_mut(&(client_id, order_id)) {
+            tracked.quantity = order.total_quantity;
+            tracked.limit_price = order.limit_price;
+            tracked.stop_price = order.aux_price;
+            tracked.order = order.clone();
+            tracked.history.push(StatusChange {
+                status: "Modified".to_string(),
+                filled: tracked.filled,
+                remaining: tracked.remaining,
+                at: now(),
+            });
+            self.publish((client_id, order_id));
+        }
+    }
+
+    /// Adds or refreshes an order reported by `open_orders`/`all_open_orders`.
+    pub fn apply_open_order(&mut self, data: &OrderData) {
+        let key = (data.order.client_id, data.order_id);
+        let tracked = self.orders.entry(key).or_insert_with(|| {
+            TrackedOrder::new(
+                data.order_id,
+                data.order.client_id,
+                &data.contract,
+                &data.order,
+            )
+        });
+        tracked.quantity = data.order.total_quantity;
+        tracked.limit_price = data.order.limit_price;
+        tracked.stop_price = data.order.aux_price;
+        tracked.order = data.order.clone();
+        if !data.order_state.status.is_empty() {
+            tracked.status = data.order_state.status.clone();
+        }
+        self.publish(key);
+    }
+
+    /// Applies a status, fill or commission update from the backend.
+    pub fn apply(&mut self, event: &OrderEvent) {
+        match event {
+            OrderEvent::Status {
+                order_id,
+                client_id,
+                status,
+                filled,
+                remaining,
+                average_fill_price,
+                perm_id,
+            } => {
+                let key = (*client_id, *order_id);
+                let Some(tracked) = self.orders.get_mut(&key) else {
+                    return;
+                };
+                let changed = tracked.status != *status
+                    || tracked.filled != *filled
+                    || tracked.remaining != *remaining;
+                tracked.status = status.clone();
+                tracked.filled = *filled;
+                tracked.remaining = *remaining;
+                tracked.average_fill_price = *average_fill_price;
+                if *perm_id != 0 {
+                    tracked.perm_id = *perm_id;
+                }
+                if changed {
+                    tracked.history.push(StatusChange {
+                        status: status.clone(),
+                        filled: *filled,
+                        remaining: *remaining,
+                        at: now(),
+                    });
+                    self.publish(key);
+                }
+            }
+            OrderEvent::Fill {
+                order_id,
+                client_id,
+                execution_id,
+                shares,
+                price,
+                time,
+            } => {
+                let key = (*client_id, *order_id);
+                let Some(tracked) = self.orders.get_mut(&key) else {
+                    return;
+                };
+                if tracked
+                    .fills
+                    .iter()
+                    .any(|f| f.execution_id == *execution_id)
+                {
+                    return;
+                }
+                tracked.fills.push(Fill {
+                    execution_id: execution_id.clone(),
+                    shares: *shares,
+                    price: *price,
+                    time: time.clone(),
+                });
+                self.publish(key);
+            }
+            OrderEvent::Commission {
+                execution_id,
+                commission,
+                realized_pnl,
+            } => {
+                let Some(tracked) = self
+                    .orders
+                    .values_mut()
+                    .find(|o| o.fills.iter().any(|f| f.execution_id == *execution_id))
+                else {
+                    return;
+                };
+                tracked.commission += commission;
+                // Opening executions carry no realized P&L; skip the infinity sentinel too.
+                if let Some(pnl) = realized_pnl.filter(|p| p.abs() < 1e12) {
+                    tracked.realized_pnl += pnl;
+                }
+                let key = (tracked.client_id, tracked.order_id);
+                self.publish(key);
+            }
+        }
+    }
+
+    /// Number of trades last updated at or after `since` whose exits so far realized a net loss.
+    pub fn losing_trades(&self, since: OffsetDateTime) -> usize {
+        let mut by_trade: HashMap<(i32, i32), (f64, Option<OffsetDateTime>)> = HashMap::new();
+        for order in self.orders.values() {
+            let (pnl, last_change) = by_trade
+                .entry((order.client_id, order.trade_id()))
+                .or_insert((0.0, None));
+            *pnl += order.realized_pnl;
+            let at = order
+                .history
+                .last()
+                .and_then(|change| OffsetDateTime::parse(&change.at, &Rfc3339).ok());
+            *last_change = (*last_change).max(at);
+        }
+        by_trade
+            .values()
+            .filter(|(pnl, last_change)| *pnl < 0.0 && last_change.is_some_and(|at| at >= since))
+            .count()
+    }
+
+    pub fn mark_rejected(&mut self, client_id: i32, order_id: i32, reason: &str) {
+        if let Some(tracked) = self.orders.get_mut(&(client_id, order_id)) {
+            tracked.status = "Inactive".to_string();
+            tracked.history.push(StatusChange {
+                status: format!("Inactive: {}", reason),
+                filled: tracked.filled,
+                remaining: tracked.remaining,
+                at: now(),
+            });
+            self.publish((client_id, order_id));
+        }
+    }
+
+    fn publish(&self, key: (i32, i32)) {
+        if let Some(tracked) = self.orders.get(&key) {
+            // No receivers just means nobody is watching the feed.
+            let _ = self.events.send(tracked.clone());
+        }
+    }
+}
+
+/// Tracks `order_id`'s updates from `subscription`, replacing any earlier tracker.
+///
+/// Re-placing an order to modify it opens a new stream for the same id, so the
+/// old tracker is stopped rather than left running beside the new one.
+pub fn spawn_tracker(order_id: i32, subscription: Subscription<PlaceOrder>) {
+    replace_tracker(order_id, track(subscription));
+}
+
+fn replace_tracker(order_id: i32, task: impl Future<Output = ()> + Send + 'static) {
+    let mut trackers = TRACKERS.lock().unwrap();
+    trackers.retain(|_, handle| !handle.is_finished());
+    if let Some(previous) = trackers.remove(&order_id) {
+        previous.abort();
+    }
+    trackers.insert(order_id, tokio::spawn(task).abort_handle());
+}
+
+/// Drains a `place_order` stream into the order book until IBKR closes it.
+pub async fn track(mut subscription: Subscription<PlaceOrder>) {
+    while let Some(update) = subscription.next().await {
+        match update {
+            Ok(PlaceOrder::OrderStatus(status)) => {
+                ORDER_BOOK.write().await.apply(&(&status).into())
+            }
+            Ok(PlaceOrder::OpenOrder(data)) => ORDER_BOOK.write().await.apply_open_order(&data),
+            Ok(PlaceOrder::ExecutionData(data)) => {
+                let order = {
+                    let mut book = ORDER_BOOK.write().await;
+                    book.apply(&(&data).into());
+                    book.get(data.execution.client_id, data.execution.order_id)
+                };
+                executions::persist_execution(&data, order.as_ref());
+            }
+            Ok(PlaceOrder::CommissionReport(report)) => {
+                ORDER_BOOK.write().await.apply(&(&report).into());
+                executions::persist_commission(&report);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                println!("Error tracking order: {:?}", e);
+                break;
+            }
+        }
+    }
+}
+
+/// Applies statuses from the client-wide order update stream until it ends.
+///
+/// Cancelling an order takes its id's stream away from the tracker, so the cancel's own
+/// status only reaches the book through here. Fills and commissions stay with the
+/// trackers, since applying a commission twice would count it twice.
+pub async fn track_statuses(mut subscription: Subscription<OrderUpdate>) {
+    while let Some(update) = subscription.next().await {
+        match update {
+            Ok(OrderUpdate::OrderStatus(status)) => {
+                ORDER_BOOK.write().await.apply(&(&status).into())
+            }
+            Ok(_) => {}
+            Err(e) => println!("Error reading order updates: {:?}", e),
+        }
+    }
+}
+
+/// Waits until `client_id`'s `order_id` reaches a terminal status, or gives up after `timeout`.
+pub async fn wait_for_terminal(
+    mut updates: broadcast::Receiver<TrackedOrder>,
+    client_id: i32,
+    order_id: i32,
+    timeout: std::time::Duration,
+) -> Option<TrackedOrder> {
+    let wait = async {
+        loop {
+            match updates.recv().await {
+                Ok(order)
+                    if order.client_id == client_id
+                        && order.order_id == order_id
+                        && !order.is_working() =>
+                {
+                    return Some(order);
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    // We may have skipped the final update, so check the book directly.
+                    if let Some(order) = ORDER_BOOK.read().await.get(client_id, order_id)
+                        && !order.is_working()
+                    {
+                        return Some(order);
+                    }
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    };
+    tokio::time::timeout(timeout, wait).await.ok().flatten()
+}
+
+impl From<&OrderStatus> for OrderEvent {
+    fn from(status: &OrderStatus) -> Self {
+        OrderEvent::Status {
+            order_id: status.order_id,
+            client_id: status.client_id,
+            status: status.status.clone(),
+            filled: status.filled,
+            remaining: status.remaining,
+            average_fill_price: status.average_fill_price,
+            perm_id: status.perm_id,
+        }
+    }
+}
+
+impl From<&ExecutionData> for OrderEvent {
+    fn from(data: &ExecutionData) -> Self {
+        OrderEvent::Fill {
+            order_id: data.execution.order_id,
+            client_id: data.execution.client_id,
+            execution_id: data.execution.execution_id.clone(),
+            shares: data.execution.shares,
+            price: data.execution.price,
+            time: data.execution.time.clone(),
+        }
+    }
+}
+
+impl From<&CommissionReport> for OrderEvent {
+    fn from(report: &CommissionReport) -> Self {
+        OrderEvent::Commission {
+            execution_id: report.execution_id.clone(),
+            commission: report.commission,
+            realized_pnl: report.realized_pnl,
+        }
+    }
+}
+
+pub fn now() -> String {
+    OffsetDateTime::now_utc()
+        .format(&Rfc3339)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book_with_order() -> OrderBook {
+        let mut book = OrderBook::new();
+        let contract = Contract::stock("TSLA").build();
+        let order = Order {
+            order_type: "MKT".to_string(),
+            total_quantity: 100.0,
+            ..Default::default()
+        };
+        book.record_placed(7, 100, &contract, &order);
+        book
+    }
+
+    #[test]
+    fn applies_status_fill_and_commission_events() {
+        let mut book = book_with_order();
+        book.apply(&OrderEvent::Status {
+            order_id: 7,
+            client_id: 100,
+            status: "Filled".to_string(),
+            filled: 100.0,
+            remaining: 0.0,
+            average_fill_price: 250.5,
+            perm_id: 42,
+        });
+        let fill = OrderEvent::Fill {
+            order_id: 7,
+            client_id: 100,
+            execution_id: "e1".to_string(),
+            shares: 100.0,
+            price: 250.5,
+            time: "20250102 15:30:00".to_string(),
+        };
+        book.apply(&fill);
+        book.apply(&fill);
+        book.apply(&OrderEvent::Commission {
+            execution_id: "e1".to_string(),
+            commission: 1.0,
+            realized_pnl: Some(f64::MAX),
+        });
+
+        let order = book.get(100, 7).unwrap();
+        assert_eq!(order.status, "Filled");
+        assert_eq!(order.perm_id, 42);
+        assert_eq!(order.fills.len(), 1);
+        assert_eq!(order.commission, 1.0);
+        assert_eq!(order.realized_pnl, 0.0);
+        assert!(!order.is_working());
+    }
+
+    #[test]
+    fn ignores_events_for_unknown_orders() {
+        let mut book = book_with_order();
+        book.apply(&OrderEvent::Status {
+            order_id: 8,
+            client_id: 100,
+            status: "Filled".to_string(),
+            filled: 1.0,
+            remaining: 0.0,
+            average_fill_price: 1.0,
+            perm_id: 0,
+        });
+        assert_eq!(book.list().len(), 1);
+        assert_eq!(book.get(100, 7).unwrap().status, "PendingSubmit");
+    }
+
+    #[test]
+    fn keeps_orders_from_two_clients_with_the_same_id_apart() {
+        let mut book = book_with_order();
+        let mut other = OrderData {
+            order_id: 7,
+            contract: Contract::stock("AAPL").build(),
+            ..Default::default()
+        };
+        other.order.client_id = 90;
+        other.order.order_type = "LMT".to_string();
+        other.order.total_quantity = 5.0;
+        other.order_state.status = "Submitted".to_string();
+        book.apply_open_order(&other);
+        book.apply(&OrderEvent::Status {
+            order_id: 7,
+            client_id: 90,
+            status: "Cancelled".to_string(),
+            filled: 0.0,
+            remaining: 5.0,
+            average_fill_price: 0.0,
+            perm_id: 43,
+        });
+
+        assert_eq!(book.list().len(), 2);
+        let ours = book.get(100, 7).unwrap();
+        assert_eq!((ours.symbol.as_str(), ours.quantity), ("TSLA", 100.0));
+        assert_eq!(ours.status, "PendingSubmit");
+        let theirs = book.get(90, 7).unwrap();
+        assert_eq!((theirs.symbol.as_str(), theirs.quantity), ("AAPL", 5.0));
+        assert_eq!(theirs.status, "Cancelled");
+    }
+
+    #[test]
+    fn counts_losing_trades_closed_since_the_day_began() {
+        let mut book = OrderBook::new();
+        let contract = Contract::stock("TSLA").build();
+        for (order_id, closed_at) in [(1, "2026-10-19T03:59:00Z"), (2, "2026-10-19T04:01:00Z")] {
+            book.record_placed(order_id, 100, &contract, &Order::default());
+            book.apply(&OrderEvent::Fill {
+                order_id,
+                client_id: 100,
+                execution_id: format!("e{}", order_id),
+                shares: 10.0,
+                price: 250.0,
+                time: String::new(),
+            });
+            book.apply(&OrderEvent::Commission {
+                execution_id: format!("e{}", order_id),
+                commission: 1.0,
+                realized_pnl: Some(-50.0),
+            });
+            let tracked = book.orders.get_mut(&(100, order_id)).unwrap();
+            tracked.history.push(StatusChange {
+                status: "Filled".to_string(),
+                filled: 10.0,
+                remaining: 0.0,
+                at: closed_at.to_string(),
+            });
+        }
+
+        // Midnight in New York, 04:00 UTC on summer time.
+        let noon = OffsetDateTime::parse("2026-10-19T16:00:00Z", &Rfc3339).unwrap();
+        let day_began = crate::market_day::start(noon);
+        assert_eq!(book.losing_trades(day_began), 1);
+        assert_eq!(book.losing_trades(day_began - time::Duration::days(1)), 2);
+    }
+
+    #[tokio::test]
+    async fn modifying_an_order_replaces_its_tracker() {
+        let (first_alive, first_dropped) = tokio::sync::oneshot::channel::<()>();
+        let (second_alive, mut second_dropped) = tokio::sync::oneshot::channel::<()>();
+        replace_tracker(-41, async move {
+            let _alive = first_alive;
+            std::future::pending::<()>().await
+        });
+        replace_tracker(-41, async move {
+            let _alive = second_alive;
+            std::future::pending::<()>().await
+        });
+
+        assert!(first_dropped.await.is_err());
+        tokio::task::yield_now().await;
+        assert_eq!(
+            second_dropped.try_recv(),
+            Err(tokio::sync::oneshot::error::TryRecvError::Empty)
+        );
+        TRACKERS.lock().unwrap().remove(&-41).unwrap().abort();
+    }
+}
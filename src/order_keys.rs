@@ -0,0 +1,163 @@
+use std::time::Duration;
+
+use rusqlite::{Connection, OptionalExtension, params};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339, macros::format_description};
+
+use crate::market_day;
+use crate::order_book;
+
+/// How long a submission may take before its claim is presumed abandoned, e.g. by a crash
+/// between claiming the key and storing the result.
+pub const STALE_AFTER: Duration = Duration::from_secs(300);
+
+/// What a submission with a `client_order_key` should do.
+#[derive(Debug, PartialEq)]
+pub enum Claim {
+    /// First use of the key today: go ahead and place the order.
+    New,
+    /// The key was already used today; return its original result.
+    Duplicate { ok: bool, message: String },
+    /// Another submission with this key has not finished yet.
+    InProgress,
+    /// A submission with this key started longer than `STALE_AFTER` ago and never finished;
+    /// whether it reached IBKR is unknown.
+    Stale,
+}
+
+pub fn create_tables(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS order_keys (
+            key TEXT PRIMARY KEY,
+            trading_day TEXT NOT NULL,
+            order_id INTEGER,
+            ok INTEGER,
+            message TEXT,
+            created_at TEXT NOT NULL
+        );",
+    )
+}
+
+/// The trading day keys are scoped to, as `yyyy-mm-dd` in New York time.
+pub fn trading_day() -> String {
+    market_day::today()
+        .format(format_description!("[year]-[month]-[day]"))
+        .unwrap_or_default()
+}
+
+/// Claims `key` for `day`, forgetting keys from earlier days.
+pub fn claim(conn: &Connection, key: &str, day: &str) -> rusqlite::Result<Claim> {
+    conn.execute(
+        "DELETE FROM order_keys WHERE trading_day < ?1",
+        params![day],
+    )?;
+    let inserted = conn.execute(
+        "INSERT OR IGNORE INTO order_keys (key, trading_day, created_at) VALUES (?1, ?2, ?3)",
+        params![key, day, order_book::now()],
+    )?;
+    if inserted > 0 {
+        return Ok(Claim::New);
+    }
+    let stored = conn
+        .query_row(
+            "SELECT ok, message, created_at FROM order_keys WHERE key = ?1",
+            params![key],
+            |row| {
+                Ok((
+                    row.get::<_, Option<bool>>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            },
+        )
+        .optional()?;
+    Ok(match stored {
+        Some((Some(ok), Some(message), _)) => Claim::Duplicate { ok, message },
+        Some((_, _, created_at)) if is_stale(&created_at) => Claim::Stale,
+        _ => Claim::InProgress,
+    })
+}
+
+fn is_stale(created_at: &str) -> bool {
+    OffsetDateTime::parse(created_at, &Rfc3339)
+        .is_ok_and(|at| OffsetDateTime::now_utc() - at > STALE_AFTER)
+}
+
+/// Stores the result for a claimed key. Without an order id nothing reached IBKR, so the
+/// key is released and a retry may place the order.
+pub fn complete(
+    conn: &Connection,
+    key: &str,
+    order_id: Option<i32>,
+    ok: bool,
+    message: &str,
+) -> rusqlite::Result<()> {
+    match order_id {
+        Some(order_id) => conn.execute(
+            "UPDATE order_keys SET order_id = ?2, ok = ?3, message = ?4 WHERE key = ?1",
+            params![key, order_id, ok, message],
+        )?,
+        None => conn.execute("DELETE FROM order_keys WHERE key = ?1", params![key])?,
+    };
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conn() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables(&conn).unwrap();
+        conn
+    }
+
+    #[test]
+    fn duplicate_key_returns_original_result() {
+        let conn = conn();
+        assert_eq!(claim(&conn, "abc", "2025-01-02").unwrap(), Claim::New);
+        assert_eq!(
+            claim(&conn, "abc", "2025-01-02").unwrap(),
+            Claim::InProgress
+        );
+
+        complete(&conn, "abc", Some(42), true, "Market order 42 filled").unwrap();
+        assert_eq!(
+            claim(&conn, "abc", "2025-01-02").unwrap(),
+            Claim::Duplicate {
+                ok: true,
+                message: "Market order 42 filled".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn keys_last_for_the_trading_day() {
+        let conn = conn();
+        claim(&conn, "abc", "2025-01-02").unwrap();
+        complete(&conn, "abc", Some(42), true, "placed").unwrap();
+
+        assert_eq!(claim(&conn, "abc", "2025-01-03").unwrap(), Claim::New);
+    }
+
+    #[test]
+    fn abandoned_claims_go_stale() {
+        let conn = conn();
+        claim(&conn, "abc", "2025-01-02").unwrap();
+        conn.execute(
+            "UPDATE order_keys SET created_at = '2025-01-02T14:30:00Z' WHERE key = 'abc'",
+            [],
+        )
+        .unwrap();
+
+        assert_eq!(claim(&conn, "abc", "2025-01-02").unwrap(), Claim::Stale);
+    }
+
+    #[test]
+    fn unplaced_orders_release_the_key() {
+        let conn = conn();
+        claim(&conn, "abc", "2025-01-02").unwrap();
+        complete(&conn, "abc", None, false, "Not connected").unwrap();
+
+        assert_eq!(claim(&conn, "abc", "2025-01-02").unwrap(), Claim::New);
+    }
+}
@@ -0,0 +1,90 @@
+use ibapi::orders::{Action, Order, TimeInForce};
+use serde::{Deserialize, Serialize};
+
+use crate::ladder::round_to_tick;
+use crate::order_book::{Fill, TrackedOrder};
+
+/// How far through the quote a marketable limit exit is priced, in percent.
+const MARKETABLE_LIMIT_OFFSET_PERCENT: f64 = 0.5;
+
+/// How an exit order is sent: at market, or as a limit priced through the quote.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitStyle {
+    #[default]
+    Market,
+    MarketableLimit,
+}
+
+/// Outcome of a close or reverse order, with the fills reported so far.
+#[derive(Serialize, Clone, Debug)]
+pub struct CloseReport {
+    pub symbol: String,
+    pub order_id: i32,
+    pub action: String,
+    pub quantity: f64,
+    pub status: String,
+    pub filled: f64,
+    pub average_fill_price: f64,
+    pub fills: Vec<Fill>,
+}
+
+impl CloseReport {
+    pub fn new(symbol: &str, order: &TrackedOrder) -> Self {
+        CloseReport {
+            symbol: symbol.to_string(),
+            order_id: order.order_id,
+            action: order.action.clone(),
+            quantity: order.quantity,
+            status: order.status.clone(),
+            filled: order.filled,
+            average_fill_price: order.average_fill_price,
+            fills: order.fills.clone(),
+        }
+    }
+}
+
+/// Shares to trade when closing `percent` of `position`; the whole position when no percent is given.
+pub fn close_quantity(position: f64, percent: Option<f64>) -> Result<f64, String> {
+    if position == 0.0 {
+        return Err("No open position".to_string());
+    }
+    let percent = percent.unwrap_or(100.0);
+    if !(percent > 0.0 && percent <= 100.0) {
+        return Err(format!("Percent {} must be between 0 and 100", percent));
+    }
+    Ok((position.abs() * percent / 100.0).floor().max(1.0))
+}
+
+/// Builds an exit order of `quantity` shares on `action`'s side.
+pub fn exit_order(
+    action: Action,
+    quantity: f64,
+    style: ExitStyle,
+    quote: Option<f64>,
+) -> Result<Order, String> {
+    match style {
+        ExitStyle::Market => Ok(Order {
+            action,
+            order_type: "MKT".to_string(),
+            total_quantity: quantity,
+            ..Default::default()
+        }),
+        ExitStyle::MarketableLimit => {
+            let quote = quote.ok_or("No quote to price a marketable limit")?;
+            let offset = quote * MARKETABLE_LIMIT_OFFSET_PERCENT / 100.0;
+            let limit = match action {
+                Action::Buy => quote + offset,
+                _ => quote - offset,
+            };
+            Ok(Order {
+                action,
+                order_type: "LMT".to_string(),
+                total_quantity: quantity,
+                limit_price: Some(round_to_tick(limit, 0.01)),
+                tif: TimeInForce::ImmediateOrCancel,
+                ..Default::default()
+            })
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use ibapi::orders::OrderState;
+use serde::Serialize;
+
+/// Margin and commission impact IBKR reports for a what-if order.
+#[derive(Serialize, Clone, Debug)]
+pub struct OrderPreview {
+    pub initial_margin_change: Option<f64>,
+    pub maintenance_margin_change: Option<f64>,
+    pub initial_margin_after: Option<f64>,
+    pub maintenance_margin_after: Option<f64>,
+    pub equity_with_loan_after: Option<f64>,
+    pub commission: Option<f64>,
+    pub minimum_commission: Option<f64>,
+    pub maximum_commission: Option<f64>,
+    pub commission_currency: String,
+    pub warning_text: String,
+}
+
+impl From<&OrderState> for OrderPreview {
+    fn from(state: &OrderState) -> Self {
+        OrderPreview {
+            initial_margin_change: state.initial_margin_change,
+            maintenance_margin_change: state.maintenance_margin_change,
+            initial_margin_after: state.initial_margin_after,
+            maintenance_margin_after: state.maintenance_margin_after,
+            equity_with_loan_after: state.equity_with_loan_after,
+            commission: state.commission,
+            minimum_commission: state.minimum_commission,
+            maximum_commission: state.maximum_commission,
+            commission_currency: state.commission_currency.clone(),
+            warning_text: state.warning_text.clone(),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use ibapi::{
+    contracts::tick_types::TickType, market_data::realtime::TickTypes, subscriptions::Subscription,
+};
+use serde::Serialize;
+use tokio::sync::{RwLock, broadcast};
+
+use crate::{order_book, replay};
+
+/// Latest streamed top of book and session stats for one symbol.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Quote {
+    pub symbol: String,
+    pub bid: Option<f64>,
+    pub ask: Option<f64>,
+    pub last: Option<f64>,
+    pub high: Option<f64>,
+    pub low: Option<f64>,
+    pub volume: Option<f64>,
+    pub updated_at: String,
+}
+
+impl Quote {
+    pub fn spread(&self) -> Option<f64> {
+        Some(self.ask? - self.bid?)
+    }
+
+    fn apply_price(&mut self, tick_type: &TickType, price: f64) {
+        // IBKR sends -1 when a side of the book is empty.
+        if price <= 0.0 {
+            return;
+        }
+        let field = match tick_type {
+            TickType::Bid | TickType::DelayedBid => &mut self.bid,
+            TickType::Ask | TickType::DelayedAsk => &mut self.ask,
+            TickType::Last | TickType::DelayedLast => &mut self.last,
+            TickType::High | TickType::DelayedHigh => &mut self.high,
+            TickType::Low | TickType::DelayedLow => &mut self.low,
+            _ => return,
+        };
+        *field = Some(price);
+        self.updated_at = order_book::now();
+    }
+
+    fn apply_size(&mut self, tick_type: &TickType, size: f64) {
+        if matches!(tick_type, TickType::Volume | TickType::DelayedVolume) {
+            self.volume = Some(size);
+            self.updated_at = order_book::now();
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    /// Quotes for every streamed symbol; a symbol is present while its stream is open.
+    pub(crate) static ref QUOTES: RwLock<HashMap<String, Quote>> = RwLock::new(HashMap::new());
+    /// Tells every open stream to cancel its subscription.
+    static ref CANCEL: broadcast::Sender<()> = broadcast::channel(1).0;
+}
+
+pub async fn get(symbol: &str) -> Option<Quote> {
+    QUOTES.read().await.get(symbol).cloned()
+}
+
+/// Drains a market data stream into the quote cache until IBKR closes it, recording
+/// each update while a recording is running.
+pub async fn stream(symbol: String, mut subscription: Subscription<TickTypes>) {
+    let mut cancel = CANCEL.subscribe();
+    loop {
+        let tick = tokio::select! {
+            tick = subscription.next() => match tick {
+                Some(tick) => tick,
+                None => break,
+            },
+            _ = cancel.recv() => {
+                subscription.cancel().await;
+                return;
+            }
+        };
+        let updated = {
+            let mut quotes = QUOTES.write().await;
+            let Some(quote) = quotes.get_mut(&symbol) else {
+                // Dropped from the cache, so stop streaming.
+                return;
+            };
+            match tick {
+                Ok(TickTypes::Price(tick)) => quote.apply_price(&tick.tick_type, tick.price),
+                Ok(TickTypes::Size(tick)) => quote.apply_size(&tick.tick_type, tick.size),
+                Ok(TickTypes::PriceSize(tick)) => {
+                    quote.apply_price(&tick.price_tick_type, tick.price);
+                    quote.apply_size(&tick.size_tick_type, tick.size);
+                }
+                Ok(_) => continue,
+                Err(e) => {
+                    println!("Error streaming quotes for {}: {:?}", symbol, e);
+                    break;
+                }
+            }
+            quote.clone()
+        };
+        replay::record(&updated).await;
+    }
+    QUOTES.write().await.remove(&symbol);
+}
+
+/// Cancels every market data stream and empties the cache; returns how many were open.
+pub async fn cancel_all() -> usize {
+    let mut quotes = QUOTES.write().await;
+    let _ = CANCEL.send(());
+    let open = quotes.len();
+    quotes.clear();
+    open
+}
@@ -0,0 +1,609 @@
+//! Recording the quotes that flow through the market data path, and playing them back.
+//!
+//! Played-back ticks are written into the quote cache, so the endpoints, triggers and
+//! the simulator see them exactly as they would see a live stream.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Component, Path};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use tokio::sync::{Mutex, broadcast};
+
+use crate::config::CONFIG;
+use crate::quotes::{QUOTES, Quote};
+
+/// Header of a replay CSV file.
+const CSV_HEADER: &str = "time,symbol,bid,ask,last,volume";
+/// First bytes of a binary replay file, followed by a one-byte version.
+const BINARY_MAGIC: &[u8] = b"IBKRPLAY";
+const BINARY_VERSION: u8 = 1;
+/// Replayed quotes buffered for subscribers that fall behind.
+const TICK_CHANNEL_CAPACITY: usize = 4096;
+
+/// One recorded quote update. Missing sides are left as they were.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplayTick {
+    pub time: OffsetDateTime,
+    pub symbol: String,
+    pub bid: Option<f64>,
+    pub ask: Option<f64>,
+    pub last: Option<f64>,
+    pub volume: Option<f64>,
+}
+
+/// How a recording is stored on disk.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplayFormat {
+    /// `time,symbol,bid,ask,last,volume` rows with RFC 3339 times.
+    Csv,
+    /// Little-endian records: unix nanos, symbol, a presence mask and the present prices.
+    Binary,
+}
+
+impl ReplayFormat {
+    /// CSV for `.csv` files, binary for anything else.
+    pub fn for_path(path: &str) -> Self {
+        if path.to_lowercase().ends_with(".csv") {
+            ReplayFormat::Csv
+        } else {
+            ReplayFormat::Binary
+        }
+    }
+}
+
+/// Parses `time,symbol,bid,ask,last,volume` rows with RFC 3339 times, sorted by time.
+pub fn parse_csv(contents: &str) -> Result<Vec<ReplayTick>, String> {
+    let mut ticks = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line == CSV_HEADER {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        if fields.len() != 6 {
+            return Err(format!(
+                "Line {}: expected 6 fields, got {}",
+                i + 1,
+                fields.len()
+            ));
+        }
+        let number = |field: &str| -> Result<Option<f64>, String> {
+            if field.is_empty() {
+                return Ok(None);
+            }
+            field
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("Line {}: {} is not a number", i + 1, field))
+        };
+        ticks.push(ReplayTick {
+            time: OffsetDateTime::parse(fields[0], &Rfc3339)
+                .map_err(|e| format!("Line {}: bad time {}: {}", i + 1, fields[0], e))?,
+            symbol: fields[1].to_string(),
+            bid: number(fields[2])?,
+            ask: number(fields[3])?,
+            last: number(fields[4])?,
+            volume: number(fields[5])?,
+        });
+    }
+    ticks.sort_by_key(|t| t.time);
+    Ok(ticks)
+}
+
+/// Parses a binary recording, sorted by time.
+pub fn parse_binary(bytes: &[u8]) -> Result<Vec<ReplayTick>, String> {
+    let mut rest = bytes
+        .strip_prefix(BINARY_MAGIC)
+        .ok_or("Not a binary replay file")?;
+    let (&version, body) = rest.split_first().ok_or("Truncated replay header")?;
+    if version != BINARY_VERSION {
+        return Err(format!("Unsupported replay file version {}", version));
+    }
+    rest = body;
+
+    let mut ticks = Vec::new();
+    while !rest.is_empty() {
+        let at = bytes.len() - rest.len();
+        let mut take = |n: usize| -> Result<&[u8], String> {
+            if rest.len() < n {
+                return Err(format!("Truncated record at byte {}", at));
+            }
+            let (head, tail) = rest.split_at(n);
+            rest = tail;
+            Ok(head)
+        };
+        let nanos = i64::from_le_bytes(take(8)?.try_into().unwrap());
+        let symbol_len = take(1)?[0] as usize;
+        let symbol = String::from_utf8(take(symbol_len)?.to_vec())
+            .map_err(|_| format!("Bad symbol at byte {}", at))?;
+        let mask = take(1)?[0];
+        let mut fields = [None; 4];
+        for (bit, field) in fields.iter_mut().enumerate() {
+            if mask & (1 << bit) != 0 {
+                *field = Some(f64::from_le_bytes(take(8)?.try_into().unwrap()));
+            }
+        }
+        let [bid, ask, last, volume] = fields;
+        ticks.push(ReplayTick {
+            time: OffsetDateTime::from_unix_timestamp_nanos(nanos as i128)
+                .map_err(|e| format!("Bad time at byte {}: {}", at, e))?,
+            symbol,
+            bid,
+            ask,
+            last,
+            volume,
+        });
+    }
+    ticks.sort_by_key(|t| t.time);
+    Ok(ticks)
+}
+
+/// The path of the replay file `name` inside the configured `replay_dir`. Names may have
+/// subdirectories but cannot be absolute or climb out with `..`.
+pub fn resolve(name: &str) -> Result<String, String> {
+    within(&CONFIG.replay_dir, name)
+}
+
+fn within(dir: &str, name: &str) -> Result<String, String> {
+    let relative = Path::new(name);
+    let plain = relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if name.is_empty() || !plain {
+        return Err(format!(
+            "{} is not a file name inside the replay directory",
+            name
+        ));
+    }
+    Ok(Path::new(dir).join(relative).to_string_lossy().to_string())
+}
+
+/// Loads a CSV or binary recording, telling them apart by the binary header.
+pub fn load(path: &str) -> Result<Vec<ReplayTick>, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Error reading {}: {}", path, e))?;
+    if bytes.starts_with(BINARY_MAGIC) {
+        return parse_binary(&bytes);
+    }
+    let contents = String::from_utf8(bytes).map_err(|_| format!("{} is not CSV", path))?;
+    parse_csv(&contents)
+}
+
+impl ReplayTick {
+    /// The tick for a quote as it stands at `time`.
+    pub fn from_quote(quote: &Quote, time: OffsetDateTime) -> Self {
+        ReplayTick {
+            time,
+            symbol: quote.symbol.clone(),
+            bid: quote.bid,
+            ask: quote.ask,
+            last: quote.last,
+            volume: quote.volume,
+        }
+    }
+
+    /// Folds the tick into `quote`, tracking the session high and low from last prices.
+    pub fn apply_to(&self, quote: &mut Quote) {
+        quote.bid = self.bid.or(quote.bid);
+        quote.ask = self.ask.or(quote.ask);
+        quote.volume = self.volume.or(quote.volume);
+        if let Some(last) = self.last {
+            quote.last = Some(last);
+            quote.high = Some(quote.high.map_or(last, |h| h.max(last)));
+            quote.low = Some(quote.low.map_or(last, |l| l.min(last)));
+        }
+        quote.updated_at = self.time.format(&Rfc3339).unwrap_or_default();
+    }
+
+    fn write_csv(&self, out: &mut impl Write) -> std::io::Result<()> {
+        let field = |value: Option<f64>| value.map(|v| v.to_string()).unwrap_or_default();
+        writeln!(
+            out,
+            "{},{},{},{},{},{}",
+            self.time.format(&Rfc3339).unwrap_or_default(),
+            self.symbol,
+            field(self.bid),
+            field(self.ask),
+            field(self.last),
+            field(self.volume)
+        )
+    }
+
+    fn write_binary(&self, out: &mut impl Write) -> std::io::Result<()> {
+        let symbol = self.symbol.as_bytes();
+        let symbol = &symbol[..symbol.len().min(u8::MAX as usize)];
+        out.write_all(&(self.time.unix_timestamp_nanos() as i64).to_le_bytes())?;
+        out.write_all(&[symbol.len() as u8])?;
+        out.write_all(symbol)?;
+        let fields = [self.bid, self.ask, self.last, self.volume];
+        let mask = fields
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| f.is_some())
+            .fold(0u8, |mask, (bit, _)| mask | (1 << bit));
+        out.write_all(&[mask])?;
+        for value in fields.into_iter().flatten() {
+            out.write_all(&value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the tick into the quote cache and returns the updated quote.
+pub async fn apply(tick: &ReplayTick) -> Quote {
+    let mut quotes = QUOTES.write().await;
+    let quote = quotes.entry(tick.symbol.clone()).or_insert_with(|| Quote {
+        symbol: tick.symbol.clone(),
+        ..Default::default()
+    });
+    tick.apply_to(quote);
+    quote.clone()
+}
+
+/// A recording in progress.
+struct Recorder {
+    path: String,
+    format: ReplayFormat,
+    out: BufWriter<File>,
+    ticks: usize,
+}
+
+impl Recorder {
+    fn write(&mut self, tick: &ReplayTick) -> std::io::Result<()> {
+        match self.format {
+            ReplayFormat::Csv => tick.write_csv(&mut self.out)?,
+            ReplayFormat::Binary => tick.write_binary(&mut self.out)?,
+        }
+        self.ticks += 1;
+        Ok(())
+    }
+
+    fn status(&self) -> RecordingStatus {
+        RecordingStatus {
+            path: self.path.clone(),
+            format: self.format,
+            ticks: self.ticks,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct RecordingStatus {
+    pub path: String,
+    pub format: ReplayFormat,
+    /// Quote updates written so far.
+    pub ticks: usize,
+}
+
+/// A recording being played back.
+struct Playback {
+    file: String,
+    ticks: Arc<Vec<ReplayTick>>,
+    /// Index of the next tick to play.
+    position: usize,
+    /// Multiple of recorded time, or `None` when stepping by hand.
+    speed: Option<f64>,
+}
+
+#[derive(Default)]
+struct Player {
+    playback: Option<Playback>,
+    /// Bumped whenever playback is started, re-paced or stopped, so a stale runner exits.
+    generation: u64,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct PlaybackStatus {
+    pub file: String,
+    pub position: usize,
+    pub total: usize,
+    /// Multiple of recorded time; absent while stepping by hand.
+    pub speed: Option<f64>,
+    /// Recorded time of the last tick played.
+    pub replay_time: Option<String>,
+    pub finished: bool,
+}
+
+impl Playback {
+    fn status(&self) -> PlaybackStatus {
+        PlaybackStatus {
+            file: self.file.clone(),
+            position: self.position,
+            total: self.ticks.len(),
+            speed: self.speed,
+            replay_time: self
+                .position
+                .checked_sub(1)
+                .and_then(|i| self.ticks[i].time.format(&Rfc3339).ok()),
+            finished: self.position >= self.ticks.len(),
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+    static ref PLAYER: Mutex<Player> = Mutex::new(Player::default());
+    static ref TICKS: broadcast::Sender<Quote> = broadcast::channel(TICK_CHANNEL_CAPACITY).0;
+}
+
+/// Starts writing every quote update to `name` in the replay directory, replacing the file.
+pub async fn start_recording(
+    name: &str,
+    format: Option<ReplayFormat>,
+) -> Result<RecordingStatus, String> {
+    let path = resolve(name)?;
+    let path = path.as_str();
+    let mut recorder = RECORDER.lock().await;
+    if let Some(current) = recorder.as_ref() {
+        return Err(format!("Already recording to {}", current.path));
+    }
+    let format = format.unwrap_or_else(|| ReplayFormat::for_path(path));
+    if let Some(dir) = Path::new(path).parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Error creating {}: {}", dir.display(), e))?;
+    }
+    let file = File::create(path).map_err(|e| format!("Error creating {}: {}", path, e))?;
+    let mut out = BufWriter::new(file);
+    match format {
+        ReplayFormat::Csv => writeln!(out, "{}", CSV_HEADER),
+        ReplayFormat::Binary => out
+            .write_all(BINARY_MAGIC)
+            .and_then(|_| out.write_all(&[BINARY_VERSION])),
+    }
+    .map_err(|e| format!("Error writing {}: {}", path, e))?;
+    let started = Recorder {
+        path: path.to_string(),
+        format,
+        out,
+        ticks: 0,
+    };
+    let status = started.status();
+    *recorder = Some(started);
+    Ok(status)
+}
+
+pub async fn stop_recording() -> Result<RecordingStatus, String> {
+    let mut recorder = RECORDER.lock().await.take().ok_or("Not recording")?;
+    recorder
+        .out
+        .flush()
+        .map_err(|e| format!("Error writing {}: {}", recorder.path, e))?;
+    Ok(recorder.status())
+}
+
+pub async fn recording() -> Option<RecordingStatus> {
+    RECORDER.lock().await.as_ref().map(Recorder::status)
+}
+
+/// Appends the quote to the recording, if one is running. A failed write ends the recording.
+pub async fn record(quote: &Quote) {
+    let mut recorder = RECORDER.lock().await;
+    let Some(current) = recorder.as_mut() else {
+        return;
+    };
+    if let Err(e) = current.write(&ReplayTick::from_quote(quote, OffsetDateTime::now_utc())) {
+        println!("Error recording quotes to {}: {:?}", current.path, e);
+        *recorder = None;
+    }
+}
+
+/// Quotes as they are played back, for backends that match orders against them.
+pub fn subscribe() -> broadcast::Receiver<Quote> {
+    TICKS.subscribe()
+}
+
+async fn play(tick: &ReplayTick) {
+    let quote = apply(tick).await;
+    let _ = TICKS.send(quote);
+}
+
+/// Replaces any running playback with `ticks`, paced at `speed` times recorded time or
+/// stepped by hand when `speed` is `None`.
+pub async fn start_playback(
+    file: &str,
+    ticks: Arc<Vec<ReplayTick>>,
+    speed: Option<f64>,
+) -> Result<PlaybackStatus, String> {
+    validate_speed(speed)?;
+    let mut player = PLAYER.lock().await;
+    player.generation += 1;
+    let playback = Playback {
+        file: file.to_string(),
+        ticks,
+        position: 0,
+        speed,
+    };
+    let status = playback.status();
+    player.playback = Some(playback);
+    if speed.is_some() {
+        tokio::spawn(run(player.generation));
+    }
+    Ok(status)
+}
+
+/// Changes the pace of the running playback; `None` pauses it for stepping.
+pub async fn set_speed(speed: Option<f64>) -> Result<PlaybackStatus, String> {
+    validate_speed(speed)?;
+    let mut player = PLAYER.lock().await;
+    player.generation += 1;
+    let generation = player.generation;
+    let playback = player.playback.as_mut().ok_or("No replay loaded")?;
+    playback.speed = speed;
+    if speed.is_some() {
+        tokio::spawn(run(generation));
+    }
+    Ok(playback.status())
+}
+
+/// Plays the next `count` ticks at once. Only allowed while paused.
+pub async fn step(count: usize) -> Result<PlaybackStatus, String> {
+    let mut player = PLAYER.lock().await;
+    let playback = player.playback.as_mut().ok_or("No replay loaded")?;
+    if playback.speed.is_some() {
+        return Err("Pause the replay before stepping".to_string());
+    }
+    let end = (playback.position + count).min(playback.ticks.len());
+    for tick in &playback.ticks[playback.position..end] {
+        play(tick).await;
+    }
+    playback.position = end;
+    Ok(playback.status())
+}
+
+pub async fn stop_playback() -> Option<PlaybackStatus> {
+    let mut player = PLAYER.lock().await;
+    player.generation += 1;
+    player.playback.take().map(|p| p.status())
+}
+
+pub async fn playback() -> Option<PlaybackStatus> {
+    PLAYER.lock().await.playback.as_ref().map(Playback::status)
+}
+
+fn validate_speed(speed: Option<f64>) -> Result<(), String> {
+    match speed {
+        Some(speed) if !(speed > 0.0 && speed.is_finite()) => {
+            Err(format!("Speed must be above zero, got {}", speed))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Plays ticks at their recorded spacing divided by the speed, until re-paced or stopped.
+async fn run(generation: u64) {
+    loop {
+        let (tick, delay) = {
+            let player = PLAYER.lock().await;
+            let Some(playback) = player.playback.as_ref() else {
+                return;
+            };
+            if player.generation != generation || playback.position >= playback.ticks.len() {
+                return;
+            }
+            let tick = playback.ticks[playback.position].clone();
+            let delay = match (playback.position.checked_sub(1), playback.speed) {
+                (Some(previous), Some(speed)) => {
+                    let gap = tick.time - playback.ticks[previous].time;
+                    gap.max(time::Duration::ZERO).unsigned_abs().div_f64(speed)
+                }
+                _ => std::time::Duration::ZERO,
+            };
+            (tick, delay)
+        };
+        tokio::time::sleep(delay).await;
+
+        let mut player = PLAYER.lock().await;
+        if player.generation != generation {
+            return;
+        }
+        if let Some(playback) = player.playback.as_mut() {
+            playback.position += 1;
+        }
+        play(&tick).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tick(seconds: i64, last: Option<f64>) -> ReplayTick {
+        ReplayTick {
+            time: OffsetDateTime::from_unix_timestamp(1_735_828_200 + seconds).unwrap(),
+            symbol: "RPLY".to_string(),
+            bid: Some(249.9),
+            ask: None,
+            last,
+            volume: Some(1200.0),
+        }
+    }
+
+    #[test]
+    fn parses_rows_in_time_order() {
+        let ticks = parse_csv(
+            "time,symbol,bid,ask,last,volume\n\
+             2025-01-02T14:30:01Z,TSLA,250.0,250.1,250.05,1000\n\
+             2025-01-02T14:30:00Z,TSLA,,,249.9,\n",
+        )
+        .unwrap();
+
+        assert_eq!(ticks.len(), 2);
+        assert_eq!(ticks[0].last, Some(249.9));
+        assert_eq!(ticks[0].bid, None);
+        assert_eq!(ticks[1].volume, Some(1000.0));
+    }
+
+    #[test]
+    fn rejects_malformed_rows() {
+        assert!(parse_csv("2025-01-02T14:30:00Z,TSLA,1,2,3").is_err());
+        assert!(parse_csv("2025-01-02T14:30:00Z,TSLA,x,2,3,4").is_err());
+        assert!(parse_csv("yesterday,TSLA,1,2,3,4").is_err());
+    }
+
+    #[test]
+    fn ticks_track_high_and_low() {
+        let mut quote = Quote::default();
+        for last in [10.0, 12.0, 9.0] {
+            tick(0, Some(last)).apply_to(&mut quote);
+        }
+
+        assert_eq!(
+            (quote.low, quote.high, quote.last),
+            (Some(9.0), Some(12.0), Some(9.0))
+        );
+    }
+
+    #[test]
+    fn csv_and_binary_records_round_trip() {
+        let ticks = vec![tick(0, Some(250.05)), tick(1, None)];
+
+        let mut csv = format!("{}\n", CSV_HEADER).into_bytes();
+        let mut binary = [BINARY_MAGIC, &[BINARY_VERSION]].concat();
+        for tick in &ticks {
+            tick.write_csv(&mut csv).unwrap();
+            tick.write_binary(&mut binary).unwrap();
+        }
+
+        assert_eq!(parse_csv(&String::from_utf8(csv).unwrap()).unwrap(), ticks);
+        assert_eq!(parse_binary(&binary).unwrap(), ticks);
+        assert!(parse_binary(&binary[..binary.len() - 3]).is_err());
+    }
+
+    #[test]
+    fn replay_names_stay_in_their_directory() {
+        assert_eq!(
+            within("replays", "2025/tsla.csv"),
+            Ok(Path::new("replays")
+                .join("2025/tsla.csv")
+                .to_string_lossy()
+                .to_string())
+        );
+        assert!(within("replays", "../watchlist.toml").is_err());
+        assert!(within("replays", "2025/../../ibkr_panel.db").is_err());
+        assert!(within("replays", "/etc/passwd").is_err());
+        assert!(within("replays", "").is_err());
+    }
+
+    #[tokio::test]
+    async fn stepping_plays_ticks_into_the_quote_cache() {
+        let ticks = Arc::new(vec![
+            tick(0, Some(250.0)),
+            tick(1, Some(251.0)),
+            tick(2, Some(249.0)),
+        ]);
+        let mut played = subscribe();
+        start_playback("test", ticks, None).await.unwrap();
+        assert!(step(2).await.is_ok());
+
+        assert_eq!(played.recv().await.unwrap().last, Some(250.0));
+        assert_eq!(played.recv().await.unwrap().last, Some(251.0));
+        let status = step(5).await.unwrap();
+        assert_eq!((status.position, status.finished), (3, true));
+        assert!(set_speed(Some(0.0)).await.is_err());
+        assert!(stop_playback().await.is_some());
+        assert!(step(1).await.is_err());
+    }
+}
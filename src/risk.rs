@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+/// Pre-trade limits from the `[risk]` section of the config. A missing limit disables its check.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct RiskConfig {
+    pub max_order_notional: Option<f64>,
+    pub max_shares: Option<i32>,
+    pub max_open_positions: Option<usize>,
+    pub max_symbol_exposure: Option<f64>,
+    /// Largest allowed distance of the entry price from the current quote, in percent.
+    pub max_quote_deviation_percent: Option<f64>,
+    /// Reject entries that would open or add to a short position.
+    pub no_short_sales: bool,
+    /// Symbols that may not be sold short even when `no_short_sales` is off.
+    pub short_sale_restricted: Vec<String>,
+    /// Reject a repeat of the same symbol, side and quantity within this many seconds.
+    pub duplicate_window_secs: Option<u64>,
+}
+
+/// Everything the gate needs to know about an order and the account at the time.
+pub struct OrderContext<'a> {
+    pub symbol: &'a str,
+    pub is_buy: bool,
+    pub qty: i32,
+    /// The limit or expected entry price, if the caller gave one.
+    pub entry_price: Option<f64>,
+    pub quote: Option<f64>,
+    /// Signed share positions by symbol.
+    pub positions: &'a HashMap<String, f64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RiskRejection {
+    NoPrice,
+    MaxOrderNotional { notional: f64, limit: f64 },
+    MaxShares { qty: i32, limit: i32 },
+    MaxOpenPositions { open: usize, limit: usize },
+    MaxSymbolExposure { exposure: f64, limit: f64 },
+    QuoteDeviation { price: f64, quote: f64, limit: f64 },
+    ShortSaleRestricted { symbol: String },
+    Duplicate { symbol: String, window_secs: u64 },
+}
+
+impl fmt::Display for RiskRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RiskRejection::NoPrice => write!(f, "no entry price or quote to value the order"),
+            RiskRejection::MaxOrderNotional { notional, limit } => {
+                write!(f, "order notional ${:.2} exceeds ${:.2}", notional, limit)
+            }
+            RiskRejection::MaxShares { qty, limit } => {
+                write!(f, "{} shares exceeds the {} share limit", qty, limit)
+            }
+            RiskRejection::MaxOpenPositions { open, limit } => {
+                write!(f, "{} open positions already, limit is {}", open, limit)
+            }
+            RiskRejection::MaxSymbolExposure { exposure, limit } => {
+                write!(
+                    f,
+                    "symbol exposure ${:.2} would exceed ${:.2}",
+                    exposure, limit
+                )
+            }
+            RiskRejection::QuoteDeviation {
+                price,
+                quote,
+                limit,
+            } => write!(
+                f,
+                "price ${:.2} is more than {}% from the quote ${:.2}",
+                price, limit, quote
+            ),
+            RiskRejection::ShortSaleRestricted { symbol } => {
+                write!(f, "short sales are not allowed for {}", symbol)
+            }
+            RiskRejection::Duplicate {
+                symbol,
+                window_secs,
+            } => write!(
+                f,
+                "same {} order was sent within the last {} seconds",
+                symbol, window_secs
+            ),
+        }
+    }
+}
+
+impl RiskConfig {
+    /// Whether the gate needs a live quote to run its checks.
+    pub fn needs_quote(&self, entry_price: Option<f64>) -> bool {
+        self.max_quote_deviation_percent.is_some() || entry_price.is_none()
+    }
+
+    /// Runs every configured check, returning the first failure.
+    pub fn check(&self, order: &OrderContext) -> Result<(), RiskRejection> {
+        let price = order
+            .entry_price
+            .or(order.quote)
+            .ok_or(RiskRejection::NoPrice)?;
+        let notional = order.qty as f64 * price;
+
+        if let Some(limit) = self.max_shares
+            && order.qty > limit
+        {
+            return Err(RiskRejection::MaxShares {
+                qty: order.qty,
+                limit,
+            });
+        }
+        if let Some(limit) = self.max_order_notional
+            && notional > limit
+        {
+            return Err(RiskRejection::MaxOrderNotional { notional, limit });
+        }
+
+        if let (Some(limit), Some(entry), Some(quote)) = (
+            self.max_quote_deviation_percent,
+            order.entry_price,
+            order.quote,
+        ) && quote > 0.0
+            && (entry - quote).abs() / quote * 100.0 > limit
+        {
+            return Err(RiskRejection::QuoteDeviation {
+                price: entry,
+                quote,
+                limit,
+            });
+        }
+
+        let current = order.positions.get(order.symbol).copied().unwrap_or(0.0);
+        let signed_qty = if order.is_buy {
+            order.qty as f64
+        } else {
+            -(order.qty as f64)
+        };
+        let after = current + signed_qty;
+
+        if let Some(limit) = self.max_open_positions {
+            let open = order.positions.values().filter(|p| **p != 0.0).count();
+            if current == 0.0 && open >= limit {
+                return Err(RiskRejection::MaxOpenPositions { open, limit });
+            }
+        }
+        if let Some(limit) = self.max_symbol_exposure {
+            let exposure = after.abs() * price;
+            if after.abs() > current.abs() && exposure > limit {
+                return Err(RiskRejection::MaxSymbolExposure { exposure, limit });
+            }
+        }
+
+        let restricted = self.no_short_sales
+            || self
+                .short_sale_restricted
+                .iter()
+                .any(|s| s.eq_ignore_ascii_case(order.symbol));
+        if restricted && !order.is_buy && after < 0.0 {
+            return Err(RiskRejection::ShortSaleRestricted {
+                symbol: order.symbol.to_string(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Runs the checks for a new entry, then records it for duplicate detection.
+    pub async fn check_entry(&self, order: &OrderContext<'_>) -> Result<(), String> {
+        self.check(order)
+            .map_err(|e| format!("Risk check failed: {}", e))?;
+        if let Some(window) = self.duplicate_window_secs {
+            RECENT_ORDERS
+                .lock()
+                .await
+                .check_and_record(
+                    order.symbol,
+                    order.is_buy,
+                    order.qty,
+                    Duration::from_secs(window),
+                )
+                .map_err(|e| format!("Risk check failed: {}", e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Recently sent orders, used for duplicate detection.
+pub struct RecentOrders {
+    sent: Vec<(String, bool, i32, Instant)>,
+}
+
+lazy_static::lazy_static! {
+    pub(crate) static ref RECENT_ORDERS: Mutex<RecentOrders> = Mutex::new(RecentOrders { sent: Vec::new() });
+}
+
+impl RecentOrders {
+    /// Records the order unless the same one was sent within `window`.
+    pub fn check_and_record(
+        &mut self,
+        symbol: &str,
+        is_buy: bool,
+        qty: i32,
+        window: Duration,
+    ) -> Result<(), RiskRejection> {
+        let now = Instant::now();
+        self.sent
+            .retain(|(_, _, _, at)| now.duration_since(*at) < window);
+        if self
+            .sent
+            .iter()
+            .any(|(s, b, q, _)| s == symbol && *b == is_buy && *q == qty)
+        {
+            return Err(RiskRejection::Duplicate {
+                symbol: symbol.to_string(),
+                window_secs: window.as_secs(),
+            });
+        }
+        self.sent.push((symbol.to_string(), is_buy, qty, now));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order<'a>(positions: &'a HashMap<String, f64>, is_buy: bool, qty: i32) -> OrderContext<'a> {
+        OrderContext {
+            symbol: "TSLA",
+            is_buy,
+            qty,
+            entry_price: Some(250.0),
+            quote: Some(250.0),
+            positions,
+        }
+    }
+
+    #[test]
+    fn passes_with_no_limits() {
+        let positions = HashMap::new();
+        assert_eq!(
+            RiskConfig::default().check(&order(&positions, true, 100)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rejects_oversized_orders() {
+        let positions = HashMap::new();
+        let config = RiskConfig {
+            max_shares: Some(50),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.check(&order(&positions, true, 100)),
+            Err(RiskRejection::MaxShares {
+                qty: 100,
+                limit: 50
+            })
+        );
+
+        let config = RiskConfig {
+            max_order_notional: Some(10_000.0),
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.check(&order(&positions, true, 100)),
+            Err(RiskRejection::MaxOrderNotional { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_fat_finger_price() {
+        let positions = HashMap::new();
+        let config = RiskConfig {
+            max_quote_deviation_percent: Some(1.0),
+            ..Default::default()
+        };
+        let mut fat = order(&positions, true, 10);
+        fat.entry_price = Some(260.0);
+        assert!(matches!(
+            config.check(&fat),
+            Err(RiskRejection::QuoteDeviation { .. })
+        ));
+    }
+
+    #[test]
+    fn allows_closing_but_not_opening_restricted_shorts() {
+        let mut positions = HashMap::new();
+        positions.insert("TSLA".to_string(), 100.0);
+        let config = RiskConfig {
+            no_short_sales: true,
+            ..Default::default()
+        };
+        assert_eq!(config.check(&order(&positions, false, 100)), Ok(()));
+        assert!(matches!(
+            config.check(&order(&positions, false, 150)),
+            Err(RiskRejection::ShortSaleRestricted { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_new_position_over_open_limit() {
+        let mut positions = HashMap::new();
+        positions.insert("NVDA".to_string(), 10.0);
+        let config = RiskConfig {
+            max_open_positions: Some(1),
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.check(&order(&positions, true, 10)),
+            Err(RiskRejection::MaxOpenPositions { open: 1, limit: 1 })
+        ));
+    }
+
+    #[test]
+    fn detects_duplicates_within_window() {
+        let mut recent = RecentOrders { sent: Vec::new() };
+        let window = Duration::from_secs(5);
+        assert!(recent.check_and_record("TSLA", true, 100, window).is_ok());
+        assert!(recent.check_and_record("TSLA", true, 100, window).is_err());
+        assert!(recent.check_and_record("TSLA", false, 100, window).is_ok());
+    }
+}
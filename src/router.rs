@@ -1,21 +1,94 @@
-use crate::connector::{CONNECTOR, ConnectorTrait};
-use axum::{Json, Router, extract::Query, routing::get, routing::post};
-use utoipa::OpenApi;
+use crate::auth::{self, Access, AuditEntry};
+use crate::broker::{Broker, OrderRequest, Position};
+use crate::config::CONFIG;
+use crate::database::DATABASE;
+use crate::executions::{ExecutionQuery, ExecutionRecord};
+use crate::journal::{self, JournalEntry, JournalQuery};
+use crate::kill_switch::{TRADING_STATUS, TradingStatus};
+use crate::ladder::DEFAULT_LADDER;
+use crate::order_book::{ORDER_BOOK, TrackedOrder};
+use crate::order_keys::{self, Claim};
+use crate::positions::{CloseReport, ExitStyle};
+use crate::preview::OrderPreview;
+use crate::quotes::Quote;
+use crate::replay::{self, PlaybackStatus, RecordingStatus, ReplayFormat};
+use crate::sizing::PositionSize;
+use crate::triggers::{self, NewTrigger, Trigger, TriggerStatus};
+use axum::{
+    Json, Router,
+    extract::{
+        Path, Query, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::header,
+    middleware,
+    response::{Html, IntoResponse, Response},
+    routing::get,
+    routing::post,
+};
+use std::sync::Arc;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityRequirement, SecurityScheme};
+use utoipa::{Modify, OpenApi};
 
-// our router
-pub fn app() -> Router {
+// our router, running against `broker`
+pub fn app<B: Broker>(broker: B) -> Router {
     Router::new()
-        .route("/connect", post(connect))
-        .route("/is_connected", get(is_connected))
-        .route("/disconnect", post(disconnect))
-        .route("/get_account_values", get(get_account_values))
-        .route("/get_positions", get(get_positions))
-        .route("/market_data", get(get_market_data))
-        .route("/get_lod_hod", get(get_lod_hod))
-        .route("/order", post(order))
+        .route("/", get(panel))
+        .route("/panel/settings", get(panel_settings))
+        .route("/connect", post(connect::<B>))
+        .route("/is_connected", get(is_connected::<B>))
+        .route("/disconnect", post(disconnect::<B>))
+        .route("/get_account_values", get(get_account_values::<B>))
+        .route("/get_positions", get(get_positions::<B>))
+        .route("/market_data", get(get_market_data::<B>))
+        .route("/get_lod_hod", get(get_lod_hod::<B>))
+        .route("/quotes", get(get_quotes::<B>))
+        .route("/size", get(size::<B>))
+        .route("/order", post(order::<B>))
+        .route("/order/preview", post(preview_order::<B>))
+        .route("/orders", get(get_orders::<B>))
+        .route("/orders/ws", get(orders_ws))
+        .route("/positions/close_all", post(close_all_positions::<B>))
+        .route("/positions/{symbol}/close", post(close_position::<B>))
+        .route("/positions/{symbol}/reverse", post(reverse_position::<B>))
+        .route("/kill_switch", get(trading_status).post(kill_switch::<B>))
+        .route("/kill_switch/reset", post(reset_kill_switch))
+        .route("/orders/cancel_all", post(cancel_all_orders::<B>))
+        .route("/executions", get(get_executions::<B>))
+        .route("/triggers", get(list_triggers).post(create_trigger))
+        .route(
+            "/triggers/{id}",
+            get(get_trigger).put(update_trigger).delete(cancel_trigger),
+        )
+        .route("/replay", get(get_playback))
+        .route("/replay/start", post(start_playback))
+        .route("/replay/speed", post(set_replay_speed))
+        .route("/replay/step", post(step_replay))
+        .route("/replay/stop", post(stop_playback))
+        .route("/replay/recording", get(get_recording))
+        .route("/replay/recording/start", post(start_recording))
+        .route("/replay/recording/stop", post(stop_recording))
+        .route("/audit", get(get_audit_log))
+        .route("/journal", get(get_journal))
+        .route("/journal/export", get(export_journal))
+        .route(
+            "/journal/{id}",
+            get(get_journal_entry).patch(set_journal_notes),
+        )
+        .route(
+            "/orders/{id}",
+            get(get_order::<B>)
+                .delete(cancel_order::<B>)
+                .patch(modify_order::<B>),
+        )
+        .with_state(broker)
+        .layer(middleware::from_fn_with_state(
+            Arc::new(Access::new(CONFIG.auth.keys.clone(), &CONFIG.server)),
+            auth::authorize,
+        ))
 }
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize)]
 pub struct ConnectQuery {
@@ -37,9 +110,11 @@ pub struct ConnectQuery {
         (status = 200, description = "Connect to IBKR", body = String)
     )
 )]
-async fn connect(Query(query): Query<ConnectQuery>) -> Json<bool> {
-    let ib = CONNECTOR.read().await;
-    let result = ib
+async fn connect<B: Broker>(
+    State(broker): State<B>,
+    Query(query): Query<ConnectQuery>,
+) -> Json<bool> {
+    let result = broker
         .connect(&query.address, query.port, query.client_id)
         .await;
     Json(result)
@@ -53,9 +128,8 @@ async fn connect(Query(query): Query<ConnectQuery>) -> Json<bool> {
         (status = 200, description = "Check if connected to IBKR")
     )
 )]
-async fn is_connected() -> Json<bool> {
-    let ib = CONNECTOR.read().await;
-    let result = ib.is_connected();
+async fn is_connected<B: Broker>(State(broker): State<B>) -> Json<bool> {
+    let result = broker.is_connected().await;
     Json(result)
 }
 
@@ -67,9 +141,8 @@ async fn is_connected() -> Json<bool> {
         (status = 200, description = "Disconnect from IBKR")
     )
 )]
-async fn disconnect() {
-    let mut ib = CONNECTOR.write().await;
-    ib.disconnect();
+async fn disconnect<B: Broker>(State(broker): State<B>) {
+    broker.disconnect().await;
 }
 
 #[utoipa::path(
@@ -80,9 +153,8 @@ async fn disconnect() {
         (status = 200, description = "Get account values from IBKR")
     )
 )]
-async fn get_account_values() -> Json<Option<Vec<String>>> {
-    let ib = CONNECTOR.read().await;
-    let account_values = ib.get_account_values().await;
+async fn get_account_values<B: Broker>(State(broker): State<B>) -> Json<Option<Vec<String>>> {
+    let account_values = broker.account_values().await;
     Json(account_values)
 }
 
@@ -94,9 +166,8 @@ async fn get_account_values() -> Json<Option<Vec<String>>> {
         (status = 200, description = "Get positions from IBKR")
     )
 )]
-async fn get_positions() -> Json<Option<Vec<String>>> {
-    let ib = CONNECTOR.read().await;
-    let positions = ib.get_positions().await;
+async fn get_positions<B: Broker>(State(broker): State<B>) -> Json<Option<Vec<Position>>> {
+    let positions = broker.positions().await;
     Json(positions)
 }
 
@@ -116,9 +187,11 @@ pub struct MarketDataQuery {
         (status = 200, description = "Get market data from IBKR")
     )
 )]
-async fn get_market_data(Query(query): Query<MarketDataQuery>) -> Json<Option<f64>> {
-    let ib = CONNECTOR.read().await;
-    let market_data = ib.market_data(&query.ticker).await;
+async fn get_market_data<B: Broker>(
+    State(broker): State<B>,
+    Query(query): Query<MarketDataQuery>,
+) -> Json<Option<f64>> {
+    let market_data = broker.last_price(&query.ticker).await;
     Json(market_data)
 }
 
@@ -133,35 +206,1038 @@ async fn get_market_data(Query(query): Query<MarketDataQuery>) -> Json<Option<f6
         (status = 200, description = "Get lowest and highest of the day from IBKR")
     )
 )]
-async fn get_lod_hod(Query(query): Query<MarketDataQuery>) -> Json<(f64, f64)> {
-    let ib = CONNECTOR.read().await;
-    let lod_hod = ib.get_lod_hod(&query.ticker).await;
+async fn get_lod_hod<B: Broker>(
+    State(broker): State<B>,
+    Query(query): Query<MarketDataQuery>,
+) -> Json<(f64, f64)> {
+    let lod_hod = broker.day_range(&query.ticker).await;
     Json(lod_hod)
 }
 
+#[derive(Deserialize)]
+pub struct QuotesQuery {
+    pub symbols: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/quotes",
+    params (
+        ("symbols" = String, Query, description = "Comma-separated ticker symbols"),
+    ),
+    tags = ["Data"],
+    responses(
+        (status = 200, description = "Get the streamed quote of each symbol, starting streams as needed")
+    )
+)]
+async fn get_quotes<B: Broker>(
+    State(broker): State<B>,
+    Query(query): Query<QuotesQuery>,
+) -> Json<Vec<Quote>> {
+    let mut quotes = Vec::new();
+    for symbol in query.symbols.split(',').filter(|s| !s.is_empty()) {
+        if let Ok(quote) = broker.quote(&symbol.to_uppercase()).await {
+            quotes.push(quote);
+        }
+    }
+    Json(quotes)
+}
+
+#[derive(Deserialize)]
+pub struct SizeQuery {
+    pub ticker: String,
+    pub entry_price: Option<f64>,
+    pub stop_price: f64,
+    pub risk_percent: Option<f64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/size",
+    params (
+        ("ticker" = String, Query, description = "The ticker symbol to size"),
+        ("entry_price" = Option<f64>, Query, description = "Entry price; the current quote is used when omitted"),
+        ("stop_price" = f64, Query, description = "Stop price for the position"),
+        ("risk_percent" = Option<f64>, Query, description = "Percent of equity to risk; defaults to risk_percent from the config"),
+    ),
+    tags = ["Data"],
+    responses(
+        (status = 200, description = "Get the share quantity that risks the given percent of equity")
+    )
+)]
+async fn size<B: Broker>(
+    State(broker): State<B>,
+    Query(query): Query<SizeQuery>,
+) -> Json<Result<PositionSize, String>> {
+    let size = broker
+        .size_position(
+            &query.ticker,
+            query.entry_price,
+            query.stop_price,
+            query.risk_percent,
+        )
+        .await;
+    Json(size)
+}
+
 #[utoipa::path(
     post,
     path = "/order",
     params (
         ("ticker" = String, Query, description = "The ticker symbol for the market data"),
-        ("qty" = i32, Query, description = "Quantity of shares to order"),
+        ("qty" = Option<i32>, Query, description = "Quantity of shares to order; sized from risk_percent when omitted"),
         ("stop_price" = f64, Query, description = "Stop price for the order"),
         ("entry_price" = f64, Query, description = "Entry price for the order"),
         ("action" = String, Query, description = "Action type: BUY or SELL"),
+        ("ladder" = Option<String>, Query, description = "Scale-out ladder preset from the config"),
+        ("risk_percent" = Option<f64>, Query, description = "Percent of equity to risk when qty is omitted"),
+        ("client_order_key" = Option<String>, Query, description = "Idempotency key; a repeat within the trading day returns the original result"),
     ),
     tags = ["Data"],
     responses(
         (status = 200, description = "Get market data from IBKR")
     )
 )]
-async fn order(Query(query): Query<(String, i32, f64, f64, String)>) -> Json<(bool, String)> {
-    let ib = CONNECTOR.read().await;
-    let market_data = ib
-        .submit_order(&query.0, query.1, query.2, query.3, query.4)
-        .await;
+async fn order<B: Broker>(
+    State(broker): State<B>,
+    Query(query): Query<OrderRequest>,
+) -> Json<(bool, String)> {
+    let market_data = place_order(&broker, &query).await;
     Json(market_data)
 }
 
+/// Sizes the request if needed and submits it; shared with the trigger engine.
+///
+/// A request with a `client_order_key` already used today gets the original result back.
+pub async fn place_order<B: Broker>(broker: &B, request: &OrderRequest) -> (bool, String) {
+    let Some(key) = request.client_order_key.as_deref() else {
+        return submit(broker, request).await;
+    };
+    let claim_key = || {
+        let conn = DATABASE.lock().unwrap();
+        order_keys::claim(&conn, key, &order_keys::trading_day())
+    };
+    let mut claim = claim_key();
+    if let Ok(Claim::Stale) = claim {
+        match find_by_order_ref(broker, key).await {
+            Ok(Some((order_id, status))) => {
+                let message = format!(
+                    "Order with client_order_key {} was already placed as order {} ({})",
+                    key, order_id, status
+                );
+                let conn = DATABASE.lock().unwrap();
+                if let Err(e) = order_keys::complete(&conn, key, Some(order_id), true, &message) {
+                    println!("Error saving client_order_key {}: {}", key, e);
+                }
+                return (true, message);
+            }
+            // Nothing reached IBKR under this key, so the abandoned claim is released.
+            Ok(None) => {
+                let released = {
+                    let conn = DATABASE.lock().unwrap();
+                    order_keys::complete(&conn, key, None, false, "")
+                };
+                claim = released.and_then(|_| claim_key());
+            }
+            Err(e) => {
+                return (
+                    false,
+                    format!(
+                        "Order with client_order_key {} may have been placed: {}",
+                        key, e
+                    ),
+                );
+            }
+        }
+    }
+    match claim {
+        Ok(Claim::New) => {}
+        Ok(Claim::Duplicate { ok, message }) => return (ok, message),
+        Ok(Claim::InProgress | Claim::Stale) => {
+            return (
+                false,
+                format!("Order with client_order_key {} is still being placed", key),
+            );
+        }
+        Err(e) => return (false, format!("Error checking client_order_key: {}", e)),
+    }
+
+    let (ok, message) = submit(broker, request).await;
+    let order_id = ORDER_BOOK
+        .read()
+        .await
+        .list()
+        .into_iter()
+        .find(|o| o.order.order_ref == key)
+        .map(|o| o.order_id);
+    let conn = DATABASE.lock().unwrap();
+    if let Err(e) = order_keys::complete(&conn, key, order_id, ok, &message) {
+        println!("Error saving client_order_key {}: {}", key, e);
+    }
+    (ok, message)
+}
+
+/// Looks for an order sent with `order_ref` among IBKR's open orders and today's executions,
+/// returning its id and status.
+async fn find_by_order_ref<B: Broker>(
+    broker: &B,
+    order_ref: &str,
+) -> Result<Option<(i32, String)>, String> {
+    if !broker.refresh_orders(true).await {
+        return Err("could not read open orders".to_string());
+    }
+    let open = ORDER_BOOK
+        .read()
+        .await
+        .list()
+        .into_iter()
+        .find(|o| o.order.order_ref == order_ref);
+    if let Some(order) = open {
+        return Ok(Some((order.order_id, order.status)));
+    }
+    let executions = broker.executions(&ExecutionQuery::default()).await?;
+    Ok(executions
+        .into_iter()
+        .find(|e| e.order_ref == order_ref)
+        .map(|e| (e.order_id, "executed".to_string())))
+}
+
+async fn submit<B: Broker>(broker: &B, request: &OrderRequest) -> (bool, String) {
+    let qty = match order_qty(broker, request).await {
+        Ok(qty) => qty,
+        Err(e) => return (false, e),
+    };
+    broker.submit_order(request, qty).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/order/preview",
+    params (
+        ("ticker" = String, Query, description = "The ticker symbol for the market data"),
+        ("qty" = Option<i32>, Query, description = "Quantity of shares to order; sized from risk_percent when omitted"),
+        ("stop_price" = f64, Query, description = "Stop price for the order"),
+        ("entry_price" = f64, Query, description = "Entry price for the order"),
+        ("action" = String, Query, description = "Action type: BUY or SELL"),
+        ("ladder" = Option<String>, Query, description = "Scale-out ladder preset from the config"),
+        ("risk_percent" = Option<f64>, Query, description = "Percent of equity to risk when qty is omitted"),
+    ),
+    tags = ["Data"],
+    responses(
+        (status = 200, description = "Preview margin impact and commission of an order without transmitting it")
+    )
+)]
+async fn preview_order<B: Broker>(
+    State(broker): State<B>,
+    Query(query): Query<OrderRequest>,
+) -> Json<Result<OrderPreview, String>> {
+    let qty = match order_qty(&broker, &query).await {
+        Ok(qty) => qty,
+        Err(e) => return Json(Err(e)),
+    };
+    let preview = broker
+        .preview_order(&query.ticker, qty, &query.action)
+        .await;
+    Json(preview)
+}
+
+/// The requested quantity, or a risk-based size when the request leaves it out.
+pub(crate) async fn order_qty<B: Broker>(broker: &B, query: &OrderRequest) -> Result<i32, String> {
+    match query.qty {
+        Some(qty) => Ok(qty),
+        None => {
+            let entry_price = Some(query.entry_price).filter(|p| *p > 0.0);
+            broker
+                .size_position(
+                    &query.ticker,
+                    entry_price,
+                    query.stop_price,
+                    query.risk_percent,
+                )
+                .await
+                .map(|size| size.qty)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct OrdersQuery {
+    pub refresh: Option<bool>,
+    pub all: Option<bool>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/orders",
+    params (
+        ("refresh" = Option<bool>, Query, description = "Sync open orders from IBKR before listing"),
+        ("all" = Option<bool>, Query, description = "With refresh, include orders from every API client and TWS"),
+    ),
+    tags = ["Orders"],
+    responses(
+        (status = 200, description = "Get tracked orders with their status, fills and average price")
+    )
+)]
+async fn get_orders<B: Broker>(
+    State(broker): State<B>,
+    Query(query): Query<OrdersQuery>,
+) -> Json<Vec<TrackedOrder>> {
+    if query.refresh.unwrap_or(false) {
+        broker.refresh_orders(query.all.unwrap_or(false)).await;
+    }
+    let orders = ORDER_BOOK.read().await.list();
+    Json(orders)
+}
+
+#[derive(Deserialize)]
+pub struct OrderQuery {
+    pub client_id: Option<i32>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/orders/{id}",
+    params (
+        ("id" = i32, Path, description = "The IBKR order id"),
+        ("client_id" = Option<i32>, Query, description = "The client id that placed it; this connection's by default"),
+    ),
+    tags = ["Orders"],
+    responses(
+        (status = 200, description = "Get a single tracked order")
+    )
+)]
+async fn get_order<B: Broker>(
+    State(broker): State<B>,
+    Path(id): Path<i32>,
+    Query(query): Query<OrderQuery>,
+) -> Json<Option<TrackedOrder>> {
+    let client_id = match query.client_id {
+        Some(client_id) => Some(client_id),
+        None => broker.client_id().await,
+    };
+    let book = ORDER_BOOK.read().await;
+    let order = match client_id {
+        Some(client_id) => book.get(client_id, id),
+        None => book.list().into_iter().find(|o| o.order_id == id),
+    };
+    Json(order)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/orders/{id}",
+    params (
+        ("id" = i32, Path, description = "The IBKR order id"),
+    ),
+    tags = ["Orders"],
+    responses(
+        (status = 200, description = "Cancel a working order placed by this client")
+    )
+)]
+async fn cancel_order<B: Broker>(
+    State(broker): State<B>,
+    Path(id): Path<i32>,
+) -> Json<(bool, String)> {
+    let result = broker.cancel_order(id).await;
+    Json(result)
+}
+
+#[derive(Deserialize)]
+pub struct CancelAllQuery {
+    pub symbol: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/orders/cancel_all",
+    params (
+        ("symbol" = Option<String>, Query, description = "Only cancel orders for this symbol"),
+    ),
+    tags = ["Orders"],
+    responses(
+        (status = 200, description = "Cancel every working order placed by this client")
+    )
+)]
+async fn cancel_all_orders<B: Broker>(
+    State(broker): State<B>,
+    Query(query): Query<CancelAllQuery>,
+) -> Json<(bool, String)> {
+    let result = broker.cancel_all(query.symbol.as_deref()).await;
+    Json(result)
+}
+
+#[derive(Deserialize)]
+pub struct ModifyOrderQuery {
+    pub quantity: Option<f64>,
+    pub limit_price: Option<f64>,
+    pub stop_price: Option<f64>,
+}
+
+#[utoipa::path(
+    patch,
+    path = "/orders/{id}",
+    params (
+        ("id" = i32, Path, description = "The IBKR order id"),
+        ("quantity" = Option<f64>, Query, description = "New total quantity"),
+        ("limit_price" = Option<f64>, Query, description = "New limit price"),
+        ("stop_price" = Option<f64>, Query, description = "New stop price"),
+    ),
+    tags = ["Orders"],
+    responses(
+        (status = 200, description = "Amend a working order placed by this client")
+    )
+)]
+async fn modify_order<B: Broker>(
+    State(broker): State<B>,
+    Path(id): Path<i32>,
+    Query(query): Query<ModifyOrderQuery>,
+) -> Json<(bool, String)> {
+    let result = broker
+        .modify_order(id, query.quantity, query.limit_price, query.stop_price)
+        .await;
+    Json(result)
+}
+
+#[utoipa::path(
+    get,
+    path = "/orders/ws",
+    tags = ["Orders"],
+    responses(
+        (status = 101, description = "WebSocket feed of order updates as JSON")
+    )
+)]
+async fn orders_ws(ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(stream_orders)
+}
+
+async fn stream_orders(mut socket: WebSocket) {
+    let mut updates = ORDER_BOOK.read().await.subscribe();
+    loop {
+        let order = match updates.recv().await {
+            Ok(order) => order,
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+        };
+        let payload = serde_json::to_string(&order).unwrap_or_default();
+        if socket.send(Message::Text(payload.into())).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ClosePositionQuery {
+    pub percent: Option<f64>,
+    pub style: Option<ExitStyle>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/positions/{symbol}/close",
+    params (
+        ("symbol" = String, Path, description = "The ticker symbol of the position"),
+        ("percent" = Option<f64>, Query, description = "Percent of the position to close; all of it when omitted"),
+        ("style" = Option<String>, Query, description = "market (default) or marketable_limit"),
+    ),
+    tags = ["Positions"],
+    responses(
+        (status = 200, description = "Cancel the symbol's working exits and close the position")
+    )
+)]
+async fn close_position<B: Broker>(
+    State(broker): State<B>,
+    Path(symbol): Path<String>,
+    Query(query): Query<ClosePositionQuery>,
+) -> Json<Result<CloseReport, String>> {
+    let report = broker
+        .close_position(&symbol, query.percent, query.style.unwrap_or_default())
+        .await;
+    Json(report)
+}
+
+#[derive(Deserialize)]
+pub struct ExitStyleQuery {
+    pub style: Option<ExitStyle>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/positions/close_all",
+    params (
+        ("style" = Option<String>, Query, description = "market (default) or marketable_limit"),
+    ),
+    tags = ["Positions"],
+    responses(
+        (status = 200, description = "Cancel all working orders and close every position")
+    )
+)]
+async fn close_all_positions<B: Broker>(
+    State(broker): State<B>,
+    Query(query): Query<ExitStyleQuery>,
+) -> Json<Vec<Result<CloseReport, String>>> {
+    let reports = broker.close_all(query.style.unwrap_or_default()).await;
+    Json(reports)
+}
+
+#[utoipa::path(
+    post,
+    path = "/positions/{symbol}/reverse",
+    params (
+        ("symbol" = String, Path, description = "The ticker symbol of the position"),
+        ("style" = Option<String>, Query, description = "market (default) or marketable_limit"),
+    ),
+    tags = ["Positions"],
+    responses(
+        (status = 200, description = "Cancel the symbol's working exits and flip the position to the other side")
+    )
+)]
+async fn reverse_position<B: Broker>(
+    State(broker): State<B>,
+    Path(symbol): Path<String>,
+    Query(query): Query<ExitStyleQuery>,
+) -> Json<Result<CloseReport, String>> {
+    let report = broker
+        .reverse_position(&symbol, query.style.unwrap_or_default())
+        .await;
+    Json(report)
+}
+
+#[utoipa::path(
+    get,
+    path = "/kill_switch",
+    tags = ["Risk"],
+    responses(
+        (status = 200, description = "Get daily P&L, losing trades and whether entries are locked")
+    )
+)]
+async fn trading_status() -> Json<TradingStatus> {
+    let status = TRADING_STATUS.read().await.clone();
+    Json(status)
+}
+
+#[derive(Deserialize)]
+pub struct KillSwitchQuery {
+    pub flatten: Option<bool>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/kill_switch",
+    params (
+        ("flatten" = Option<bool>, Query, description = "Also close every position"),
+    ),
+    tags = ["Risk"],
+    responses(
+        (status = 200, description = "Cancel all orders and disable order entry until reset")
+    )
+)]
+async fn kill_switch<B: Broker>(
+    State(broker): State<B>,
+    Query(query): Query<KillSwitchQuery>,
+) -> Json<(bool, String)> {
+    let result = broker.kill_switch(query.flatten.unwrap_or(false)).await;
+    Json(result)
+}
+
+#[utoipa::path(
+    post,
+    path = "/kill_switch/reset",
+    tags = ["Risk"],
+    responses(
+        (status = 200, description = "Re-enable order entry")
+    )
+)]
+async fn reset_kill_switch() -> Json<TradingStatus> {
+    let mut status = TRADING_STATUS.write().await;
+    status.reset();
+    Json(status.clone())
+}
+
+#[derive(Deserialize)]
+pub struct ExecutionsQuery {
+    pub time: Option<String>,
+    pub symbol: Option<String>,
+    pub side: Option<String>,
+    pub account: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/executions",
+    params (
+        ("time" = Option<String>, Query, description = "Only executions at or after this time, yyyymmdd hh:mm:ss"),
+        ("symbol" = Option<String>, Query, description = "Only executions for this symbol"),
+        ("side" = Option<String>, Query, description = "BUY or SELL"),
+        ("account" = Option<String>, Query, description = "Only executions for this account"),
+    ),
+    tags = ["Orders"],
+    responses(
+        (status = 200, description = "Get executions with their commission reports, linked to the order and bracket that produced them")
+    )
+)]
+async fn get_executions<B: Broker>(
+    State(broker): State<B>,
+    Query(query): Query<ExecutionsQuery>,
+) -> Json<Result<Vec<ExecutionRecord>, String>> {
+    let filter = ExecutionQuery {
+        symbol: query.symbol,
+        side: query.side.map(|s| s.to_uppercase()),
+        account: query.account,
+        since: query.time,
+    };
+    let executions = broker.executions(&filter).await;
+    Json(executions)
+}
+
+#[derive(Deserialize)]
+pub struct JournalFilterQuery {
+    pub symbol: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+impl From<JournalFilterQuery> for JournalQuery {
+    fn from(query: JournalFilterQuery) -> Self {
+        JournalQuery {
+            symbol: query.symbol,
+            from: query.from,
+            to: query.to,
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/journal",
+    params (
+        ("symbol" = Option<String>, Query, description = "Only trades in this symbol"),
+        ("from" = Option<String>, Query, description = "Only trades opened on or after this date, yyyy-mm-dd"),
+        ("to" = Option<String>, Query, description = "Only trades opened on or before this date, yyyy-mm-dd"),
+    ),
+    tags = ["Journal"],
+    responses(
+        (status = 200, description = "Get journaled trades with their events, fills and R-multiple")
+    )
+)]
+async fn get_journal(
+    Query(query): Query<JournalFilterQuery>,
+) -> Json<Result<Vec<JournalEntry>, String>> {
+    let conn = DATABASE.lock().unwrap();
+    let entries = journal::query(&conn, &query.into()).map_err(|e| e.to_string());
+    Json(entries)
+}
+
+#[utoipa::path(
+    get,
+    path = "/journal/export",
+    params (
+        ("symbol" = Option<String>, Query, description = "Only trades in this symbol"),
+        ("from" = Option<String>, Query, description = "Only trades opened on or after this date, yyyy-mm-dd"),
+        ("to" = Option<String>, Query, description = "Only trades opened on or before this date, yyyy-mm-dd"),
+    ),
+    tags = ["Journal"],
+    responses(
+        (status = 200, description = "Download the journal as CSV, one row per trade")
+    )
+)]
+async fn export_journal(Query(query): Query<JournalFilterQuery>) -> Response {
+    let conn = DATABASE.lock().unwrap();
+    match journal::query(&conn, &query.into()) {
+        Ok(entries) => (
+            [
+                (header::CONTENT_TYPE, "text/csv"),
+                (
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"journal.csv\"",
+                ),
+            ],
+            journal::to_csv(&entries),
+        )
+            .into_response(),
+        Err(e) => Json(Err::<(), String>(e.to_string())).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/journal/{id}",
+    params (
+        ("id" = i64, Path, description = "The journal id of the trade"),
+    ),
+    tags = ["Journal"],
+    responses(
+        (status = 200, description = "Get a single journaled trade")
+    )
+)]
+async fn get_journal_entry(Path(id): Path<i64>) -> Json<Result<Option<JournalEntry>, String>> {
+    let conn = DATABASE.lock().unwrap();
+    let entry = journal::get(&conn, id).map_err(|e| e.to_string());
+    Json(entry)
+}
+
+#[derive(Deserialize)]
+pub struct JournalNotesQuery {
+    pub notes: String,
+}
+
+#[utoipa::path(
+    patch,
+    path = "/journal/{id}",
+    params (
+        ("id" = i64, Path, description = "The journal id of the trade"),
+        ("notes" = String, Query, description = "Notes to store with the trade, replacing any earlier ones"),
+    ),
+    tags = ["Journal"],
+    responses(
+        (status = 200, description = "Set the notes on a journaled trade")
+    )
+)]
+async fn set_journal_notes(
+    Path(id): Path<i64>,
+    Query(query): Query<JournalNotesQuery>,
+) -> Json<(bool, String)> {
+    let conn = DATABASE.lock().unwrap();
+    let result = match journal::set_notes(&conn, id, &query.notes) {
+        Ok(true) => (true, format!("Notes saved for journal entry {}", id)),
+        Ok(false) => (false, format!("Journal entry {} does not exist", id)),
+        Err(e) => (false, e.to_string()),
+    };
+    Json(result)
+}
+
+#[derive(Deserialize)]
+pub struct TriggersQuery {
+    pub status: Option<TriggerStatus>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/triggers",
+    params (
+        ("status" = Option<String>, Query, description = "Only triggers in this status: pending, fired, failed, expired or cancelled"),
+    ),
+    tags = ["Triggers"],
+    responses(
+        (status = 200, description = "Get stored triggers")
+    )
+)]
+async fn list_triggers(Query(query): Query<TriggersQuery>) -> Json<Result<Vec<Trigger>, String>> {
+    let conn = DATABASE.lock().unwrap();
+    let triggers = triggers::list(&conn, query.status).map_err(|e| e.to_string());
+    Json(triggers)
+}
+
+#[utoipa::path(
+    post,
+    path = "/triggers",
+    request_body(
+        content = String,
+        content_type = "application/json",
+        description = r#"{"conditions": [{"condition": "breaks_hod"}, {"condition": "volume_above", "shares": 500000}], "order": {"ticker": "TSLA", "qty": 200, "stop_price": 245.0, "entry_price": 0.0, "action": "BUY"}, "expires_at": "2025-01-02T21:00:00Z"}"#
+    ),
+    tags = ["Triggers"],
+    responses(
+        (status = 200, description = "Store an order request that fires once all conditions hold")
+    )
+)]
+async fn create_trigger(Json(new): Json<NewTrigger>) -> Json<Result<Trigger, String>> {
+    if let Err(e) = new.validate() {
+        return Json(Err(e));
+    }
+    let conn = DATABASE.lock().unwrap();
+    let trigger = triggers::insert(&conn, &new).map_err(|e| e.to_string());
+    Json(trigger)
+}
+
+#[utoipa::path(
+    get,
+    path = "/triggers/{id}",
+    params (
+        ("id" = i64, Path, description = "The trigger id"),
+    ),
+    tags = ["Triggers"],
+    responses(
+        (status = 200, description = "Get a single trigger")
+    )
+)]
+async fn get_trigger(Path(id): Path<i64>) -> Json<Result<Option<Trigger>, String>> {
+    let conn = DATABASE.lock().unwrap();
+    let trigger = triggers::get(&conn, id).map_err(|e| e.to_string());
+    Json(trigger)
+}
+
+#[utoipa::path(
+    put,
+    path = "/triggers/{id}",
+    params (
+        ("id" = i64, Path, description = "The trigger id"),
+    ),
+    request_body(
+        content = String,
+        content_type = "application/json",
+        description = "The same body as POST /triggers"
+    ),
+    tags = ["Triggers"],
+    responses(
+        (status = 200, description = "Replace the conditions, order and expiry of a pending trigger")
+    )
+)]
+async fn update_trigger(Path(id): Path<i64>, Json(new): Json<NewTrigger>) -> Json<(bool, String)> {
+    if let Err(e) = new.validate() {
+        return Json((false, e));
+    }
+    let conn = DATABASE.lock().unwrap();
+    let result = match triggers::update(&conn, id, &new) {
+        Ok(true) => (true, format!("Trigger {} updated", id)),
+        Ok(false) => (false, format!("Trigger {} is not pending", id)),
+        Err(e) => (false, e.to_string()),
+    };
+    Json(result)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/triggers/{id}",
+    params (
+        ("id" = i64, Path, description = "The trigger id"),
+    ),
+    tags = ["Triggers"],
+    responses(
+        (status = 200, description = "Cancel a pending trigger")
+    )
+)]
+async fn cancel_trigger(Path(id): Path<i64>) -> Json<(bool, String)> {
+    let conn = DATABASE.lock().unwrap();
+    let cancelled = triggers::set_status(
+        &conn,
+        id,
+        TriggerStatus::Pending,
+        TriggerStatus::Cancelled,
+        None,
+    );
+    let result = match cancelled {
+        Ok(true) => (true, format!("Trigger {} cancelled", id)),
+        Ok(false) => (false, format!("Trigger {} is not pending", id)),
+        Err(e) => (false, e.to_string()),
+    };
+    Json(result)
+}
+
+#[utoipa::path(
+    get,
+    path = "/replay",
+    tags = ["Replay"],
+    responses(
+        (status = 200, description = "Get the loaded replay's position and speed")
+    )
+)]
+async fn get_playback() -> Json<Option<PlaybackStatus>> {
+    Json(replay::playback().await)
+}
+
+#[derive(Deserialize)]
+pub struct StartReplayQuery {
+    pub file: String,
+    pub speed: Option<f64>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/replay/start",
+    params (
+        ("file" = String, Query, description = "A CSV or binary recording in the server's replay directory"),
+        ("speed" = Option<f64>, Query, description = "Multiple of recorded time, default 1; 0 loads it paused for stepping"),
+    ),
+    tags = ["Replay"],
+    responses(
+        (status = 200, description = "Play a recording into the quote cache, replacing any running replay")
+    )
+)]
+async fn start_playback(
+    Query(query): Query<StartReplayQuery>,
+) -> Json<Result<PlaybackStatus, String>> {
+    let ticks = match replay::resolve(&query.file).and_then(|path| replay::load(&path)) {
+        Ok(ticks) => ticks,
+        Err(e) => return Json(Err(e)),
+    };
+    let speed = Some(query.speed.unwrap_or(1.0)).filter(|s| *s != 0.0);
+    Json(replay::start_playback(&query.file, ticks.into(), speed).await)
+}
+
+#[derive(Deserialize)]
+pub struct ReplaySpeedQuery {
+    pub speed: f64,
+}
+
+#[utoipa::path(
+    post,
+    path = "/replay/speed",
+    params (
+        ("speed" = f64, Query, description = "Multiple of recorded time; 0 pauses for stepping"),
+    ),
+    tags = ["Replay"],
+    responses(
+        (status = 200, description = "Change the pace of the loaded replay")
+    )
+)]
+async fn set_replay_speed(
+    Query(query): Query<ReplaySpeedQuery>,
+) -> Json<Result<PlaybackStatus, String>> {
+    let speed = Some(query.speed).filter(|s| *s != 0.0);
+    Json(replay::set_speed(speed).await)
+}
+
+#[derive(Deserialize)]
+pub struct ReplayStepQuery {
+    pub count: Option<usize>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/replay/step",
+    params (
+        ("count" = Option<usize>, Query, description = "Ticks to play, default 1"),
+    ),
+    tags = ["Replay"],
+    responses(
+        (status = 200, description = "Play the next ticks of a paused replay")
+    )
+)]
+async fn step_replay(Query(query): Query<ReplayStepQuery>) -> Json<Result<PlaybackStatus, String>> {
+    Json(replay::step(query.count.unwrap_or(1)).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/replay/stop",
+    tags = ["Replay"],
+    responses(
+        (status = 200, description = "Stop and unload the replay; replayed quotes stay in the cache")
+    )
+)]
+async fn stop_playback() -> Json<Option<PlaybackStatus>> {
+    Json(replay::stop_playback().await)
+}
+
+#[utoipa::path(
+    get,
+    path = "/replay/recording",
+    tags = ["Replay"],
+    responses(
+        (status = 200, description = "Get the running quote recording")
+    )
+)]
+async fn get_recording() -> Json<Option<RecordingStatus>> {
+    Json(replay::recording().await)
+}
+
+#[derive(Deserialize)]
+pub struct StartRecordingQuery {
+    pub path: String,
+    pub format: Option<ReplayFormat>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/replay/recording/start",
+    params (
+        ("path" = String, Query, description = "File in the server's replay directory to write, replaced if it exists"),
+        ("format" = Option<String>, Query, description = "csv or binary; by default csv for .csv paths and binary otherwise"),
+    ),
+    tags = ["Replay"],
+    responses(
+        (status = 200, description = "Record every streamed quote update to disk")
+    )
+)]
+async fn start_recording(
+    Query(query): Query<StartRecordingQuery>,
+) -> Json<Result<RecordingStatus, String>> {
+    Json(replay::start_recording(&query.path, query.format).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/replay/recording/stop",
+    tags = ["Replay"],
+    responses(
+        (status = 200, description = "Stop recording and flush the file")
+    )
+)]
+async fn stop_recording() -> Json<Result<RecordingStatus, String>> {
+    Json(replay::stop_recording().await)
+}
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    pub caller: Option<String>,
+    pub limit: Option<usize>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/audit",
+    params (
+        ("caller" = Option<String>, Query, description = "Only requests made with this key name"),
+        ("limit" = Option<usize>, Query, description = "Most entries to return, newest first; 100 when omitted"),
+    ),
+    tags = ["Audit"],
+    responses(
+        (status = 200, description = "Get who placed, changed or was refused what, newest first")
+    )
+)]
+async fn get_audit_log(Query(query): Query<AuditQuery>) -> Json<Result<Vec<AuditEntry>, String>> {
+    let conn = DATABASE.lock().unwrap();
+    let entries = auth::list(&conn, query.caller.as_deref(), query.limit.unwrap_or(100))
+        .map_err(|e| e.to_string());
+    Json(entries)
+}
+
+/// The order panel page; it drives the endpoints above from the browser.
+async fn panel() -> Html<&'static str> {
+    Html(include_str!("panel/index.html"))
+}
+
+/// What the order panel needs from the config.
+#[derive(Serialize)]
+pub struct PanelSettings {
+    pub watchlist: Vec<String>,
+    /// Gateway port to offer in the connect form.
+    pub port: String,
+    pub hotkey_refresh: String,
+    pub hotkey_place_order: String,
+    pub risk_percent: Option<f64>,
+    pub ladders: Vec<String>,
+    pub default_ladder: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/panel/settings",
+    tags = ["Panel"],
+    responses(
+        (status = 200, description = "Get the watchlist, hotkeys, risk and ladder presets for the order panel")
+    )
+)]
+async fn panel_settings() -> Json<PanelSettings> {
+    let default_ladder = CONFIG
+        .default_ladder
+        .clone()
+        .unwrap_or(DEFAULT_LADDER.to_string());
+    let mut ladders: Vec<String> = CONFIG.ladders.keys().cloned().collect();
+    ladders.push(DEFAULT_LADDER.to_string());
+    ladders.sort();
+    ladders.dedup();
+    Json(PanelSettings {
+        watchlist: CONFIG.watchlist.iter().map(|s| s.to_uppercase()).collect(),
+        port: CONFIG.port.clone(),
+        hotkey_refresh: CONFIG.hotkey_refresh.clone(),
+        hotkey_place_order: CONFIG.hotkey_place_order.clone(),
+        risk_percent: CONFIG.risk_percent(),
+        ladders,
+        default_ladder,
+    })
+}
+
 #[derive(OpenApi)]
 #[openapi(
     paths(
@@ -171,8 +1247,45 @@ async fn order(Query(query): Query<(String, i32, f64, f64, String)>) -> Json<(bo
         get_account_values,
         get_positions,
         get_market_data,
-        get_lod_hod
+        get_lod_hod,
+        get_quotes,
+        size,
+        order,
+        preview_order,
+        get_orders,
+        get_order,
+        cancel_order,
+        cancel_all_orders,
+        modify_order,
+        orders_ws,
+        get_executions,
+        list_triggers,
+        create_trigger,
+        get_trigger,
+        update_trigger,
+        cancel_trigger,
+        get_playback,
+        start_playback,
+        set_replay_speed,
+        step_replay,
+        stop_playback,
+        get_recording,
+        start_recording,
+        stop_recording,
+        get_journal,
+        export_journal,
+        get_journal_entry,
+        set_journal_notes,
+        close_position,
+        close_all_positions,
+        reverse_position,
+        trading_status,
+        kill_switch,
+        reset_kill_switch,
+        get_audit_log,
+        panel_settings
     ),
+    modifiers(&SecurityAddon),
     components(
         schemas()
     ),
@@ -183,7 +1296,33 @@ async fn order(Query(query): Query<(String, i32, f64, f64, String)>) -> Json<(bo
         (name = "get_account_values", description = "Get account values from IBKR"),
         (name = "get_positions", description = "Get positions from IBKR"),
         (name = "market_data", description = "Get market data from IBKR"),
-        (name = "get_lod_hod", description = "Get lowest and highest of the day from IBKR")
+        (name = "get_lod_hod", description = "Get lowest and highest of the day from IBKR"),
+        (name = "quotes", description = "Streamed quotes with session low, high and volume"),
+        (name = "orders", description = "Track orders placed through IBKR"),
+        (name = "positions", description = "Close, flatten and reverse positions"),
+        (name = "kill_switch", description = "Daily loss limits and the trading kill switch"),
+        (name = "triggers", description = "Conditional entries held server-side until market conditions are met"),
+        (name = "replay", description = "Record streamed quotes and play them back through the quote cache"),
+        (name = "journal", description = "Trade journal with fills, stop changes, exits and R-multiples"),
+        (name = "panel", description = "Settings for the order panel served at /"),
+        (name = "audit", description = "Requests made with each API key")
     )
 )]
 pub struct ApiDoc;
+
+/// Lets Swagger UI send an API key with its requests.
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(auth::API_KEY_HEADER))),
+        );
+        openapi.security = Some(vec![SecurityRequirement::new(
+            "api_key",
+            Vec::<String>::new(),
+        )]);
+    }
+}
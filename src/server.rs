@@ -0,0 +1,479 @@
+//! Where and how the HTTP API listens: TCP, HTTPS through rustls or a Unix socket, with a
+//! graceful shutdown on SIGTERM or Ctrl-C.
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::Router;
+use axum::serve::Listener;
+use serde::Deserialize;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::{
+    self,
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+};
+use tokio_rustls::server::TlsStream;
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::broker::Broker;
+use crate::config::CONFIG;
+use crate::replay;
+use crate::router::{self, ApiDoc};
+use crate::triggers;
+
+/// Longest a client may take over the TLS handshake before it is dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The `[server]` section of the config.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub bind: String,
+    pub port: u16,
+    /// Serve on this Unix socket instead of TCP, e.g. behind a reverse proxy.
+    pub unix_socket: Option<String>,
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: "127.0.0.1".to_string(),
+            port: 3000,
+            unix_socket: None,
+            tls: None,
+        }
+    }
+}
+
+/// PEM files for HTTPS; the certificate file may hold the whole chain.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+}
+
+impl ServerConfig {
+    /// Whether only this machine can connect. A Unix socket does not count, since it is
+    /// usually there for a reverse proxy.
+    pub fn is_loopback(&self) -> bool {
+        self.unix_socket.is_none()
+            && (self.bind == "localhost"
+                || self
+                    .bind
+                    .parse::<std::net::IpAddr>()
+                    .is_ok_and(|ip| ip.is_loopback()))
+    }
+
+    pub fn address(&self) -> String {
+        if self.bind.contains(':') {
+            format!("[{}]:{}", self.bind, self.port)
+        } else {
+            format!("{}:{}", self.bind, self.port)
+        }
+    }
+}
+
+/// Reads the certificate chain and private key into an acceptor.
+pub fn tls_acceptor(tls: &TlsConfig) -> Result<TlsAcceptor, String> {
+    let certs = CertificateDer::pem_file_iter(&tls.cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Error reading {}: {}", tls.cert_path, e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificates in {}", tls.cert_path));
+    }
+    let key = PrivateKeyDer::from_pem_file(&tls.key_path)
+        .map_err(|e| format!("Error reading {}: {}", tls.key_path, e))?;
+    let config = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .map_err(|e| e.to_string())?
+    .with_no_client_auth()
+    .with_single_cert(certs, key)
+    .map_err(|e| format!("Error loading {}: {}", tls.cert_path, e))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// A TCP listener handing out connections once their TLS handshake is done. Handshakes run
+/// on their own tasks so a slow client does not hold up the others.
+pub struct TlsListener {
+    handshaken: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl TlsListener {
+    pub fn new(tcp: TcpListener, acceptor: TlsAcceptor) -> io::Result<Self> {
+        let local_addr = tcp.local_addr()?;
+        let (sender, handshaken) = mpsc::channel(64);
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer) = match tcp.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        println!("Error accepting a connection: {}", e);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                };
+                if sender.is_closed() {
+                    break;
+                }
+                let (acceptor, sender) = (acceptor.clone(), sender.clone());
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = sender.send((stream, peer)).await;
+                        }
+                        Ok(Err(e)) => println!("TLS handshake with {} failed: {}", peer, e),
+                        Err(_) => println!("TLS handshake with {} timed out", peer),
+                    }
+                });
+            }
+        });
+        Ok(TlsListener {
+            handshaken,
+            local_addr,
+        })
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.handshaken.recv().await {
+            Some(connection) => connection,
+            // The accept loop only stops once this listener is gone.
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+/// A bound listener of whichever kind the config asks for.
+pub enum Endpoint {
+    Http(TcpListener),
+    Https(TlsListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, String),
+}
+
+impl Endpoint {
+    pub fn describe(&self) -> String {
+        match self {
+            Endpoint::Http(tcp) => format!("http://{}", address(tcp.local_addr())),
+            Endpoint::Https(tls) => format!("https://{}", address(tls.local_addr())),
+            #[cfg(unix)]
+            Endpoint::Unix(_, path) => format!("unix:{}", path),
+        }
+    }
+}
+
+fn address(addr: io::Result<SocketAddr>) -> String {
+    addr.map_or("?".to_string(), |a| a.to_string())
+}
+
+/// Binds what `config` describes: the Unix socket if one is set, else TCP with or without TLS.
+pub async fn listen(config: &ServerConfig) -> Result<Endpoint, String> {
+    if let Some(path) = &config.unix_socket {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+
+            if config.tls.is_some() {
+                println!("TLS is not used on the Unix socket {}", path);
+            }
+            // A socket left over from an earlier run would make the bind fail; a socket
+            // something still listens on, or anything else at that path, is not ours to delete.
+            if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                if !metadata.file_type().is_socket() {
+                    return Err(format!("{} exists and is not a socket", path));
+                }
+                match std::os::unix::net::UnixStream::connect(path) {
+                    Ok(_) => return Err(format!("{} is already in use", path)),
+                    Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                        std::fs::remove_file(path)
+                            .map_err(|e| format!("Error removing {}: {}", path, e))?;
+                    }
+                    Err(e) => return Err(format!("Error checking {}: {}", path, e)),
+                }
+            }
+            let listener = tokio::net::UnixListener::bind(path)
+                .map_err(|e| format!("Error binding {}: {}", path, e))?;
+            return Ok(Endpoint::Unix(listener, path.clone()));
+        }
+        #[cfg(not(unix))]
+        return Err(format!("Unix socket {} needs a Unix system", path));
+    }
+    let address = config.address();
+    let tcp = TcpListener::bind(&address)
+        .await
+        .map_err(|e| format!("Error binding {}: {}", address, e))?;
+    match &config.tls {
+        Some(tls) => {
+            let listener = TlsListener::new(tcp, tls_acceptor(tls)?).map_err(|e| e.to_string())?;
+            Ok(Endpoint::Https(listener))
+        }
+        None => Ok(Endpoint::Http(tcp)),
+    }
+}
+
+/// Serves `app` on `endpoint` until `shutdown` completes, then lets open requests finish.
+pub async fn run<F>(endpoint: Endpoint, app: Router, shutdown: F) -> io::Result<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    match endpoint {
+        Endpoint::Http(listener) => {
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown)
+                .await
+        }
+        Endpoint::Https(listener) => {
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown)
+                .await
+        }
+        #[cfg(unix)]
+        Endpoint::Unix(listener, path) => {
+            let result = axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown)
+                .await;
+            let _ = std::fs::remove_file(path);
+            result
+        }
+    }
+}
+
+/// Completes on SIGTERM or Ctrl-C.
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                println!("Error listening for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+}
+
+/// Serves the HTTP API, order panel and Swagger UI against `broker` as the `[server]` config
+/// says, and on SIGTERM or Ctrl-C stops, cancels market data and disconnects.
+pub async fn serve<B: Broker>(broker: B) -> Result<(), String> {
+    let endpoint = listen(&CONFIG.server).await?;
+    if CONFIG.auth.keys.is_empty() && CONFIG.server.is_loopback() {
+        println!(
+            "No API keys in [auth]; anyone on this machine can trade through {}.",
+            endpoint.describe()
+        );
+    } else if CONFIG.auth.keys.is_empty() {
+        println!(
+            "No API keys in [auth]; {} only serves reads until keys are added.",
+            endpoint.describe()
+        );
+    }
+    tokio::spawn(triggers::run(broker.clone()));
+
+    let app = Router::new()
+        .merge(router::app(broker.clone()))
+        .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", ApiDoc::openapi()));
+
+    println!("Serving on {}", endpoint.describe());
+    let result = run(endpoint, app, shutdown_signal()).await;
+
+    println!("Shutting down");
+    if replay::recording().await.is_some() {
+        let _ = replay::stop_recording().await;
+    }
+    broker.disconnect().await;
+    result.map_err(|e| format!("Error serving: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+    use tokio::sync::oneshot;
+    use tokio_rustls::TlsConnector;
+    use tokio_rustls::rustls::RootCertStore;
+
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/tls/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn tls() -> TlsConfig {
+        TlsConfig {
+            cert_path: fixture("cert.pem"),
+            key_path: fixture("key.pem"),
+        }
+    }
+
+    fn app() -> Router {
+        Router::new().route("/", get(|| async { "panel" }))
+    }
+
+    async fn get_root<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S) -> String {
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[test]
+    fn reads_server_section() {
+        let config: ServerConfig = toml::from_str(
+            r#"
+            port = 8443
+            tls = { cert_path = "cert.pem", key_path = "key.pem" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.address(), "127.0.0.1:8443");
+        assert_eq!(config.tls.unwrap().key_path, "key.pem");
+        assert_eq!(ServerConfig::default().address(), "127.0.0.1:3000");
+        let ipv6 = ServerConfig {
+            bind: "::1".to_string(),
+            ..ServerConfig::default()
+        };
+        assert_eq!(ipv6.address(), "[::1]:3000");
+
+        assert!(tls_acceptor(&tls()).is_ok());
+        let missing = TlsConfig {
+            key_path: fixture("missing.pem"),
+            ..tls()
+        };
+        let Err(e) = tls_acceptor(&missing) else {
+            panic!("loaded a missing key");
+        };
+        assert!(e.contains("missing.pem"), "{}", e);
+    }
+
+    #[tokio::test]
+    async fn serves_https_until_shutdown() {
+        let config = ServerConfig {
+            bind: "127.0.0.1".to_string(),
+            port: 0,
+            tls: Some(tls()),
+            ..ServerConfig::default()
+        };
+        let endpoint = listen(&config).await.unwrap();
+        let Endpoint::Https(listener) = &endpoint else {
+            panic!("not https: {}", endpoint.describe());
+        };
+        let address = listener.local_addr().unwrap();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = tokio::spawn(run(endpoint, app(), async {
+            let _ = stopped.await;
+        }));
+
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_file_iter(fixture("ca.pem")).unwrap() {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let client = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        let tcp = TcpStream::connect(address).await.unwrap();
+        let stream = TlsConnector::from(Arc::new(client))
+            .connect("localhost".try_into().unwrap(), tcp)
+            .await
+            .unwrap();
+        let response = get_root(stream).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("panel"));
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serves_on_a_unix_socket_and_removes_it() {
+        let path = std::env::temp_dir().join(format!("panel-{}.sock", std::process::id()));
+        let config = ServerConfig {
+            unix_socket: Some(path.display().to_string()),
+            ..ServerConfig::default()
+        };
+        let endpoint = listen(&config).await.unwrap();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = tokio::spawn(run(endpoint, app(), async {
+            let _ = stopped.await;
+        }));
+
+        let stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        let response = get_root(stream).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn leaves_other_files_at_the_socket_path_alone() {
+        let path = std::env::temp_dir().join(format!("panel-{}.db", std::process::id()));
+        std::fs::write(&path, "not a socket").unwrap();
+        let config = ServerConfig {
+            unix_socket: Some(path.display().to_string()),
+            ..ServerConfig::default()
+        };
+        let Err(e) = listen(&config).await else {
+            panic!("bound over a regular file");
+        };
+        assert!(e.contains("not a socket"), "{}", e);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a socket");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn refuses_a_socket_another_server_is_listening_on() {
+        let path = std::env::temp_dir().join(format!("panel-{}-in-use.sock", std::process::id()));
+        let config = ServerConfig {
+            unix_socket: Some(path.display().to_string()),
+            ..ServerConfig::default()
+        };
+        let first = listen(&config).await.unwrap();
+        let Err(e) = listen(&config).await else {
+            panic!("bound over a live socket");
+        };
+        assert!(e.contains("already in use"), "{}", e);
+
+        // Once the first server is gone its socket is stale and may be replaced.
+        drop(first);
+        let second = listen(&config).await.unwrap();
+        drop(second);
+        std::fs::remove_file(path).unwrap();
+    }
+}
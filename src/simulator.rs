@@ -0,0 +1,1294 @@
+//! A local paper-trading backend that matches orders against live or replayed quotes.
+//!
+//! Orders go through the same order book, journal and ladder logic as with IBKR, so scale-outs
+//! can be rehearsed without a broker; the journal is kept in a separate database so simulated
+//! trades never mix with live ones. Positions use average-cost accounting.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use ibapi::contracts::Contract;
+use ibapi::orders::{Action, Order, TimeInForce};
+use rusqlite::Connection;
+use serde::Deserialize;
+use time::{OffsetDateTime, format_description::well_known::Rfc3339, macros::format_description};
+use tokio::sync::{Mutex, broadcast};
+
+use crate::broker::{Bar, BarSize, Broker, OrderEvent, Position, Quote};
+use crate::config::CONFIG;
+use crate::connector::IbkrBroker;
+use crate::database::{self, DATABASE};
+use crate::executions::{ExecutionQuery, ExecutionRecord};
+use crate::kill_switch;
+use crate::ladder::round_to_tick;
+use crate::order_book::{self, ORDER_BOOK};
+use crate::preview::OrderPreview;
+use crate::quotes;
+use crate::replay::{self, ReplayTick};
+use crate::sizing::{self, PositionSize, SizingLimits};
+use crate::trading;
+
+/// How often working orders are checked against live quotes.
+const MATCH_INTERVAL: Duration = Duration::from_millis(250);
+const ACCOUNT: &str = "SIM";
+/// Intraday buying power as a multiple of equity.
+const BUYING_POWER_MULTIPLIER: f64 = 4.0;
+const INITIAL_MARGIN_RATE: f64 = 0.5;
+const MAINTENANCE_MARGIN_RATE: f64 = 0.25;
+/// Trades kept per symbol to build bars from in replay.
+const MAX_TRADE_HISTORY: usize = 50_000;
+
+/// Settings from the `[simulator]` section of the config.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SimulatorConfig {
+    /// Serve the simulator instead of IBKR.
+    pub enabled: bool,
+    pub starting_cash: f64,
+    /// Ticks market and stop fills give up against the quote.
+    pub slippage_ticks: f64,
+    pub min_tick: f64,
+    pub commission_per_share: f64,
+    pub min_commission: f64,
+    /// Replay file to take quotes from instead of IBKR market data.
+    pub replay_file: Option<String>,
+    /// Multiple of recorded time the replay file plays at; 0 starts it paused for stepping.
+    pub replay_speed: f64,
+    /// SQLite file for simulated executions and journal entries, kept apart from the live one.
+    pub database_path: Option<String>,
+}
+
+impl Default for SimulatorConfig {
+    fn default() -> Self {
+        SimulatorConfig {
+            enabled: false,
+            starting_cash: 100_000.0,
+            slippage_ticks: 1.0,
+            min_tick: 0.01,
+            commission_per_share: 0.005,
+            min_commission: 1.0,
+            replay_file: None,
+            replay_speed: 1.0,
+            database_path: None,
+        }
+    }
+}
+
+impl SimulatorConfig {
+    fn commission(&self, shares: f64) -> f64 {
+        (shares * self.commission_per_share).max(self.min_commission)
+    }
+}
+
+/// Where the simulator's quotes come from.
+#[derive(Clone)]
+enum Prices {
+    /// IBKR market data; the connection is only used for quotes and bars.
+    Live(IbkrBroker),
+    /// Replayed quotes; these ticks, if any, start playing on each connect.
+    Replay(Arc<Vec<ReplayTick>>),
+}
+
+/// A change for the shared order book, applied in the order it happened.
+#[derive(Clone, Debug)]
+enum Update {
+    Placed {
+        order_id: i32,
+        symbol: String,
+        order: Box<Order>,
+    },
+    Modified {
+        order_id: i32,
+        order: Box<Order>,
+    },
+    Event(OrderEvent),
+}
+
+#[derive(Clone, Debug)]
+struct SimOrder {
+    order_id: i32,
+    symbol: String,
+    order: Order,
+    filled: f64,
+    remaining: f64,
+    average_fill_price: f64,
+    /// Current stop of a TRAIL order, ratcheted as price moves in its favour.
+    trail_stop: Option<f64>,
+}
+
+impl SimOrder {
+    fn is_buy(&self) -> bool {
+        self.order.action == Action::Buy
+    }
+
+    fn status(&self, status: &str) -> Update {
+        Update::Event(OrderEvent::Status {
+            order_id: self.order_id,
+            client_id: 0,
+            status: status.to_string(),
+            filled: self.filled,
+            remaining: self.remaining,
+            average_fill_price: self.average_fill_price,
+            perm_id: self.order_id,
+        })
+    }
+
+    /// Moves a TRAIL order's stop behind `last`; other orders are left alone.
+    fn follow(&mut self, last: f64) {
+        if self.order.order_type != "TRAIL" {
+            return;
+        }
+        let offset = match self.order.trailing_percent {
+            Some(percent) => last * percent / 100.0,
+            None => self.order.aux_price.unwrap_or(0.0),
+        };
+        let stop = self.trail_stop;
+        self.trail_stop = Some(if self.is_buy() {
+            stop.map_or(last + offset, |s| s.min(last + offset))
+        } else {
+            stop.map_or(last - offset, |s| s.max(last - offset))
+        });
+    }
+
+    /// The price this order fills at against `quote`, if it fills now.
+    fn fill_price(&self, quote: &Quote, settings: &SimulatorConfig) -> Option<f64> {
+        let mid = quote.bid.zip(quote.ask).map(|(b, a)| (b + a) / 2.0);
+        let last = quote.last.or(mid)?;
+        let bid = quote.bid.unwrap_or(last);
+        let ask = quote.ask.unwrap_or(last);
+        let slippage = settings.slippage_ticks * settings.min_tick;
+        let market = if self.is_buy() {
+            ask + slippage
+        } else {
+            bid - slippage
+        };
+        let price = match self.order.order_type.as_str() {
+            "MKT" => market,
+            "LMT" => {
+                let limit = self.order.limit_price?;
+                match self.is_buy() {
+                    true if ask <= limit => ask.min(limit),
+                    false if bid >= limit => bid.max(limit),
+                    _ => return None,
+                }
+            }
+            "STP" | "TRAIL" => {
+                let stop = match self.order.order_type.as_str() {
+                    "TRAIL" => self.trail_stop?,
+                    _ => self.order.aux_price?,
+                };
+                match self.is_buy() {
+                    true if last >= stop => market,
+                    false if last <= stop => market,
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        Some(round_to_tick(price, settings.min_tick))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Holding {
+    quantity: f64,
+    average_cost: f64,
+}
+
+/// The simulated account and its working orders.
+struct SimState {
+    connected: bool,
+    /// Bumped on every connect and disconnect so stale background tasks stop.
+    session: u64,
+    starting_cash: f64,
+    cash: f64,
+    /// Closed-trade P&L net of every commission paid.
+    realized_pnl: f64,
+    holdings: BTreeMap<String, Holding>,
+    orders: BTreeMap<i32, SimOrder>,
+    quotes: HashMap<String, Quote>,
+    trades: HashMap<String, Vec<(OffsetDateTime, f64, f64)>>,
+    executions: Vec<ExecutionRecord>,
+    next_order_id: i32,
+    /// Tells this session's execution ids apart from earlier sessions' in the database.
+    execution_prefix: String,
+    next_execution_id: u64,
+}
+
+impl SimState {
+    fn new(starting_cash: f64) -> Self {
+        SimState {
+            connected: false,
+            session: 0,
+            starting_cash,
+            cash: starting_cash,
+            realized_pnl: 0.0,
+            holdings: BTreeMap::new(),
+            orders: BTreeMap::new(),
+            quotes: HashMap::new(),
+            trades: HashMap::new(),
+            executions: Vec::new(),
+            next_order_id: 1,
+            execution_prefix: format!("sim.{:x}", OffsetDateTime::now_utc().unix_timestamp_nanos()),
+            next_execution_id: 1,
+        }
+    }
+
+    fn position(&self, symbol: &str) -> f64 {
+        self.holdings.get(symbol).map_or(0.0, |h| h.quantity)
+    }
+
+    fn mark(&self, symbol: &str) -> Option<f64> {
+        self.quotes.get(symbol).and_then(|q| q.last)
+    }
+
+    fn unrealized_pnl(&self) -> f64 {
+        self.holdings
+            .iter()
+            .map(|(symbol, h)| {
+                let mark = self.mark(symbol).unwrap_or(h.average_cost);
+                (mark - h.average_cost) * h.quantity
+            })
+            .sum()
+    }
+
+    fn equity(&self) -> f64 {
+        let market_value: f64 = self
+            .holdings
+            .iter()
+            .map(|(symbol, h)| h.quantity * self.mark(symbol).unwrap_or(h.average_cost))
+            .sum();
+        self.cash + market_value
+    }
+
+    /// Account values keyed like IBKR's tags.
+    fn account_numbers(&self) -> HashMap<String, f64> {
+        let equity = self.equity();
+        let gross: f64 = self
+            .holdings
+            .iter()
+            .map(|(symbol, h)| (h.quantity * self.mark(symbol).unwrap_or(h.average_cost)).abs())
+            .sum();
+        let buying_power = (equity * BUYING_POWER_MULTIPLIER - gross).max(0.0);
+        HashMap::from([
+            ("NetLiquidation".to_string(), equity),
+            ("EquityWithLoanValue".to_string(), equity),
+            ("TotalCashValue".to_string(), self.cash),
+            ("GrossPositionValue".to_string(), gross),
+            ("BuyingPower".to_string(), buying_power),
+            (
+                "AvailableFunds".to_string(),
+                buying_power / BUYING_POWER_MULTIPLIER,
+            ),
+            ("RealizedPnL".to_string(), self.realized_pnl),
+            ("UnrealizedPnL".to_string(), self.unrealized_pnl()),
+        ])
+    }
+
+    /// Stores a new quote and fills whatever it reaches.
+    fn on_quote(&mut self, quote: Quote, settings: &SimulatorConfig) -> Vec<Update> {
+        let symbol = quote.symbol.clone();
+        if let Some(last) = quote.last {
+            let previous = self.quotes.get(&symbol).and_then(|q| q.last);
+            let volume = quote.volume.unwrap_or(0.0);
+            if previous != Some(last) || quote.updated_at.is_empty() {
+                let trades = self.trades.entry(symbol.clone()).or_default();
+                let time = OffsetDateTime::parse(&quote.updated_at, &Rfc3339)
+                    .unwrap_or_else(|_| OffsetDateTime::now_utc());
+                trades.push((time, last, volume));
+                if trades.len() > MAX_TRADE_HISTORY {
+                    trades.remove(0);
+                }
+            }
+            for order in self.orders.values_mut().filter(|o| o.symbol == symbol) {
+                order.follow(last);
+            }
+        }
+        self.quotes.insert(symbol.clone(), quote);
+
+        let ids: Vec<i32> = self
+            .orders
+            .values()
+            .filter(|o| o.symbol == symbol)
+            .map(|o| o.order_id)
+            .collect();
+        let mut updates = Vec::new();
+        for order_id in ids {
+            updates.extend(self.try_fill(order_id, settings));
+        }
+        updates
+    }
+
+    fn place(
+        &mut self,
+        symbol: &str,
+        order: Order,
+        settings: &SimulatorConfig,
+    ) -> (i32, Vec<Update>) {
+        let order_id = self.next_order_id;
+        self.next_order_id += 1;
+        let mut sim = SimOrder {
+            order_id,
+            symbol: symbol.to_string(),
+            filled: 0.0,
+            remaining: order.total_quantity,
+            average_fill_price: 0.0,
+            trail_stop: order.trail_stop_price,
+            order,
+        };
+        if let Some(last) = self.mark(symbol) {
+            sim.follow(last);
+        }
+        let mut updates = vec![
+            Update::Placed {
+                order_id,
+                symbol: symbol.to_string(),
+                order: Box::new(sim.order.clone()),
+            },
+            sim.status("Submitted"),
+        ];
+        self.orders.insert(order_id, sim);
+        updates.extend(self.try_fill(order_id, settings));
+        (order_id, updates)
+    }
+
+    fn try_fill(&mut self, order_id: i32, settings: &SimulatorConfig) -> Vec<Update> {
+        let Some(order) = self.orders.get(&order_id) else {
+            return Vec::new();
+        };
+        let price = self
+            .quotes
+            .get(&order.symbol)
+            .and_then(|quote| order.fill_price(quote, settings));
+        match price {
+            Some(price) => self.fill(order_id, order.remaining, price, settings),
+            None if order.order.tif == TimeInForce::ImmediateOrCancel => {
+                let order = self.orders.remove(&order_id).unwrap();
+                vec![order.status("Cancelled")]
+            }
+            None => Vec::new(),
+        }
+    }
+
+    fn fill(
+        &mut self,
+        order_id: i32,
+        shares: f64,
+        price: f64,
+        settings: &SimulatorConfig,
+    ) -> Vec<Update> {
+        let Some(order) = self.orders.get_mut(&order_id) else {
+            return Vec::new();
+        };
+        order.average_fill_price =
+            (order.average_fill_price * order.filled + price * shares) / (order.filled + shares);
+        order.filled += shares;
+        order.remaining -= shares;
+        let order = order.clone();
+        if order.remaining <= 0.0 {
+            self.orders.remove(&order_id);
+        }
+
+        let commission = settings.commission(shares);
+        let signed = if order.is_buy() { shares } else { -shares };
+        let closing_pnl = self.apply_fill(&order.symbol, signed, price);
+        self.cash -= signed * price + commission;
+        self.realized_pnl += closing_pnl.unwrap_or(0.0) - commission;
+
+        let now = OffsetDateTime::now_utc();
+        let execution_id = format!("{}.{:08}", self.execution_prefix, self.next_execution_id);
+        self.next_execution_id += 1;
+        let time = execution_time(now);
+        self.executions.push(ExecutionRecord {
+            execution_id: execution_id.clone(),
+            order_id,
+            perm_id: order_id,
+            client_id: 0,
+            trade_id: Some(order_book::trade_id(order_id, &order.order.oca_group)),
+            oca_group: order.order.oca_group.clone(),
+            order_ref: order.order.order_ref.clone(),
+            symbol: order.symbol.clone(),
+            side: if order.is_buy() { "BOT" } else { "SLD" }.to_string(),
+            shares,
+            price,
+            time: time.clone(),
+            account: ACCOUNT.to_string(),
+            exchange: "SIM".to_string(),
+            commission: Some(commission),
+            commission_currency: Some("USD".to_string()),
+            realized_pnl: closing_pnl.map(|pnl| pnl - commission),
+        });
+
+        let status = if order.remaining <= 0.0 {
+            "Filled"
+        } else {
+            "PartiallyFilled"
+        };
+        let mut updates = vec![
+            Update::Event(OrderEvent::Fill {
+                order_id,
+                client_id: 0,
+                execution_id: execution_id.clone(),
+                shares,
+                price,
+                time,
+            }),
+            order.status(status),
+            Update::Event(OrderEvent::Commission {
+                execution_id,
+                commission,
+                realized_pnl: closing_pnl.map(|pnl| pnl - commission),
+            }),
+        ];
+        updates.extend(self.reduce_oca_group(&order, shares));
+        updates
+    }
+
+    /// Shrinks the rest of the order's OCA group by `shares`, cancelling what reaches zero.
+    fn reduce_oca_group(&mut self, filled: &SimOrder, shares: f64) -> Vec<Update> {
+        if filled.order.oca_group.is_empty() {
+            return Vec::new();
+        }
+        let siblings: Vec<i32> = self
+            .orders
+            .values()
+            .filter(|o| o.order.oca_group == filled.order.oca_group)
+            .map(|o| o.order_id)
+            .collect();
+        let mut updates = Vec::new();
+        for order_id in siblings {
+            let order = self.orders.get_mut(&order_id).unwrap();
+            order.remaining -= shares;
+            if order.remaining <= 0.0 {
+                order.remaining = 0.0;
+                let order = self.orders.remove(&order_id).unwrap();
+                updates.push(order.status("Cancelled"));
+            } else {
+                updates.push(order.status("Submitted"));
+            }
+        }
+        updates
+    }
+
+    /// Books a signed fill into the holding; returns the gross P&L of any shares it closed.
+    fn apply_fill(&mut self, symbol: &str, signed: f64, price: f64) -> Option<f64> {
+        let holding = self.holdings.entry(symbol.to_string()).or_default();
+        let mut closing_pnl = None;
+        if holding.quantity == 0.0 || holding.quantity.signum() == signed.signum() {
+            let quantity = holding.quantity + signed;
+            holding.average_cost = (holding.average_cost * holding.quantity.abs()
+                + price * signed.abs())
+                / quantity.abs();
+            holding.quantity = quantity;
+        } else {
+            let closed = holding.quantity.abs().min(signed.abs());
+            closing_pnl = Some((price - holding.average_cost) * closed * holding.quantity.signum());
+            holding.quantity += signed;
+            if holding.quantity.abs() < 1e-9 {
+                holding.quantity = 0.0;
+            } else if holding.quantity.signum() == signed.signum() {
+                // Flipped through zero; the remainder opened at this fill.
+                holding.average_cost = price;
+            }
+        }
+        if holding.quantity == 0.0 {
+            self.holdings.remove(symbol);
+        }
+        closing_pnl
+    }
+
+    fn cancel(&mut self, order_id: i32) -> Result<Vec<Update>, String> {
+        let order = self
+            .orders
+            .remove(&order_id)
+            .ok_or(format!("Order {} is not working", order_id))?;
+        Ok(vec![order.status("Cancelled")])
+    }
+
+    fn modify(
+        &mut self,
+        order_id: i32,
+        quantity: Option<f64>,
+        limit_price: Option<f64>,
+        stop_price: Option<f64>,
+        settings: &SimulatorConfig,
+    ) -> Result<Vec<Update>, String> {
+        let order = self
+            .orders
+            .get_mut(&order_id)
+            .ok_or(format!("Order {} is not working", order_id))?;
+        if let Some(quantity) = quantity {
+            if quantity <= order.filled {
+                return Err(format!(
+                    "Quantity {} is not above the {} already filled",
+                    quantity, order.filled
+                ));
+            }
+            order.order.total_quantity = quantity;
+            order.remaining = quantity - order.filled;
+        }
+        if limit_price.is_some() {
+            order.order.limit_price = limit_price;
+        }
+        if stop_price.is_some() {
+            order.order.aux_price = stop_price;
+        }
+        let mut updates = vec![Update::Modified {
+            order_id,
+            order: Box::new(order.order.clone()),
+        }];
+        updates.extend(self.try_fill(order_id, settings));
+        Ok(updates)
+    }
+
+    /// Completed bars built from the trades seen so far; the last one is still forming.
+    fn bars(&self, symbol: &str, bar_size: BarSize, count: usize) -> Vec<Bar> {
+        let seconds = bar_seconds(bar_size);
+        let mut bars: Vec<(i64, Bar)> = Vec::new();
+        for (time, price, volume) in self.trades.get(symbol).into_iter().flatten() {
+            let bucket = time.unix_timestamp().div_euclid(seconds);
+            match bars.last_mut() {
+                Some((b, bar)) if *b == bucket => {
+                    bar.high = bar.high.max(*price);
+                    bar.low = bar.low.min(*price);
+                    bar.close = *price;
+                    bar.volume += volume;
+                }
+                _ => bars.push((
+                    bucket,
+                    Bar {
+                        time: OffsetDateTime::from_unix_timestamp(bucket * seconds)
+                            .unwrap_or(OffsetDateTime::UNIX_EPOCH),
+                        open: *price,
+                        high: *price,
+                        low: *price,
+                        close: *price,
+                        volume: *volume,
+                    },
+                )),
+            }
+        }
+        bars.pop();
+        let skip = bars.len().saturating_sub(count);
+        bars.into_iter().skip(skip).map(|(_, bar)| bar).collect()
+    }
+}
+
+fn bar_seconds(bar_size: BarSize) -> i64 {
+    match bar_size {
+        BarSize::Sec => 1,
+        BarSize::Sec5 => 5,
+        BarSize::Sec15 => 15,
+        BarSize::Sec30 => 30,
+        BarSize::Min => 60,
+        BarSize::Min2 => 120,
+        BarSize::Min3 => 180,
+        BarSize::Min5 => 300,
+        BarSize::Min15 => 900,
+        BarSize::Min20 => 1200,
+        BarSize::Min30 => 1800,
+        BarSize::Hour => 3600,
+        BarSize::Hour2 => 7200,
+        BarSize::Hour3 => 10_800,
+        BarSize::Hour4 => 14_400,
+        BarSize::Hour8 => 28_800,
+        BarSize::Day => 86_400,
+        BarSize::Week => 604_800,
+        BarSize::Month => 2_592_000,
+    }
+}
+
+/// Execution times in IBKR's `yyyymmdd hh:mm:ss` form.
+fn execution_time(at: OffsetDateTime) -> String {
+    at.format(format_description!(
+        "[year][month][day] [hour]:[minute]:[second]"
+    ))
+    .unwrap_or_default()
+}
+
+/// The paper-trading backend. Clones share one account.
+#[derive(Clone)]
+pub struct SimBroker {
+    prices: Prices,
+    settings: SimulatorConfig,
+    state: Arc<Mutex<SimState>>,
+}
+
+impl SimBroker {
+    /// A simulator quoting from IBKR market data.
+    pub fn live(settings: SimulatorConfig) -> Self {
+        Self::with_prices(Prices::Live(IbkrBroker::default()), settings)
+    }
+
+    /// A simulator quoting from replayed ticks. `ticks` are played from the start on each
+    /// connect; others can be loaded through the replay endpoints.
+    pub fn replay(settings: SimulatorConfig, ticks: Vec<ReplayTick>) -> Self {
+        Self::with_prices(Prices::Replay(Arc::new(ticks)), settings)
+    }
+
+    /// The simulator described by the `[simulator]` section of the config.
+    pub fn from_config() -> Result<Self, String> {
+        let settings = CONFIG.simulator.clone();
+        match &settings.replay_file {
+            Some(path) => {
+                let ticks = replay::load(path)?;
+                Ok(Self::replay(settings, ticks))
+            }
+            None => Ok(Self::live(settings)),
+        }
+    }
+
+    fn with_prices(prices: Prices, settings: SimulatorConfig) -> Self {
+        database::use_simulator_database();
+        let mut state = SimState::new(settings.starting_cash);
+        // Order ids carry on from earlier sessions, which the journal is keyed by.
+        match last_order_id(&DATABASE.lock().unwrap()) {
+            Ok(last) => state.next_order_id = last + 1,
+            Err(e) => println!("Error reading simulated order ids: {}", e),
+        }
+        SimBroker {
+            prices,
+            settings,
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Feeds a quote update to the matching engine, as replayed ticks do.
+    pub async fn apply_tick(&self, tick: &ReplayTick) {
+        let quote = replay::apply(tick).await;
+        self.match_quote(quote).await;
+    }
+
+    async fn match_quote(&self, quote: Quote) {
+        {
+            let mut state = self.state.lock().await;
+            let updates = state.on_quote(quote, &self.settings);
+            publish(updates).await;
+        }
+        self.record_pnl().await;
+    }
+
+    async fn record_pnl(&self) {
+        let (daily, realized, unrealized) = {
+            let state = self.state.lock().await;
+            let unrealized = state.unrealized_pnl();
+            (
+                state.equity() - state.starting_cash,
+                state.realized_pnl,
+                unrealized,
+            )
+        };
+        kill_switch::record_pnl(self, daily, Some(realized), Some(unrealized)).await;
+    }
+
+    /// Matches orders against played-back quotes until the session changes.
+    async fn follow_replay(self, mut played: broadcast::Receiver<Quote>, session: u64) {
+        loop {
+            let quote = match played.recv().await {
+                Ok(quote) => quote,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    println!("Simulator skipped {} replayed quotes", skipped);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            };
+            if self.state.lock().await.session != session {
+                return;
+            }
+            self.match_quote(quote).await;
+        }
+    }
+
+    /// Re-checks working orders and positions against live quotes until the session changes.
+    async fn follow_live(self, live: IbkrBroker, session: u64) {
+        let mut timer = tokio::time::interval(MATCH_INTERVAL);
+        loop {
+            timer.tick().await;
+            let symbols: Vec<String> = {
+                let state = self.state.lock().await;
+                if state.session != session {
+                    return;
+                }
+                let mut symbols: Vec<String> =
+                    state.orders.values().map(|o| o.symbol.clone()).collect();
+                symbols.extend(state.holdings.keys().cloned());
+                symbols.sort();
+                symbols.dedup();
+                symbols
+            };
+            for symbol in symbols {
+                if let Ok(quote) = live.quote(&symbol).await {
+                    let mut state = self.state.lock().await;
+                    let updates = state.on_quote(quote, &self.settings);
+                    publish(updates).await;
+                }
+            }
+            self.record_pnl().await;
+        }
+    }
+
+    /// Refreshes the symbol's quote from the price source before an order is matched.
+    async fn refresh_quote(&self, symbol: &str) -> Option<Quote> {
+        let quote = self.quote(symbol).await.ok()?;
+        let mut state = self.state.lock().await;
+        let updates = state.on_quote(quote.clone(), &self.settings);
+        publish(updates).await;
+        Some(quote)
+    }
+
+    async fn place(&self, symbol: &str, order: Order) -> i32 {
+        self.refresh_quote(symbol).await;
+        let mut state = self.state.lock().await;
+        // Stay clear of ids already in the book from another session or backend.
+        state.next_order_id = state
+            .next_order_id
+            .max(ORDER_BOOK.read().await.next_order_id());
+        let (order_id, updates) = state.place(symbol, order, &self.settings);
+        publish(updates).await;
+        order_id
+    }
+
+    async fn not_connected(&self) -> bool {
+        !self.state.lock().await.connected
+    }
+}
+
+/// The highest order id simulated sessions have recorded.
+fn last_order_id(conn: &Connection) -> rusqlite::Result<i32> {
+    conn.query_row(
+        "SELECT MAX(id) FROM (
+            SELECT MAX(order_id) AS id FROM journal_orders WHERE client_id = 0
+            UNION ALL
+            SELECT MAX(order_id) FROM executions WHERE client_id = 0
+        )",
+        [],
+        |row| row.get::<_, Option<i32>>(0),
+    )
+    .map(|id| id.unwrap_or(0))
+}
+
+/// Applies simulator updates to the shared order book in order.
+async fn publish(updates: Vec<Update>) {
+    if updates.is_empty() {
+        return;
+    }
+    let mut book = ORDER_BOOK.write().await;
+    for update in updates {
+        match update {
+            Update::Placed {
+                order_id,
+                symbol,
+                order,
+            } => book.record_placed(order_id, 0, &Contract::stock(&symbol).build(), &order),
+            Update::Modified { order_id, order } => book.record_modified(0, order_id, &order),
+            Update::Event(event) => book.apply(&event),
+        }
+    }
+}
+
+impl Broker for SimBroker {
+    async fn connect(&self, address: &str, port: u16, client_id: i32) -> bool {
+        if let Prices::Live(live) = &self.prices
+            && !live.connect_market_data(address, port, client_id).await
+        {
+            return false;
+        }
+        let session = {
+            let mut state = self.state.lock().await;
+            state.connected = true;
+            state.session += 1;
+            state.session
+        };
+        match &self.prices {
+            Prices::Live(live) => {
+                tokio::spawn(self.clone().follow_live(live.clone(), session));
+            }
+            Prices::Replay(ticks) => {
+                let played = replay::subscribe();
+                tokio::spawn(self.clone().follow_replay(played, session));
+                if !ticks.is_empty() {
+                    let file = self.settings.replay_file.as_deref().unwrap_or("preloaded");
+                    let speed = Some(self.settings.replay_speed).filter(|s| *s > 0.0);
+                    if let Err(e) = replay::start_playback(file, ticks.clone(), speed).await {
+                        println!("Error starting replay: {}", e);
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    async fn is_connected(&self) -> bool {
+        self.state.lock().await.connected
+    }
+
+    async fn client_id(&self) -> Option<i32> {
+        // Simulated orders are recorded in the order book under client id 0.
+        (!self.not_connected().await).then_some(0)
+    }
+
+    async fn disconnect(&self) {
+        {
+            let mut state = self.state.lock().await;
+            state.connected = false;
+            state.session += 1;
+        }
+        match &self.prices {
+            Prices::Live(live) => live.disconnect().await,
+            Prices::Replay(ticks) if !ticks.is_empty() => {
+                replay::stop_playback().await;
+            }
+            Prices::Replay(_) => {}
+        }
+    }
+
+    async fn account_values(&self) -> Option<Vec<String>> {
+        if self.not_connected().await {
+            return None;
+        }
+        let values = self.state.lock().await.account_numbers();
+        let mut keys: Vec<&String> = values.keys().collect();
+        keys.sort();
+        Some(
+            keys.into_iter()
+                .map(|key| {
+                    format!(
+                        "key: {}, value: {:.2}, currency: USD, account: {}",
+                        key, values[key], ACCOUNT
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    async fn positions(&self) -> Option<Vec<Position>> {
+        if self.not_connected().await {
+            return None;
+        }
+        let state = self.state.lock().await;
+        Some(
+            state
+                .holdings
+                .iter()
+                .map(|(symbol, h)| Position {
+                    account: ACCOUNT.to_string(),
+                    symbol: symbol.clone(),
+                    quantity: h.quantity,
+                    average_cost: h.average_cost,
+                })
+                .collect(),
+        )
+    }
+
+    async fn last_price(&self, symbol: &str) -> Option<f64> {
+        let quote = self.quote(symbol).await.ok();
+        match (quote.and_then(|q| q.last), &self.prices) {
+            (Some(last), _) => Some(last),
+            (None, Prices::Live(live)) => live.last_price(symbol).await,
+            (None, Prices::Replay(_)) => None,
+        }
+    }
+
+    async fn quote(&self, symbol: &str) -> Result<Quote, String> {
+        match &self.prices {
+            Prices::Live(live) => live.quote(symbol).await,
+            Prices::Replay(_) => quotes::get(symbol)
+                .await
+                .ok_or(format!("No replayed quote for {}", symbol)),
+        }
+    }
+
+    async fn day_range(&self, symbol: &str) -> (f64, f64) {
+        match &self.prices {
+            Prices::Live(live) => live.day_range(symbol).await,
+            Prices::Replay(_) => match quotes::get(symbol).await {
+                Some(Quote {
+                    low: Some(low),
+                    high: Some(high),
+                    ..
+                }) => (low, high),
+                _ => (0.0, 0.0),
+            },
+        }
+    }
+
+    async fn min_tick(&self, _symbol: &str) -> f64 {
+        self.settings.min_tick
+    }
+
+    async fn recent_bars(
+        &self,
+        symbol: &str,
+        bar_size: BarSize,
+        count: usize,
+    ) -> Result<Vec<Bar>, String> {
+        match &self.prices {
+            Prices::Live(live) => live.recent_bars(symbol, bar_size, count).await,
+            Prices::Replay(_) => Ok(self.state.lock().await.bars(symbol, bar_size, count)),
+        }
+    }
+
+    async fn size_position(
+        &self,
+        symbol: &str,
+        entry_price: Option<f64>,
+        stop_price: f64,
+        risk_percent: Option<f64>,
+    ) -> Result<PositionSize, String> {
+        if self.not_connected().await {
+            return Err("Not connected to the simulator".to_string());
+        }
+        let settings = &CONFIG.sizing;
+        let risk_percent = risk_percent
+            .or_else(|| CONFIG.risk_percent())
+            .ok_or("No risk percent given or configured")?;
+        let entry_price = match entry_price.filter(|p| *p > 0.0) {
+            Some(price) => price,
+            None => self
+                .last_price(symbol)
+                .await
+                .ok_or(format!("No quote for {} to size against", symbol))?,
+        };
+        let values = self.state.lock().await.account_numbers();
+        let equity = match settings.fixed_equity {
+            Some(equity) => equity,
+            None => *values
+                .get(&settings.equity_basis)
+                .ok_or(format!("Account value {} not found", settings.equity_basis))?,
+        };
+        let limits = SizingLimits {
+            lot_size: settings.lot_size,
+            buying_power: values.get("BuyingPower").copied(),
+            max_position_value: settings.max_position_value,
+        };
+        sizing::size_position(equity, entry_price, stop_price, risk_percent, &limits)
+    }
+
+    async fn place_order(&self, symbol: &str, order: &Order) -> Result<i32, String> {
+        if self.not_connected().await {
+            return Err("Not connected to the simulator".to_string());
+        }
+        Ok(self.place(symbol, order.clone()).await)
+    }
+
+    async fn preview_order(
+        &self,
+        symbol: &str,
+        qty: i32,
+        action: &str,
+    ) -> Result<OrderPreview, String> {
+        if self.not_connected().await {
+            return Err("Not connected to the simulator".to_string());
+        }
+        let price = self
+            .last_price(symbol)
+            .await
+            .ok_or(format!("No quote for {}", symbol))?;
+        let state = self.state.lock().await;
+        let values = state.account_numbers();
+        let gross = values["GrossPositionValue"];
+        let signed = match trading::parse_action(action)? {
+            Action::Sell => -qty,
+            _ => qty,
+        } as f64;
+        let after = (state.position(symbol) + signed) * price;
+        let before = state.position(symbol) * price;
+        let change = after.abs() - before.abs();
+        let commission = self.settings.commission(qty as f64);
+        Ok(OrderPreview {
+            initial_margin_change: Some(change * INITIAL_MARGIN_RATE),
+            maintenance_margin_change: Some(change * MAINTENANCE_MARGIN_RATE),
+            initial_margin_after: Some((gross + change) * INITIAL_MARGIN_RATE),
+            maintenance_margin_after: Some((gross + change) * MAINTENANCE_MARGIN_RATE),
+            equity_with_loan_after: Some(values["EquityWithLoanValue"] - commission),
+            commission: Some(commission),
+            minimum_commission: Some(commission),
+            maximum_commission: Some(commission),
+            commission_currency: "USD".to_string(),
+            warning_text: String::new(),
+        })
+    }
+
+    async fn refresh_orders(&self, _all: bool) -> bool {
+        // The book is fed directly, so there is nothing to sync.
+        !self.not_connected().await
+    }
+
+    async fn executions(&self, query: &ExecutionQuery) -> Result<Vec<ExecutionRecord>, String> {
+        let side = query.side.as_deref().map(|s| match s {
+            "BUY" => "BOT",
+            "SELL" => "SLD",
+            other => other,
+        });
+        let state = self.state.lock().await;
+        Ok(state
+            .executions
+            .iter()
+            .filter(|e| query.symbol.as_ref().is_none_or(|s| e.symbol == *s))
+            .filter(|e| side.is_none_or(|s| e.side == s))
+            .filter(|e| query.account.as_ref().is_none_or(|a| e.account == *a))
+            .filter(|e| query.since.as_ref().is_none_or(|t| e.time >= *t))
+            .cloned()
+            .collect())
+    }
+
+    async fn cancel_order(&self, order_id: i32) -> (bool, String) {
+        if self.not_connected().await {
+            return (false, "Not connected to the simulator".to_string());
+        }
+        let mut state = self.state.lock().await;
+        match state.cancel(order_id) {
+            Ok(updates) => {
+                publish(updates).await;
+                (true, format!("Order {} cancelled.", order_id))
+            }
+            Err(e) => (false, e),
+        }
+    }
+
+    async fn cancel_all(&self, symbol: Option<&str>) -> (bool, String) {
+        if self.not_connected().await {
+            return (false, "Not connected to the simulator".to_string());
+        }
+        let mut state = self.state.lock().await;
+        let order_ids: Vec<i32> = state
+            .orders
+            .values()
+            .filter(|o| symbol.is_none_or(|s| o.symbol == s))
+            .map(|o| o.order_id)
+            .collect();
+        let mut updates = Vec::new();
+        for order_id in &order_ids {
+            updates.extend(state.cancel(*order_id).unwrap_or_default());
+        }
+        publish(updates).await;
+        (
+            true,
+            format!("Cancel requested for {} orders.", order_ids.len()),
+        )
+    }
+
+    async fn global_cancel(&self) -> (bool, String) {
+        // Every simulated order belongs to this one session.
+        self.cancel_all(None).await
+    }
+
+    async fn modify_order(
+        &self,
+        order_id: i32,
+        quantity: Option<f64>,
+        limit_price: Option<f64>,
+        stop_price: Option<f64>,
+    ) -> (bool, String) {
+        if self.not_connected().await {
+            return (false, "Not connected to the simulator".to_string());
+        }
+        let mut state = self.state.lock().await;
+        match state.modify(order_id, quantity, limit_price, stop_price, &self.settings) {
+            Ok(updates) => {
+                publish(updates).await;
+                (true, format!("Order {} modification submitted.", order_id))
+            }
+            Err(e) => (false, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> SimulatorConfig {
+        SimulatorConfig {
+            slippage_ticks: 2.0,
+            min_commission: 1.0,
+            commission_per_share: 0.01,
+            ..Default::default()
+        }
+    }
+
+    fn quote(bid: f64, ask: f64, last: f64) -> Quote {
+        Quote {
+            symbol: "TSLA".to_string(),
+            bid: Some(bid),
+            ask: Some(ask),
+            last: Some(last),
+            ..Default::default()
+        }
+    }
+
+    fn order(action: Action, order_type: &str, quantity: f64) -> Order {
+        Order {
+            action,
+            order_type: order_type.to_string(),
+            total_quantity: quantity,
+            ..Default::default()
+        }
+    }
+
+    fn fills(updates: &[Update]) -> Vec<(i32, f64, f64)> {
+        updates
+            .iter()
+            .filter_map(|u| match u {
+                Update::Event(OrderEvent::Fill {
+                    order_id,
+                    shares,
+                    price,
+                    ..
+                }) => Some((*order_id, *shares, *price)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn market_orders_fill_through_the_quote_with_slippage() {
+        let settings = settings();
+        let mut state = SimState::new(100_000.0);
+        state.on_quote(quote(99.9, 100.0, 99.95), &settings);
+
+        let (buy, updates) = state.place("TSLA", order(Action::Buy, "MKT", 100.0), &settings);
+        assert_eq!(fills(&updates), vec![(buy, 100.0, 100.02)]);
+        assert_eq!(state.position("TSLA"), 100.0);
+        assert_eq!(state.cash, 100_000.0 - 10_002.0 - 1.0);
+
+        let (sell, updates) = state.place("TSLA", order(Action::Sell, "MKT", 40.0), &settings);
+        assert_eq!(fills(&updates), vec![(sell, 40.0, 99.88)]);
+        assert_eq!(state.position("TSLA"), 60.0);
+        assert!((state.realized_pnl - (-0.14 * 40.0 - 2.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn limit_and_stop_orders_wait_for_price() {
+        let settings = settings();
+        let mut state = SimState::new(100_000.0);
+        state.on_quote(quote(99.9, 100.0, 99.95), &settings);
+
+        let limit = Order {
+            limit_price: Some(99.5),
+            ..order(Action::Buy, "LMT", 10.0)
+        };
+        let (limit_id, updates) = state.place("TSLA", limit, &settings);
+        assert!(fills(&updates).is_empty());
+        let stop = Order {
+            aux_price: Some(101.0),
+            ..order(Action::Buy, "STP", 10.0)
+        };
+        let (stop_id, _) = state.place("TSLA", stop, &settings);
+
+        let updates = state.on_quote(quote(99.3, 99.4, 99.35), &settings);
+        assert_eq!(fills(&updates), vec![(limit_id, 10.0, 99.4)]);
+        let updates = state.on_quote(quote(101.0, 101.1, 101.05), &settings);
+        assert_eq!(fills(&updates), vec![(stop_id, 10.0, 101.12)]);
+        assert!(state.orders.is_empty());
+        assert_eq!(state.position("TSLA"), 20.0);
+    }
+
+    #[test]
+    fn oca_fills_reduce_and_cancel_siblings() {
+        let settings = settings();
+        let mut state = SimState::new(100_000.0);
+        state.on_quote(quote(99.9, 100.0, 99.95), &settings);
+        state.place("TSLA", order(Action::Buy, "MKT", 50.0), &settings);
+        let stop = Order {
+            aux_price: Some(98.0),
+            oca_group: "scale-out-1-1".to_string(),
+            ..order(Action::Sell, "STP", 50.0)
+        };
+        let target = Order {
+            limit_price: Some(102.0),
+            oca_group: "scale-out-1-1".to_string(),
+            ..order(Action::Sell, "LMT", 50.0)
+        };
+        let (stop_id, _) = state.place("TSLA", stop, &settings);
+        let (target_id, _) = state.place("TSLA", target, &settings);
+
+        let updates = state.on_quote(quote(102.0, 102.1, 102.05), &settings);
+        assert_eq!(fills(&updates), vec![(target_id, 50.0, 102.0)]);
+        assert!(updates.iter().any(|u| matches!(
+            u,
+            Update::Event(OrderEvent::Status { order_id, status, .. })
+                if *order_id == stop_id && status == "Cancelled"
+        )));
+        assert!(state.orders.is_empty());
+        assert_eq!(state.position("TSLA"), 0.0);
+        assert!(state.holdings.is_empty());
+    }
+
+    #[test]
+    fn trailing_stops_ratchet_and_trigger() {
+        let settings = SimulatorConfig {
+            slippage_ticks: 0.0,
+            ..settings()
+        };
+        let mut state = SimState::new(100_000.0);
+        state.on_quote(quote(99.9, 100.0, 100.0), &settings);
+        state.place("TSLA", order(Action::Buy, "MKT", 10.0), &settings);
+        let trail = Order {
+            aux_price: Some(1.0),
+            trail_stop_price: Some(98.0),
+            ..order(Action::Sell, "TRAIL", 10.0)
+        };
+        let (trail_id, _) = state.place("TSLA", trail, &settings);
+        assert_eq!(state.orders[&trail_id].trail_stop, Some(99.0));
+
+        state.on_quote(quote(104.9, 105.0, 105.0), &settings);
+        assert_eq!(state.orders[&trail_id].trail_stop, Some(104.0));
+        let updates = state.on_quote(quote(103.9, 104.0, 103.95), &settings);
+        assert_eq!(fills(&updates), vec![(trail_id, 10.0, 103.9)]);
+    }
+
+    #[test]
+    fn flipping_a_position_resets_its_cost() {
+        let mut state = SimState::new(0.0);
+        assert_eq!(state.apply_fill("TSLA", -10.0, 50.0), None);
+        assert_eq!(state.apply_fill("TSLA", 25.0, 45.0), Some(50.0));
+        assert_eq!(
+            state.holdings["TSLA"],
+            Holding {
+                quantity: 15.0,
+                average_cost: 45.0
+            }
+        );
+    }
+
+    #[test]
+    fn immediate_or_cancel_limits_do_not_rest() {
+        let settings = settings();
+        let mut state = SimState::new(100_000.0);
+        state.on_quote(quote(99.9, 100.0, 99.95), &settings);
+        let ioc = Order {
+            limit_price: Some(99.0),
+            tif: TimeInForce::ImmediateOrCancel,
+            ..order(Action::Buy, "LMT", 10.0)
+        };
+        let (_, updates) = state.place("TSLA", ioc, &settings);
+
+        assert!(fills(&updates).is_empty());
+        assert!(state.orders.is_empty());
+    }
+
+    #[test]
+    fn order_ids_continue_from_earlier_sessions() {
+        let conn = database::open(":memory:").unwrap();
+        assert_eq!(last_order_id(&conn).unwrap(), 0);
+        conn.execute_batch(
+            "INSERT INTO journal_orders (order_id, client_id, perm_id, trade_id, symbol, action,
+                order_type, quantity, status, fills)
+             VALUES (41, 0, 41, 40, 'TSLA', 'SELL', 'STP', 10, 'Filled', 1),
+                    (900, 7, 5000, 900, 'TSLA', 'BUY', 'MKT', 10, 'Filled', 1);",
+        )
+        .unwrap();
+        assert_eq!(last_order_id(&conn).unwrap(), 41);
+    }
+
+    #[test]
+    fn sessions_number_executions_apart() {
+        let first = SimState::new(100_000.0);
+        std::thread::sleep(Duration::from_millis(1));
+        let second = SimState::new(100_000.0);
+        assert_ne!(first.execution_prefix, second.execution_prefix);
+    }
+}
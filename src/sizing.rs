@@ -0,0 +1,161 @@
+use serde::{Deserialize, Serialize};
+
+/// Sizing settings from the `[sizing]` section of the config.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SizingConfig {
+    /// Account value key used as equity, e.g. `NetLiquidation` or `EquityWithLoanValue`.
+    pub equity_basis: String,
+    /// Fixed equity to size against instead of the account value.
+    pub fixed_equity: Option<f64>,
+    pub lot_size: i32,
+    pub max_position_value: Option<f64>,
+}
+
+impl Default for SizingConfig {
+    fn default() -> Self {
+        SizingConfig {
+            equity_basis: "NetLiquidation".to_string(),
+            fixed_equity: None,
+            lot_size: 1,
+            max_position_value: None,
+        }
+    }
+}
+
+/// Caps applied after the risk-based quantity is computed.
+#[derive(Clone, Debug, Default)]
+pub struct SizingLimits {
+    pub lot_size: i32,
+    pub buying_power: Option<f64>,
+    pub max_position_value: Option<f64>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct PositionSize {
+    pub qty: i32,
+    pub equity: f64,
+    pub risk_percent: f64,
+    pub risk_amount: f64,
+    pub risk_per_share: f64,
+    pub position_value: f64,
+    /// Which limit reduced the quantity below the risk-based size, if any.
+    pub capped_by: Option<String>,
+}
+
+/// Sizes a position so a stop-out loses `risk_percent` of `equity`.
+pub fn size_position(
+    equity: f64,
+    entry_price: f64,
+    stop_price: f64,
+    risk_percent: f64,
+    limits: &SizingLimits,
+) -> Result<PositionSize, String> {
+    if !(equity.is_finite() && equity > 0.0) {
+        return Err(format!("Equity {:.2} is not positive", equity));
+    }
+    if !(entry_price.is_finite() && entry_price > 0.0) {
+        return Err(format!("Entry price {:.2} is not positive", entry_price));
+    }
+    if !(risk_percent.is_finite() && risk_percent > 0.0) {
+        return Err(format!("Risk percent {} is not positive", risk_percent));
+    }
+    let risk_per_share = (entry_price - stop_price).abs();
+    if !(risk_per_share.is_finite() && risk_per_share > 0.0) {
+        return Err("Stop price must differ from the entry price".to_string());
+    }
+
+    let lot_size = limits.lot_size.max(1);
+    let risk_amount = equity * risk_percent / 100.0;
+    let mut shares = (risk_amount / risk_per_share).floor();
+    let mut capped_by = None;
+
+    let caps = [
+        ("buying_power", limits.buying_power),
+        ("max_position_value", limits.max_position_value),
+    ];
+    for (name, cap) in caps {
+        if let Some(cap) = cap {
+            let max_shares = (cap / entry_price).floor().max(0.0);
+            if max_shares < shares {
+                shares = max_shares;
+                capped_by = Some(name.to_string());
+            }
+        }
+    }
+
+    let qty = (shares as i64 / lot_size as i64 * lot_size as i64).min(i32::MAX as i64) as i32;
+    if qty <= 0 {
+        return Err(format!(
+            "Risk of {:.2} is too small for a {:.2} stop distance",
+            risk_amount, risk_per_share
+        ));
+    }
+
+    Ok(PositionSize {
+        qty,
+        equity,
+        risk_percent,
+        risk_amount,
+        risk_per_share,
+        position_value: qty as f64 * entry_price,
+        capped_by,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> SizingLimits {
+        SizingLimits {
+            lot_size: 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sizes_by_risk() {
+        let size = size_position(100_000.0, 250.0, 245.0, 0.25, &limits()).unwrap();
+
+        assert_eq!(size.qty, 50);
+        assert_eq!(size.risk_amount, 250.0);
+        assert_eq!(size.capped_by, None);
+    }
+
+    #[test]
+    fn short_stops_above_entry() {
+        let size = size_position(100_000.0, 100.0, 102.0, 0.5, &limits()).unwrap();
+
+        assert_eq!(size.qty, 250);
+    }
+
+    #[test]
+    fn rounds_down_to_lot_size() {
+        let limits = SizingLimits {
+            lot_size: 100,
+            ..Default::default()
+        };
+        let size = size_position(100_000.0, 10.0, 9.0, 0.25, &limits).unwrap();
+
+        assert_eq!(size.qty, 200);
+    }
+
+    #[test]
+    fn caps_by_buying_power_and_position_value() {
+        let limits = SizingLimits {
+            lot_size: 1,
+            buying_power: Some(10_000.0),
+            max_position_value: Some(5_000.0),
+        };
+        let size = size_position(100_000.0, 100.0, 99.0, 1.0, &limits).unwrap();
+
+        assert_eq!(size.qty, 50);
+        assert_eq!(size.capped_by.as_deref(), Some("max_position_value"));
+    }
+
+    #[test]
+    fn rejects_stop_at_entry() {
+        assert!(size_position(100_000.0, 100.0, 100.0, 0.25, &limits()).is_err());
+    }
+}
@@ -0,0 +1,333 @@
+use ibapi::orders::{Action, OcaType, Order};
+
+use crate::ladder::{Ladder, Trail, round_to_tick};
+
+/// One exit leg: a protective stop and an optional target for part of the position.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StopLeg {
+    pub quantity: i32,
+    pub stop_price: f64,
+    pub target_price: Option<f64>,
+    pub trail: Option<Trail>,
+}
+
+impl StopLeg {
+    /// The protective order for this leg: a fixed STP, or a TRAIL starting at the stop price.
+    pub fn stop_order(&self, exit_action: Action) -> Order {
+        let order = Order {
+            action: exit_action,
+            total_quantity: self.quantity as f64,
+            ..Default::default()
+        };
+        match self.trail {
+            None => Order {
+                order_type: "STP".to_string(),
+                aux_price: Some(self.stop_price),
+                ..order
+            },
+            Some(Trail::Amount(amount)) => Order {
+                order_type: "TRAIL".to_string(),
+                aux_price: Some(amount),
+                trail_stop_price: Some(self.stop_price),
+                ..order
+            },
+            Some(Trail::Percent(percent)) => Order {
+                order_type: "TRAIL".to_string(),
+                trailing_percent: Some(percent),
+                trail_stop_price: Some(self.stop_price),
+                ..order
+            },
+        }
+    }
+}
+
+/// Exit orders derived from an entry fill, its original stop and a ladder.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StopPlan {
+    /// Side of every exit order, always the opposite of the entry.
+    pub exit_action: Action,
+    pub legs: Vec<StopLeg>,
+}
+
+impl StopPlan {
+    /// Builds the exit legs for an entry of `qty` filled at `fill` with an original `stop`.
+    ///
+    /// Stops are clamped so they never sit beyond the original stop nor on or through the fill.
+    /// Targets must be positive prices on the profit side of the fill.
+    pub fn build(
+        entry_action: Action,
+        qty: i32,
+        fill: f64,
+        stop: f64,
+        ladder: &Ladder,
+        min_tick: f64,
+    ) -> Result<StopPlan, String> {
+        let tick = if min_tick > 0.0 { min_tick } else { 0.01 };
+        // +1.0 for a long entry (stops below, targets above), -1.0 for a short one.
+        let direction = match entry_action {
+            Action::Buy => 1.0,
+            _ => -1.0,
+        };
+        let risk = (fill - stop) * direction;
+        if !risk.is_finite() || risk <= 0.0 {
+            return Err(format!(
+                "Stop {:.2} is not on the protective side of the fill {:.2}",
+                stop, fill
+            ));
+        }
+        if risk < tick - 1e-9 {
+            return Err(format!(
+                "Stop {:.2} is less than one tick from the fill {:.2}",
+                stop, fill
+            ));
+        }
+
+        // The closest a stop may sit to the fill, and the farthest (the original stop).
+        let nearest = round_to_tick(fill - direction * tick, tick);
+        let farthest = round_to_tick(stop, tick);
+        let (low, high) = if direction > 0.0 {
+            (farthest, nearest)
+        } else {
+            (nearest, farthest)
+        };
+        if low > high {
+            return Err(format!("Stop {:.2} rounds onto the fill {:.2}", stop, fill));
+        }
+
+        let sizes = ladder.sizes(qty)?;
+        let mut legs = Vec::new();
+        for (i, (tranche, quantity)) in ladder.tranches.iter().zip(sizes).enumerate() {
+            let target_price = tranche
+                .target
+                .as_ref()
+                .map(|target| target.resolve(fill, risk, direction, tick));
+            if let Some(target) = target_price {
+                if target <= 0.0 {
+                    return Err(format!(
+                        "Tranche {} target {:.2} is not a positive price",
+                        i + 1,
+                        target
+                    ));
+                }
+                // A target on the stop's side of the fill would exit at a loss straight away.
+                if (target - fill) * direction <= 0.0 {
+                    return Err(format!(
+                        "Tranche {} target {:.2} is not on the profit side of the fill {:.2}",
+                        i + 1,
+                        target,
+                        fill
+                    ));
+                }
+            }
+            legs.push(StopLeg {
+                quantity,
+                stop_price: tranche
+                    .stop
+                    .resolve(fill, risk, -direction, tick)
+                    .clamp(low, high),
+                target_price,
+                trail: tranche.trail.clone(),
+            });
+        }
+
+        Ok(StopPlan {
+            exit_action: entry_action.reverse(),
+            legs,
+        })
+    }
+
+    /// The stop and target orders for entry `trade_id`, in the order they are placed.
+    ///
+    /// Each stop/target pair shares an OCA group so a fill on one side reduces the
+    /// quantity left on the other.
+    pub fn exit_orders(&self, trade_id: i32) -> Vec<Order> {
+        let mut orders = Vec::new();
+        for (i, leg) in self.legs.iter().enumerate() {
+            let oca_group = format!("scale-out-{}-{}", trade_id, i + 1);
+            orders.push(Order {
+                oca_group: oca_group.clone(),
+                oca_type: OcaType::ReduceWithBlock,
+                ..leg.stop_order(self.exit_action)
+            });
+            if let Some(target_price) = leg.target_price {
+                orders.push(Order {
+                    action: self.exit_action,
+                    order_type: "LMT".to_string(),
+                    total_quantity: leg.quantity as f64,
+                    limit_price: Some(target_price),
+                    oca_group,
+                    oca_type: OcaType::ReduceWithBlock,
+                    ..Default::default()
+                });
+            }
+        }
+        orders
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ladder::{Level, Tranche};
+    use proptest::prelude::*;
+
+    #[test]
+    fn long_entry_places_sell_stops_below_fill() {
+        let plan =
+            StopPlan::build(Action::Buy, 100, 250.0, 244.0, &Ladder::thirds(), 0.01).unwrap();
+
+        assert_eq!(plan.exit_action, Action::Sell);
+        let stops: Vec<f64> = plan.legs.iter().map(|l| l.stop_price).collect();
+        assert_eq!(stops, vec![248.0, 246.0, 244.0]);
+        let targets: Vec<Option<f64>> = plan.legs.iter().map(|l| l.target_price).collect();
+        assert_eq!(targets, vec![Some(256.0), Some(262.0), Some(268.0)]);
+        let sizes: Vec<i32> = plan.legs.iter().map(|l| l.quantity).collect();
+        assert_eq!(sizes, vec![33, 33, 34]);
+    }
+
+    #[test]
+    fn short_entry_places_buy_stops_above_fill() {
+        let plan =
+            StopPlan::build(Action::Sell, 30, 100.0, 103.0, &Ladder::thirds(), 0.01).unwrap();
+
+        assert_eq!(plan.exit_action, Action::Buy);
+        let stops: Vec<f64> = plan.legs.iter().map(|l| l.stop_price).collect();
+        assert_eq!(stops, vec![101.0, 102.0, 103.0]);
+        assert_eq!(plan.legs[0].target_price, Some(97.0));
+    }
+
+    #[test]
+    fn stop_through_fill_is_rejected() {
+        assert!(StopPlan::build(Action::Buy, 10, 100.0, 101.0, &Ladder::thirds(), 0.01).is_err());
+        assert!(StopPlan::build(Action::Sell, 10, 100.0, 99.0, &Ladder::thirds(), 0.01).is_err());
+    }
+
+    #[test]
+    fn exit_orders_pair_stops_and_targets_by_oca_group() {
+        let plan =
+            StopPlan::build(Action::Buy, 100, 250.0, 244.0, &Ladder::thirds(), 0.01).unwrap();
+        let orders = plan.exit_orders(7);
+
+        assert_eq!(orders.len(), 6);
+        assert_eq!(orders[0].order_type, "STP");
+        assert_eq!(orders[1].order_type, "LMT");
+        assert_eq!(orders[1].limit_price, Some(256.0));
+        assert_eq!(orders[0].oca_group, "scale-out-7-1");
+        assert_eq!(orders[1].oca_group, "scale-out-7-1");
+        assert_eq!(orders[5].oca_group, "scale-out-7-3");
+        assert!(orders.iter().all(|o| o.action == Action::Sell));
+    }
+
+    #[test]
+    fn stops_beyond_original_stop_are_clamped() {
+        let ladder = Ladder {
+            tranches: vec![Tranche {
+                size_percent: 100.0,
+                stop: Level::Percent(10.0),
+                target: None,
+                trail: None,
+            }],
+            rules: Vec::new(),
+        };
+        let plan = StopPlan::build(Action::Buy, 10, 100.0, 98.0, &ladder, 0.01).unwrap();
+
+        assert_eq!(plan.legs[0].stop_price, 98.0);
+    }
+
+    #[test]
+    fn prices_respect_min_tick() {
+        let plan = StopPlan::build(Action::Buy, 9, 10.0, 9.0, &Ladder::thirds(), 0.05).unwrap();
+
+        for leg in &plan.legs {
+            let ticks = leg.stop_price / 0.05;
+            assert!((ticks - ticks.round()).abs() < 1e-6, "{}", leg.stop_price);
+        }
+    }
+
+    #[test]
+    fn trailing_legs_become_trail_orders() {
+        let ladder = Ladder {
+            tranches: vec![Tranche {
+                size_percent: 100.0,
+                stop: Level::R(1.0),
+                target: None,
+                trail: Some(Trail::Percent(1.5)),
+            }],
+            rules: Vec::new(),
+        };
+        let plan = StopPlan::build(Action::Buy, 10, 100.0, 98.0, &ladder, 0.01).unwrap();
+        let order = plan.legs[0].stop_order(plan.exit_action);
+
+        assert_eq!(order.order_type, "TRAIL");
+        assert_eq!(order.action, Action::Sell);
+        assert_eq!(order.trailing_percent, Some(1.5));
+        assert_eq!(order.trail_stop_price, Some(98.0));
+        assert_eq!(order.aux_price, None);
+    }
+
+    fn ladder_strategy() -> impl Strategy<Value = Ladder> {
+        prop::collection::vec(0.05f64..3.0, 1..5).prop_map(|stops| {
+            let size = 100.0 / stops.len() as f64;
+            Ladder {
+                tranches: stops
+                    .into_iter()
+                    .map(|r| Tranche {
+                        size_percent: size,
+                        stop: Level::R(r),
+                        target: Some(Level::R(r * 2.0)),
+                        trail: None,
+                    })
+                    .collect(),
+                rules: Vec::new(),
+            }
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn stops_sit_between_fill_and_original_stop(
+            long in any::<bool>(),
+            fill in 1.0f64..1000.0,
+            risk_ticks in 1u32..2000,
+            qty in 20i32..10_000,
+            ladder in ladder_strategy(),
+        ) {
+            let fill = round_to_tick(fill, 0.01);
+            let risk = risk_ticks as f64 * 0.01;
+            prop_assume!(fill - risk > 0.0);
+            let (action, stop) = if long {
+                (Action::Buy, round_to_tick(fill - risk, 0.01))
+            } else {
+                (Action::Sell, round_to_tick(fill + risk, 0.01))
+            };
+
+            // Far short targets on cheap fills would sit at or below zero.
+            let farthest_r = ladder
+                .tranches
+                .iter()
+                .filter_map(|t| match t.target {
+                    Some(Level::R(r)) => Some(r),
+                    _ => None,
+                })
+                .fold(0.0, f64::max);
+            let lowest_target = round_to_tick(fill - farthest_r * risk, 0.01);
+            let plan = match StopPlan::build(action, qty, fill, stop, &ladder, 0.01) {
+                Ok(plan) => plan,
+                Err(e) => {
+                    prop_assert!(!long && lowest_target <= 0.0, "{}", e);
+                    return Ok(());
+                }
+            };
+
+            prop_assert_eq!(plan.exit_action, action.reverse());
+            prop_assert_eq!(plan.legs.iter().map(|l| l.quantity).sum::<i32>(), qty);
+            for leg in &plan.legs {
+                if long {
+                    prop_assert!(leg.stop_price < fill && leg.stop_price >= stop);
+                } else {
+                    prop_assert!(leg.stop_price > fill && leg.stop_price <= stop);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,563 @@
+//! Entry and exit workflows shared by every `Broker`.
+//!
+//! Backends only place, cancel and modify single orders; sizing the ladder, journaling the
+//! trade, risk-checking entries and working out close and reverse orders happen here, once.
+
+use std::collections::HashMap;
+
+use ibapi::orders::{Action, Order, builder::OrderType};
+use tokio::sync::broadcast;
+
+use crate::broker::{Broker, OrderRequest};
+use crate::config::CONFIG;
+use crate::exit_rules::{self, ManagedTrade};
+use crate::journal::{self, RiskSnapshot, TradeRequest};
+use crate::kill_switch::{self, TRADING_STATUS};
+use crate::ladder::Ladder;
+use crate::order_book::{self, ORDER_BOOK, TrackedOrder};
+use crate::positions::{self, CloseReport, ExitStyle};
+use crate::risk::OrderContext;
+use crate::stop_plan::StopPlan;
+
+pub(crate) const NOT_CONNECTED: &str = "Not connected to the broker";
+
+/// Parses an entry's BUY or SELL, in any case.
+pub(crate) fn parse_action(action: &str) -> Result<Action, String> {
+    match action.to_uppercase().as_str() {
+        "BUY" => Ok(Action::Buy),
+        "SELL" => Ok(Action::Sell),
+        _ => Err(format!("Unknown action {}", action)),
+    }
+}
+
+/// Places the entry for `request` and, once it fills, its ladder's exits.
+//TODO other order types where different stops are needed
+pub async fn submit_order<B: Broker>(
+    broker: &B,
+    request: &OrderRequest,
+    qty: i32,
+) -> (bool, String) {
+    let Some(client_id) = broker.client_id().await else {
+        return (false, NOT_CONNECTED.to_string());
+    };
+    if let Some(reason) = kill_switch::entry_block().await {
+        return (false, format!("Order entry disabled: {}", reason));
+    }
+    let entry_action = match parse_action(&request.action) {
+        Ok(action) => action,
+        Err(e) => return (false, e),
+    };
+    let ticker = request.ticker.as_str();
+    let entry_price = Some(request.entry_price).filter(|p| *p > 0.0);
+    let trade = TradeRequest {
+        symbol: ticker.to_string(),
+        action: entry_action.to_string(),
+        quantity: qty,
+        entry_price,
+        stop_price: request.stop_price,
+        ladder: request.ladder.clone(),
+    };
+
+    let ladder = match Ladder::preset(request.ladder.as_deref()) {
+        Ok(ladder) => ladder,
+        Err(e) => return (false, e),
+    };
+    if let Err(e) = ladder.sizes(qty) {
+        return (false, e);
+    }
+    if let Err(e) = pre_trade_check(
+        broker,
+        ticker,
+        entry_action == Action::Buy,
+        qty,
+        entry_price,
+    )
+    .await
+    {
+        return (false, e);
+    }
+    let min_tick = broker.min_tick(ticker).await;
+    // Refuse a stop on the wrong side before the entry goes out, not after it fills.
+    let Some(reference) =
+        reference_price(broker, ticker, entry_action == Action::Buy, entry_price).await
+    else {
+        return (
+            false,
+            format!("No price for {} to check the stop against", ticker),
+        );
+    };
+    if let Err(e) = StopPlan::build(
+        entry_action,
+        qty,
+        reference,
+        request.stop_price,
+        &ladder,
+        min_tick,
+    ) {
+        return (false, e);
+    }
+    let order_ref = request.client_order_key.clone().unwrap_or_default();
+
+    match OrderType::Market {
+        OrderType::Market => {
+            let order = Order {
+                action: entry_action,
+                order_type: "MKT".to_string(),
+                total_quantity: qty as f64,
+                order_ref,
+                ..Default::default()
+            };
+
+            // Subscribe before placing so the entry's fill cannot slip past us.
+            let updates = ORDER_BOOK.read().await.subscribe();
+            let snapshot = RiskSnapshot {
+                trading_status: TRADING_STATUS.read().await.clone(),
+                limits: CONFIG.risk.clone(),
+                risk_percent: CONFIG.risk_percent(),
+            };
+            let order_id = match broker.place_order(ticker, &order).await {
+                Ok(order_id) => order_id,
+                Err(e) => return (false, e),
+            };
+            journal::open_trade(order_id, client_id, &trade, &snapshot);
+
+            let entry = match order_book::wait_for_terminal(
+                updates,
+                client_id,
+                order_id,
+                CONFIG.timeouts.entry_fill(),
+            )
+            .await
+            {
+                Some(entry) if entry.filled > 0.0 => entry,
+                Some(entry) => {
+                    return (
+                        false,
+                        format!("Market order was not filled: {}", entry.status),
+                    );
+                }
+                None => match cancel_late_entry(broker, client_id, order_id).await {
+                    Ok(entry) => entry,
+                    Err(e) => return (false, e),
+                },
+            };
+            // A late cancel can leave part of the entry filled; protect what we hold.
+            let filled = entry.filled as i32;
+            // The stop was checked against the quote; this re-anchors the legs on the fill.
+
+            let plan = match StopPlan::build(
+                entry_action,
+                filled,
+                entry.average_fill_price,
+                request.stop_price,
+                &ladder,
+                min_tick,
+            ) {
+                Ok(plan) => plan,
+                Err(e) => {
+                    return (
+                        false,
+                        format!("Entry filled but stops were not placed: {}", e),
+                    );
+                }
+            };
+
+            let exits = plan.exit_orders(order_id);
+            let mut failures = Vec::new();
+            for exit in &exits {
+                if let Err(e) = broker.place_order(ticker, exit).await {
+                    failures.push(format!("{} {}: {}", exit.oca_group, exit.order_type, e));
+                }
+            }
+
+            if !ladder.rules.is_empty() {
+                tokio::spawn(exit_rules::manage(
+                    broker.clone(),
+                    ManagedTrade {
+                        trade_id: order_id,
+                        symbol: ticker.to_string(),
+                        is_long: entry_action == Action::Buy,
+                        fill: entry.average_fill_price,
+                        min_tick,
+                        rules: ladder.rules.clone(),
+                    },
+                ));
+            }
+
+            if !failures.is_empty() {
+                return (
+                    false,
+                    format!(
+                        "Entry filled but exit leg {} failed; {} of {} exit orders placed.",
+                        failures.join(", "),
+                        exits.len() - failures.len(),
+                        exits.len()
+                    ),
+                );
+            }
+
+            (
+                true,
+                format!(
+                    "Market order {} to {} {} shares of {} filled at ${:.2}, {} exit legs placed.",
+                    order_id,
+                    request.action,
+                    filled,
+                    ticker,
+                    entry.average_fill_price,
+                    plan.legs.len()
+                ),
+            )
+        }
+        OrderType::Limit => {
+            let order = Order {
+                action: entry_action,
+                order_type: "STP".to_string(),
+                total_quantity: qty as f64,
+                aux_price: Some(request.stop_price),
+                order_ref,
+                ..Default::default()
+            };
+            if let Err(e) = broker.place_order(ticker, &order).await {
+                return (false, e);
+            }
+            (
+                true,
+                format!(
+                    "Limit order to {} {} shares of {} at ${:.2} submitted.",
+                    request.action, qty, ticker, request.entry_price
+                ),
+            )
+        }
+        OrderType::Stop => {
+            let order = Order {
+                action: entry_action,
+                order_type: "STP".to_string(),
+                total_quantity: qty as f64,
+                aux_price: Some(request.stop_price),
+                order_ref,
+                ..Default::default()
+            };
+            if let Err(e) = broker.place_order(ticker, &order).await {
+                return (false, e);
+            }
+            (
+                true,
+                format!(
+                    "Stop order to {} {} shares of {} at stop ${:.2} submitted.",
+                    request.action, qty, ticker, request.stop_price
+                ),
+            )
+        }
+        _ => (
+            false,
+            "Order submission logic not yet implemented.".to_string(),
+        ),
+    }
+}
+
+/// Closes `percent` of the position, shrinking its exits to what is left, or all of it.
+pub async fn close_position<B: Broker>(
+    broker: &B,
+    symbol: &str,
+    percent: Option<f64>,
+    style: ExitStyle,
+) -> Result<CloseReport, String> {
+    broker.client_id().await.ok_or(NOT_CONNECTED)?;
+    let positions = position_sizes(broker).await?;
+    let position = positions.get(symbol).copied().unwrap_or(0.0);
+    let quantity = positions::close_quantity(position, percent)?;
+    let remaining = position.abs() - quantity;
+
+    if remaining > 0.0 {
+        resize_exits(broker, symbol, remaining / position.abs()).await?;
+    } else {
+        let (cancelled, message) = broker.cancel_all(Some(symbol)).await;
+        if !cancelled {
+            return Err(message);
+        }
+    }
+
+    let pending = send_exit(broker, symbol, closing_action(position), quantity, style).await?;
+    exit_report(symbol, pending).await
+}
+
+/// Cancels every order, whichever client placed it, and closes every position.
+pub async fn close_all<B: Broker>(
+    broker: &B,
+    style: ExitStyle,
+) -> Vec<Result<CloseReport, String>> {
+    if broker.client_id().await.is_none() {
+        return vec![Err(NOT_CONNECTED.to_string())];
+    }
+    // Exits left working by another session would reopen the position once it is flat.
+    let (cancelled, message) = broker.global_cancel().await;
+    if !cancelled {
+        return vec![Err(message)];
+    }
+    let positions = match position_sizes(broker).await {
+        Ok(positions) => positions,
+        Err(e) => return vec![Err(e)],
+    };
+
+    // Send every exit before waiting on any of them.
+    let mut pending = Vec::new();
+    for (symbol, position) in positions.iter().filter(|(_, p)| **p != 0.0) {
+        let sent = send_exit(
+            broker,
+            symbol,
+            closing_action(*position),
+            position.abs(),
+            style,
+        )
+        .await;
+        pending.push((symbol.clone(), sent));
+    }
+
+    let mut results = Vec::new();
+    for (symbol, sent) in pending {
+        results.push(match sent {
+            Ok(sent) => exit_report(&symbol, sent).await,
+            Err(e) => Err(format!("{}: {}", symbol, e)),
+        });
+    }
+    results
+}
+
+/// Turns the position around with one order for twice its size.
+pub async fn reverse_position<B: Broker>(
+    broker: &B,
+    symbol: &str,
+    style: ExitStyle,
+) -> Result<CloseReport, String> {
+    broker.client_id().await.ok_or(NOT_CONNECTED)?;
+    if let Some(reason) = kill_switch::entry_block().await {
+        return Err(format!("Order entry disabled: {}", reason));
+    }
+    let positions = position_sizes(broker).await?;
+    let position = positions.get(symbol).copied().unwrap_or(0.0);
+    let quantity = positions::close_quantity(position, None)?;
+    let action = closing_action(position);
+    // The new side is an entry, so the whole reversing order goes through the risk
+    // gate against the current position, as it would land on the account.
+    pre_trade_check(
+        broker,
+        symbol,
+        action == Action::Buy,
+        (quantity * 2.0) as i32,
+        None,
+    )
+    .await?;
+
+    let (cancelled, message) = broker.cancel_all(Some(symbol)).await;
+    if !cancelled {
+        return Err(message);
+    }
+    let pending = send_exit(broker, symbol, action, quantity * 2.0, style).await?;
+    exit_report(symbol, pending).await
+}
+
+/// Closes every position at market.
+pub async fn flatten_all<B: Broker>(broker: &B) -> (bool, String) {
+    let results = close_all(broker, ExitStyle::Market).await;
+    let failures: Vec<String> = results.iter().filter_map(|r| r.clone().err()).collect();
+    if failures.is_empty() {
+        (
+            true,
+            format!("Sent orders to close {} positions.", results.len()),
+        )
+    } else {
+        (false, failures.join("; "))
+    }
+}
+
+/// Locks entries and cancels every order, optionally closing all positions too.
+pub async fn engage_kill_switch<B: Broker>(broker: &B, flatten: bool) -> (bool, String) {
+    TRADING_STATUS
+        .write()
+        .await
+        .lock("kill switch engaged".to_string());
+    if broker.client_id().await.is_none() {
+        return (
+            false,
+            "Entries locked, but not connected to the broker".to_string(),
+        );
+    }
+    let (cancelled, message) = broker.global_cancel().await;
+    if !cancelled {
+        return (
+            false,
+            format!("Entries locked, but cancel failed: {}", message),
+        );
+    }
+    if flatten {
+        return flatten_all(broker).await;
+    }
+    (true, "Entries locked and all orders cancelled.".to_string())
+}
+
+/// The price an entry is expected to fill near: the requested entry price, else the side
+/// of the quote a market order would take, else the last trade.
+async fn reference_price<B: Broker>(
+    broker: &B,
+    symbol: &str,
+    is_buy: bool,
+    entry_price: Option<f64>,
+) -> Option<f64> {
+    if entry_price.is_some() {
+        return entry_price;
+    }
+    let quoted = broker
+        .quote(symbol)
+        .await
+        .ok()
+        .and_then(|q| if is_buy { q.ask } else { q.bid }.or(q.last))
+        .filter(|p| *p > 0.0);
+    match quoted {
+        Some(price) => Some(price),
+        None => broker.last_price(symbol).await,
+    }
+}
+
+async fn pre_trade_check<B: Broker>(
+    broker: &B,
+    symbol: &str,
+    is_buy: bool,
+    qty: i32,
+    entry_price: Option<f64>,
+) -> Result<(), String> {
+    let limits = &CONFIG.risk;
+    let quote = if limits.needs_quote(entry_price) {
+        broker.last_price(symbol).await
+    } else {
+        None
+    };
+    let positions = position_sizes(broker).await?;
+    let order = OrderContext {
+        symbol,
+        is_buy,
+        qty,
+        entry_price,
+        quote,
+        positions: &positions,
+    };
+    limits.check_entry(&order).await
+}
+
+/// Signed share positions keyed by symbol.
+async fn position_sizes<B: Broker>(broker: &B) -> Result<HashMap<String, f64>, String> {
+    let held = broker.positions().await.ok_or("Could not read positions")?;
+    let mut positions = HashMap::new();
+    for position in held {
+        *positions.entry(position.symbol).or_insert(0.0) += position.quantity;
+    }
+    Ok(positions)
+}
+
+fn closing_action(position: f64) -> Action {
+    if position > 0.0 {
+        Action::Sell
+    } else {
+        Action::Buy
+    }
+}
+
+/// Cancels a market entry that did not fill in time and waits for where it ends up.
+/// Shares that filled before the cancel landed come back as a partially filled entry.
+async fn cancel_late_entry<B: Broker>(
+    broker: &B,
+    client_id: i32,
+    order_id: i32,
+) -> Result<TrackedOrder, String> {
+    let updates = ORDER_BOOK.read().await.subscribe();
+    let (_, cancel) = broker.cancel_order(order_id).await;
+    let timeout = CONFIG.timeouts.exit_fill();
+    let entry = match order_book::wait_for_terminal(updates, client_id, order_id, timeout).await {
+        Some(entry) => Some(entry),
+        None => ORDER_BOOK.read().await.get(client_id, order_id),
+    };
+    match entry {
+        Some(entry) if !entry.is_working() && entry.filled > 0.0 => Ok(entry),
+        Some(entry) if !entry.is_working() => Err(format!(
+            "Market order {} was not filled in time and was cancelled: {}",
+            order_id, cancel
+        )),
+        _ => Err(format!(
+            "Market order {} was not filled in time and may still be working: {}",
+            order_id, cancel
+        )),
+    }
+}
+
+/// An exit order that was sent, with a receiver subscribed before it went out.
+struct PendingExit {
+    client_id: i32,
+    order_id: i32,
+    updates: broadcast::Receiver<TrackedOrder>,
+}
+
+/// Places an exit order and returns it with its updates.
+async fn send_exit<B: Broker>(
+    broker: &B,
+    symbol: &str,
+    action: Action,
+    quantity: f64,
+    style: ExitStyle,
+) -> Result<PendingExit, String> {
+    let client_id = broker.client_id().await.ok_or(NOT_CONNECTED)?;
+    let quote = match style {
+        ExitStyle::Market => None,
+        ExitStyle::MarketableLimit => broker.last_price(symbol).await,
+    };
+    let order = positions::exit_order(action, quantity, style, quote)?;
+    let updates = ORDER_BOOK.read().await.subscribe();
+    let order_id = broker.place_order(symbol, &order).await?;
+    Ok(PendingExit {
+        client_id,
+        order_id,
+        updates,
+    })
+}
+
+/// Waits briefly for an exit to finish and reports what filled.
+async fn exit_report(symbol: &str, pending: PendingExit) -> Result<CloseReport, String> {
+    let PendingExit {
+        client_id,
+        order_id,
+        updates,
+    } = pending;
+    let timeout = CONFIG.timeouts.exit_fill();
+    let order = match order_book::wait_for_terminal(updates, client_id, order_id, timeout).await {
+        Some(order) => Some(order),
+        None => ORDER_BOOK.read().await.get(client_id, order_id),
+    };
+    order
+        .map(|order| CloseReport::new(symbol, &order))
+        .ok_or(format!("Order {} is not tracked", order_id))
+}
+
+/// Shrinks the symbol's working exits to `fraction` of their size after a partial close.
+async fn resize_exits<B: Broker>(broker: &B, symbol: &str, fraction: f64) -> Result<(), String> {
+    let client_id = broker.client_id().await.ok_or(NOT_CONNECTED)?;
+    let exits: Vec<TrackedOrder> = ORDER_BOOK
+        .read()
+        .await
+        .list()
+        .into_iter()
+        .filter(|o| o.is_working() && o.client_id == client_id && o.symbol == symbol)
+        .collect();
+    for exit in exits {
+        let quantity = (exit.remaining * fraction).floor();
+        let (ok, message) = if quantity < 1.0 {
+            broker.cancel_order(exit.order_id).await
+        } else {
+            broker
+                .modify_order(exit.order_id, Some(exit.filled + quantity), None, None)
+                .await
+        };
+        if !ok {
+            return Err(message);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,512 @@
+use std::time::Duration;
+
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use time::{
+    OffsetDateTime, Time, format_description::well_known::Rfc3339, macros::format_description,
+};
+
+use crate::broker::{Broker, OrderRequest, Quote};
+use crate::database::DATABASE;
+use crate::order_book;
+use crate::router;
+
+/// How often pending triggers are checked against the quote cache.
+const TRIGGER_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A market condition; a trigger fires when all of its conditions hold at once.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "condition", rename_all = "snake_case")]
+pub enum Condition {
+    /// The last price moves from below `price` to at or above it.
+    CrossesAbove { price: f64 },
+    /// The last price moves from above `price` to at or below it.
+    CrossesBelow { price: f64 },
+    /// The last price trades above the high of day seen when the trigger was armed.
+    BreaksHod,
+    /// The last price trades below the low of day seen when the trigger was armed.
+    BreaksLod,
+    /// The time is at or after `time`, `HH:MM` in UTC.
+    AfterTime { time: String },
+    /// The time is before `time`, `HH:MM` in UTC.
+    BeforeTime { time: String },
+    /// The bid/ask spread is below `max`.
+    SpreadBelow { max: f64 },
+    /// Day volume is above `shares`.
+    VolumeAbove { shares: f64 },
+}
+
+impl Condition {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Condition::AfterTime { time } | Condition::BeforeTime { time } => {
+                parse_time(time).map(|_| ())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn parse_time(time: &str) -> Result<Time, String> {
+    Time::parse(time, format_description!("[hour]:[minute]"))
+        .map_err(|e| format!("Invalid time {}: {}", time, e))
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerStatus {
+    Pending,
+    Fired,
+    Failed,
+    Expired,
+    Cancelled,
+}
+
+/// A stored order request waiting on market conditions.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Trigger {
+    pub id: i64,
+    pub conditions: Vec<Condition>,
+    pub order: OrderRequest,
+    /// RFC3339 time after which the trigger expires unfired.
+    pub expires_at: Option<String>,
+    pub status: TriggerStatus,
+    pub created_at: String,
+    pub fired_at: Option<String>,
+    /// The order result once fired.
+    pub result: Option<String>,
+    /// High and low of day captured when the trigger was armed, for the HOD/LOD conditions.
+    pub hod: Option<f64>,
+    pub lod: Option<f64>,
+    /// Last price seen on the previous check, for the cross conditions.
+    pub last_price: Option<f64>,
+}
+
+/// A trigger as submitted to `POST /triggers` and `PUT /triggers/{id}`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct NewTrigger {
+    pub conditions: Vec<Condition>,
+    pub order: OrderRequest,
+    pub expires_at: Option<String>,
+}
+
+impl NewTrigger {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.conditions.is_empty() {
+            return Err("A trigger needs at least one condition".to_string());
+        }
+        for condition in &self.conditions {
+            condition.validate()?;
+        }
+        if let Some(expires_at) = &self.expires_at {
+            OffsetDateTime::parse(expires_at, &Rfc3339)
+                .map_err(|e| format!("Invalid expires_at {}: {}", expires_at, e))?;
+        }
+        Ok(())
+    }
+}
+
+impl Trigger {
+    fn needs_day_range(&self) -> bool {
+        self.conditions
+            .iter()
+            .any(|c| matches!(c, Condition::BreaksHod | Condition::BreaksLod))
+            && (self.hod.is_none() || self.lod.is_none())
+    }
+
+    pub fn is_expired(&self, now: OffsetDateTime) -> bool {
+        self.expires_at
+            .as_deref()
+            .and_then(|at| OffsetDateTime::parse(at, &Rfc3339).ok())
+            .is_some_and(|at| now >= at)
+    }
+
+    /// Whether every condition holds for `quote` at `now`.
+    pub fn is_met(&self, quote: &Quote, now: OffsetDateTime) -> bool {
+        let time_of_day = now.to_offset(time::UtcOffset::UTC).time();
+        self.conditions.iter().all(|condition| match condition {
+            Condition::CrossesAbove { price } => {
+                matches!((self.last_price, quote.last), (Some(prev), Some(last)) if prev < *price && last >= *price)
+            }
+            Condition::CrossesBelow { price } => {
+                matches!((self.last_price, quote.last), (Some(prev), Some(last)) if prev > *price && last <= *price)
+            }
+            Condition::BreaksHod => {
+                matches!((self.hod, quote.last), (Some(hod), Some(last)) if last > hod)
+            }
+            Condition::BreaksLod => {
+                matches!((self.lod, quote.last), (Some(lod), Some(last)) if last < lod)
+            }
+            Condition::AfterTime { time } => parse_time(time).is_ok_and(|t| time_of_day >= t),
+            Condition::BeforeTime { time } => parse_time(time).is_ok_and(|t| time_of_day < t),
+            Condition::SpreadBelow { max } => quote.spread().is_some_and(|s| s < *max),
+            Condition::VolumeAbove { shares } => quote.volume.is_some_and(|v| v > *shares),
+        })
+    }
+}
+
+pub fn create_tables(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS triggers (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            conditions TEXT NOT NULL,
+            request TEXT NOT NULL,
+            expires_at TEXT,
+            status TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            fired_at TEXT,
+            result TEXT,
+            hod REAL,
+            lod REAL,
+            last_price REAL
+        );",
+    )
+}
+
+pub fn insert(conn: &Connection, new: &NewTrigger) -> rusqlite::Result<Trigger> {
+    let trigger = Trigger {
+        id: 0,
+        conditions: new.conditions.clone(),
+        order: new.order.clone(),
+        expires_at: new.expires_at.clone(),
+        status: TriggerStatus::Pending,
+        created_at: order_book::now(),
+        fired_at: None,
+        result: None,
+        hod: None,
+        lod: None,
+        last_price: None,
+    };
+    conn.execute(
+        "INSERT INTO triggers (conditions, request, expires_at, status, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            to_json(&trigger.conditions),
+            to_json(&trigger.order),
+            trigger.expires_at,
+            to_json(&trigger.status),
+            trigger.created_at,
+        ],
+    )?;
+    Ok(Trigger {
+        id: conn.last_insert_rowid(),
+        ..trigger
+    })
+}
+
+/// Replaces a pending trigger's definition; returns false if it is missing or no longer pending.
+pub fn update(conn: &Connection, id: i64, new: &NewTrigger) -> rusqlite::Result<bool> {
+    let updated = conn.execute(
+        "UPDATE triggers SET conditions = ?2, request = ?3, expires_at = ?4,
+            hod = NULL, lod = NULL, last_price = NULL
+         WHERE id = ?1 AND status = ?5",
+        params![
+            id,
+            to_json(&new.conditions),
+            to_json(&new.order),
+            new.expires_at,
+            to_json(&TriggerStatus::Pending),
+        ],
+    )?;
+    Ok(updated > 0)
+}
+
+/// Stores the levels and last price seen while the trigger is still pending.
+pub fn record_check(conn: &Connection, trigger: &Trigger) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE triggers SET hod = ?2, lod = ?3, last_price = ?4 WHERE id = ?1 AND status = ?5",
+        params![
+            trigger.id,
+            trigger.hod,
+            trigger.lod,
+            trigger.last_price,
+            to_json(&TriggerStatus::Pending),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Moves a trigger from `from` to `to`; returns false if it was not in `from`, so only one
+/// caller wins a race such as firing against cancelling.
+pub fn set_status(
+    conn: &Connection,
+    id: i64,
+    from: TriggerStatus,
+    to: TriggerStatus,
+    result: Option<&str>,
+) -> rusqlite::Result<bool> {
+    let fired_at =
+        (to == TriggerStatus::Fired && from == TriggerStatus::Pending).then(order_book::now);
+    let updated = conn.execute(
+        "UPDATE triggers SET status = ?3, fired_at = COALESCE(?4, fired_at),
+            result = COALESCE(?5, result)
+         WHERE id = ?1 AND status = ?2",
+        params![id, to_json(&from), to_json(&to), fired_at, result],
+    )?;
+    Ok(updated > 0)
+}
+
+pub fn get(conn: &Connection, id: i64) -> rusqlite::Result<Option<Trigger>> {
+    conn.query_row(
+        "SELECT id, conditions, request, expires_at, status, created_at, fired_at, result,
+            hod, lod, last_price
+         FROM triggers WHERE id = ?1",
+        params![id],
+        row_to_trigger,
+    )
+    .optional()
+}
+
+pub fn list(conn: &Connection, status: Option<TriggerStatus>) -> rusqlite::Result<Vec<Trigger>> {
+    let mut statement = conn.prepare(
+        "SELECT id, conditions, request, expires_at, status, created_at, fired_at, result,
+            hod, lod, last_price
+         FROM triggers WHERE (?1 IS NULL OR status = ?1) ORDER BY id",
+    )?;
+    let rows = statement.query_map(params![status.map(|s| to_json(&s))], row_to_trigger)?;
+    rows.collect()
+}
+
+fn row_to_trigger(row: &rusqlite::Row) -> rusqlite::Result<Trigger> {
+    Ok(Trigger {
+        id: row.get(0)?,
+        conditions: from_json(row, 1)?,
+        order: from_json(row, 2)?,
+        expires_at: row.get(3)?,
+        status: from_json(row, 4)?,
+        created_at: row.get(5)?,
+        fired_at: row.get(6)?,
+        result: row.get(7)?,
+        hod: row.get(8)?,
+        lod: row.get(9)?,
+        last_price: row.get(10)?,
+    })
+}
+
+fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+fn from_json<T: serde::de::DeserializeOwned>(
+    row: &rusqlite::Row,
+    index: usize,
+) -> rusqlite::Result<T> {
+    let text: String = row.get(index)?;
+    serde_json::from_str(&text).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, Box::new(e))
+    })
+}
+
+/// Checks pending triggers against the quote cache and fires those whose conditions are met.
+pub async fn run<B: Broker>(broker: B) {
+    let mut timer = tokio::time::interval(TRIGGER_CHECK_INTERVAL);
+    loop {
+        timer.tick().await;
+        let pending = {
+            let conn = DATABASE.lock().unwrap();
+            list(&conn, Some(TriggerStatus::Pending)).unwrap_or_default()
+        };
+        for trigger in pending {
+            check(&broker, trigger).await;
+        }
+    }
+}
+
+async fn check<B: Broker>(broker: &B, mut trigger: Trigger) {
+    let now = OffsetDateTime::now_utc();
+    if trigger.is_expired(now) {
+        let conn = DATABASE.lock().unwrap();
+        if let Err(e) = set_status(
+            &conn,
+            trigger.id,
+            TriggerStatus::Pending,
+            TriggerStatus::Expired,
+            None,
+        ) {
+            println!("Error expiring trigger {}: {}", trigger.id, e);
+        }
+        return;
+    }
+
+    let symbol = trigger.order.ticker.clone();
+    if !broker.is_connected().await {
+        return;
+    }
+    let quote = match broker.quote(&symbol).await {
+        Ok(quote) => quote,
+        Err(e) => {
+            println!("Trigger {}: {}", trigger.id, e);
+            return;
+        }
+    };
+    if trigger.needs_day_range() {
+        let (lod, hod) = broker.day_range(&symbol).await;
+        if hod <= 0.0 {
+            return;
+        }
+        trigger.lod = Some(lod);
+        trigger.hod = Some(hod);
+    }
+
+    let fire = trigger.is_met(&quote, now);
+    trigger.last_price = quote.last.or(trigger.last_price);
+    let fired = {
+        let conn = DATABASE.lock().unwrap();
+        let result = record_check(&conn, &trigger).and_then(|_| {
+            // Claim the trigger before placing so it cannot fire twice or after a cancel.
+            Ok(fire
+                && set_status(
+                    &conn,
+                    trigger.id,
+                    TriggerStatus::Pending,
+                    TriggerStatus::Fired,
+                    None,
+                )?)
+        });
+        result.unwrap_or_else(|e| {
+            println!("Error saving trigger {}: {}", trigger.id, e);
+            false
+        })
+    };
+    if !fired {
+        return;
+    }
+
+    let broker = broker.clone();
+    tokio::spawn(async move {
+        let (ok, message) = router::place_order(&broker, &trigger.order).await;
+        println!("Trigger {} fired: {}", trigger.id, message);
+        let status = if ok {
+            TriggerStatus::Fired
+        } else {
+            TriggerStatus::Failed
+        };
+        let conn = DATABASE.lock().unwrap();
+        if let Err(e) = set_status(
+            &conn,
+            trigger.id,
+            TriggerStatus::Fired,
+            status,
+            Some(&message),
+        ) {
+            println!("Error saving trigger {}: {}", trigger.id, e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    fn request() -> OrderRequest {
+        OrderRequest {
+            ticker: "TSLA".to_string(),
+            qty: Some(200),
+            stop_price: 245.0,
+            entry_price: 0.0,
+            action: "BUY".to_string(),
+            ladder: None,
+            risk_percent: None,
+            client_order_key: None,
+        }
+    }
+
+    fn trigger(conditions: Vec<Condition>) -> Trigger {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables(&conn).unwrap();
+        insert(
+            &conn,
+            &NewTrigger {
+                conditions,
+                order: request(),
+                expires_at: None,
+            },
+        )
+        .unwrap()
+    }
+
+    fn quote(last: f64) -> Quote {
+        Quote {
+            symbol: "TSLA".to_string(),
+            bid: Some(last - 0.02),
+            ask: Some(last + 0.02),
+            last: Some(last),
+            volume: Some(1_000_000.0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn fires_on_hod_break_with_volume() {
+        let mut trigger = trigger(vec![
+            Condition::BreaksHod,
+            Condition::VolumeAbove { shares: 500_000.0 },
+            Condition::SpreadBelow { max: 0.05 },
+        ]);
+        let now = datetime!(2025-01-02 15:00 UTC);
+        assert!(!trigger.is_met(&quote(251.0), now));
+
+        trigger.hod = Some(250.0);
+        assert!(trigger.is_met(&quote(251.0), now));
+        assert!(!trigger.is_met(&quote(249.0), now));
+    }
+
+    #[test]
+    fn cross_needs_a_previous_price_on_the_other_side() {
+        let mut trigger = trigger(vec![Condition::CrossesAbove { price: 250.0 }]);
+        let now = datetime!(2025-01-02 15:00 UTC);
+        assert!(!trigger.is_met(&quote(251.0), now));
+
+        trigger.last_price = Some(251.0);
+        assert!(!trigger.is_met(&quote(252.0), now));
+        trigger.last_price = Some(249.5);
+        assert!(trigger.is_met(&quote(250.0), now));
+    }
+
+    #[test]
+    fn respects_time_window_and_expiry() {
+        let mut trigger = trigger(vec![
+            Condition::AfterTime {
+                time: "14:45".to_string(),
+            },
+            Condition::BeforeTime {
+                time: "20:00".to_string(),
+            },
+        ]);
+        assert!(!trigger.is_met(&quote(250.0), datetime!(2025-01-02 14:30 UTC)));
+        assert!(trigger.is_met(&quote(250.0), datetime!(2025-01-02 15:00 UTC)));
+
+        trigger.expires_at = Some("2025-01-02T16:00:00Z".to_string());
+        assert!(!trigger.is_expired(datetime!(2025-01-02 15:59 UTC)));
+        assert!(trigger.is_expired(datetime!(2025-01-02 16:00 UTC)));
+    }
+
+    #[test]
+    fn round_trips_through_the_database() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables(&conn).unwrap();
+        let mut trigger = insert(
+            &conn,
+            &NewTrigger {
+                conditions: vec![Condition::SpreadBelow { max: 0.05 }],
+                order: request(),
+                expires_at: None,
+            },
+        )
+        .unwrap();
+        trigger.hod = Some(250.0);
+        record_check(&conn, &trigger).unwrap();
+        assert_eq!(get(&conn, trigger.id).unwrap(), Some(trigger.clone()));
+
+        let (from, to) = (TriggerStatus::Pending, TriggerStatus::Cancelled);
+        assert!(set_status(&conn, trigger.id, from, to, None).unwrap());
+        assert!(!set_status(&conn, trigger.id, from, TriggerStatus::Fired, None).unwrap());
+        assert!(
+            list(&conn, Some(TriggerStatus::Pending))
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(list(&conn, Some(to)).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,536 @@
+//! Terminal order panel: the watchlist with live quotes, positions, working orders and a sized
+//! order ticket, driven by the hotkeys from the config.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use ratatui::Frame;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table};
+use tokio::sync::mpsc;
+
+use crate::broker::{Broker, OrderRequest, Position};
+use crate::config::CONFIG;
+use crate::ladder::DEFAULT_LADDER;
+use crate::order_book::{ORDER_BOOK, TrackedOrder};
+use crate::quotes::Quote;
+use crate::router;
+use crate::sizing::PositionSize;
+
+/// How often quotes, working orders and the ticket size are redrawn.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Reads a key name as written in the config: `Return`, `F1`..`F12`, `Tab`, `Escape`, `Space`
+/// or a single character.
+pub fn parse_hotkey(name: &str) -> Result<KeyCode, String> {
+    let code = match name.to_lowercase().as_str() {
+        "return" | "enter" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "escape" | "esc" => KeyCode::Esc,
+        "space" => KeyCode::Char(' '),
+        "backspace" => KeyCode::Backspace,
+        "insert" => KeyCode::Insert,
+        "delete" => KeyCode::Delete,
+        lower => match lower.strip_prefix('f').map(str::parse::<u8>) {
+            Some(Ok(n)) if (1..=12).contains(&n) => KeyCode::F(n),
+            _ => {
+                let mut chars = name.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => KeyCode::Char(c),
+                    _ => return Err(format!("Unknown hotkey {}", name)),
+                }
+            }
+        },
+    };
+    Ok(code)
+}
+
+/// The ticket field digits are typed into.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TicketField {
+    Stop,
+    Entry,
+    Risk,
+}
+
+impl TicketField {
+    fn next(self) -> Self {
+        match self {
+            TicketField::Stop => TicketField::Entry,
+            TicketField::Entry => TicketField::Risk,
+            TicketField::Risk => TicketField::Stop,
+        }
+    }
+}
+
+/// The entry being prepared for the selected symbol. Prices are kept as typed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ticket {
+    pub action: String,
+    pub field: TicketField,
+    pub stop: String,
+    pub entry: String,
+    pub risk: String,
+    pub ladder: String,
+}
+
+/// What the run loop should do after a key press.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    None,
+    Refresh,
+    Place,
+    Quit,
+}
+
+pub struct Panel {
+    pub symbols: Vec<String>,
+    pub selected: usize,
+    pub ticket: Ticket,
+    pub quotes: HashMap<String, Quote>,
+    pub positions: Vec<Position>,
+    pub orders: Vec<TrackedOrder>,
+    pub sizing: Option<Result<PositionSize, String>>,
+    pub status: String,
+    refresh_key: KeyCode,
+    place_key: KeyCode,
+    ladders: Vec<String>,
+    /// Set when the ticket changed and its size must be recomputed.
+    resize: bool,
+}
+
+impl Panel {
+    pub fn new(symbols: Vec<String>, refresh_key: KeyCode, place_key: KeyCode) -> Self {
+        let mut ladders: Vec<String> = CONFIG.ladders.keys().cloned().collect();
+        ladders.sort();
+        ladders.retain(|name| name != DEFAULT_LADDER);
+        ladders.insert(0, DEFAULT_LADDER.to_string());
+        Panel {
+            symbols,
+            selected: 0,
+            ticket: Ticket {
+                action: "BUY".to_string(),
+                field: TicketField::Stop,
+                stop: String::new(),
+                entry: String::new(),
+                risk: CONFIG.risk_percent.clone(),
+                ladder: DEFAULT_LADDER.to_string(),
+            },
+            quotes: HashMap::new(),
+            positions: Vec::new(),
+            orders: Vec::new(),
+            sizing: None,
+            status: String::new(),
+            refresh_key,
+            place_key,
+            ladders,
+            resize: false,
+        }
+    }
+
+    /// The panel for the configured watchlist and hotkeys.
+    pub fn from_config() -> Result<Self, String> {
+        if CONFIG.watchlist.is_empty() {
+            return Err("The watchlist in the config is empty".to_string());
+        }
+        Ok(Self::new(
+            CONFIG.watchlist.iter().map(|s| s.to_uppercase()).collect(),
+            parse_hotkey(&CONFIG.hotkey_refresh)?,
+            parse_hotkey(&CONFIG.hotkey_place_order)?,
+        ))
+    }
+
+    pub fn symbol(&self) -> &str {
+        &self.symbols[self.selected]
+    }
+
+    /// Applies a key press to the selection and ticket. Configured hotkeys win over editing keys.
+    pub fn handle_key(&mut self, key: KeyEvent) -> Action {
+        if key.code == self.place_key {
+            return Action::Place;
+        }
+        if key.code == self.refresh_key {
+            return Action::Refresh;
+        }
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Action::Quit;
+        }
+        let field = match self.ticket.field {
+            TicketField::Stop => &mut self.ticket.stop,
+            TicketField::Entry => &mut self.ticket.entry,
+            TicketField::Risk => &mut self.ticket.risk,
+        };
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Action::Quit,
+            KeyCode::Up if self.selected > 0 => self.selected -= 1,
+            KeyCode::Down if self.selected + 1 < self.symbols.len() => self.selected += 1,
+            KeyCode::Up | KeyCode::Down => return Action::None,
+            KeyCode::Tab => {
+                self.ticket.field = self.ticket.field.next();
+                return Action::None;
+            }
+            KeyCode::Char('b') => self.ticket.action = "BUY".to_string(),
+            KeyCode::Char('s') => self.ticket.action = "SELL".to_string(),
+            KeyCode::Char('l') => {
+                let current = self.ladders.iter().position(|l| *l == self.ticket.ladder);
+                let next = current.map_or(0, |i| (i + 1) % self.ladders.len());
+                self.ticket.ladder = self.ladders[next].clone();
+                return Action::None;
+            }
+            KeyCode::Char(c) if c.is_ascii_digit() || (c == '.' && !field.contains('.')) => {
+                field.push(c)
+            }
+            KeyCode::Backspace => {
+                field.pop();
+            }
+            KeyCode::Delete => field.clear(),
+            _ => return Action::None,
+        }
+        self.resize = true;
+        Action::None
+    }
+
+    /// The entry the ticket describes; the quantity is left to risk-based sizing.
+    pub fn order_request(&self) -> Result<OrderRequest, String> {
+        let number = |text: &str, name: &str| -> Result<Option<f64>, String> {
+            if text.is_empty() {
+                return Ok(None);
+            }
+            text.parse()
+                .map(Some)
+                .map_err(|_| format!("{} {} is not a number", name, text))
+        };
+        let stop_price = number(&self.ticket.stop, "Stop")?.ok_or("Type a stop price first")?;
+        Ok(OrderRequest {
+            ticker: self.symbol().to_string(),
+            qty: None,
+            stop_price,
+            entry_price: number(&self.ticket.entry, "Entry")?.unwrap_or(0.0),
+            action: self.ticket.action.clone(),
+            ladder: Some(self.ticket.ladder.clone()),
+            risk_percent: number(&self.ticket.risk, "Risk")?,
+            client_order_key: None,
+        })
+    }
+
+    /// Pulls quotes and the order book, and resizes the ticket if it changed.
+    pub async fn update<B: Broker>(&mut self, broker: &B) {
+        for symbol in &self.symbols {
+            if let Ok(quote) = broker.quote(symbol).await {
+                self.quotes.insert(symbol.clone(), quote);
+            }
+        }
+        self.orders = ORDER_BOOK
+            .read()
+            .await
+            .list()
+            .into_iter()
+            .filter(TrackedOrder::is_working)
+            .collect();
+        if std::mem::take(&mut self.resize) {
+            self.sizing = match self.order_request() {
+                Ok(request) => Some(
+                    broker
+                        .size_position(
+                            &request.ticker,
+                            Some(request.entry_price),
+                            request.stop_price,
+                            request.risk_percent,
+                        )
+                        .await,
+                ),
+                Err(_) => None,
+            };
+        }
+    }
+
+    /// Asks the backend for positions and open orders as well, for the refresh hotkey.
+    pub async fn refresh<B: Broker>(&mut self, broker: &B) {
+        if !broker.refresh_orders(false).await {
+            self.status = "Could not refresh open orders".to_string();
+        }
+        match broker.positions().await {
+            Some(positions) => self.positions = positions,
+            None => self.status = "Could not read positions".to_string(),
+        }
+        self.resize = true;
+        self.update(broker).await;
+    }
+
+    pub async fn place<B: Broker>(&mut self, broker: &B) {
+        self.status = match self.order_request() {
+            Ok(request) => router::place_order(broker, &request).await.1,
+            Err(e) => e,
+        };
+        self.refresh(broker).await;
+    }
+
+    pub fn draw(&self, frame: &mut Frame) {
+        let [watchlist_area, middle_area, ticket_area, status_area] = Layout::vertical([
+            Constraint::Length(self.symbols.len() as u16 + 3),
+            Constraint::Min(5),
+            Constraint::Length(5),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [positions_area, orders_area] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(middle_area);
+
+        let price = |value: Option<f64>| value.map_or("-".to_string(), |v| format!("{:.2}", v));
+        let quotes = self.symbols.iter().enumerate().map(|(i, symbol)| {
+            let quote = self.quotes.get(symbol);
+            let row = Row::new(vec![
+                symbol.clone(),
+                price(quote.and_then(|q| q.bid)),
+                price(quote.and_then(|q| q.ask)),
+                price(quote.and_then(|q| q.last)),
+                price(quote.and_then(|q| q.low)),
+                price(quote.and_then(|q| q.high)),
+                quote
+                    .and_then(|q| q.volume)
+                    .map_or("-".to_string(), |v| format!("{:.0}", v)),
+            ]);
+            if i == self.selected {
+                row.style(Style::new().add_modifier(Modifier::REVERSED))
+            } else {
+                row
+            }
+        });
+        frame.render_widget(
+            Table::new(quotes, [Constraint::Length(10); 7])
+                .header(
+                    Row::new(["Symbol", "Bid", "Ask", "Last", "LOD", "HOD", "Volume"])
+                        .style(Style::new().add_modifier(Modifier::BOLD)),
+                )
+                .block(Block::bordered().title(" Watchlist ")),
+            watchlist_area,
+        );
+
+        let positions = self
+            .positions
+            .iter()
+            .filter(|p| p.quantity != 0.0)
+            .map(|p| {
+                Row::new(vec![
+                    p.symbol.clone(),
+                    format!("{}", p.quantity),
+                    format!("{:.2}", p.average_cost),
+                ])
+            });
+        frame.render_widget(
+            Table::new(positions, [Constraint::Length(8); 3])
+                .header(Row::new(["Symbol", "Qty", "Avg"]))
+                .block(Block::bordered().title(" Positions ")),
+            positions_area,
+        );
+
+        let orders = self.orders.iter().map(|o| {
+            Row::new(vec![
+                o.order_id.to_string(),
+                o.symbol.clone(),
+                o.action.clone(),
+                o.order_type.clone(),
+                format!("{}", o.remaining),
+                price(o.limit_price.or(o.stop_price)),
+                o.status.clone(),
+            ])
+        });
+        frame.render_widget(
+            Table::new(
+                orders,
+                [
+                    Constraint::Length(6),
+                    Constraint::Length(7),
+                    Constraint::Length(5),
+                    Constraint::Length(6),
+                    Constraint::Length(6),
+                    Constraint::Length(9),
+                    Constraint::Min(9),
+                ],
+            )
+            .header(Row::new([
+                "Id", "Symbol", "Side", "Type", "Qty", "Price", "Status",
+            ]))
+            .block(Block::bordered().title(" Working orders ")),
+            orders_area,
+        );
+
+        let field = |name: &str, value: &str, which: TicketField| {
+            let shown = if value.is_empty() { "-" } else { value };
+            if self.ticket.field == which {
+                format!("[{}: {}]", name, shown)
+            } else {
+                format!(" {}: {} ", name, shown)
+            }
+        };
+        let size = match &self.sizing {
+            None => "size: type a stop".to_string(),
+            Some(Ok(size)) => format!(
+                "size: {} shares, ${:.2} at risk, ${:.2} position{}",
+                size.qty,
+                size.risk_amount,
+                size.position_value,
+                size.capped_by
+                    .as_ref()
+                    .map_or(String::new(), |by| format!(", capped by {}", by))
+            ),
+            Some(Err(e)) => format!("size: {}", e),
+        };
+        let ticket = vec![
+            Line::from(format!(
+                "{} {} {} {} {}  ladder: {}",
+                self.ticket.action,
+                self.symbol(),
+                field("stop", &self.ticket.stop, TicketField::Stop),
+                field("entry", &self.ticket.entry, TicketField::Entry),
+                field("risk %", &self.ticket.risk, TicketField::Risk),
+                self.ticket.ladder
+            )),
+            Line::from(size),
+            Line::from(format!(
+                "{} place  {} refresh  up/down symbol  b/s side  tab field  l ladder  q quit",
+                key_name(self.place_key),
+                key_name(self.refresh_key)
+            )),
+        ];
+        frame.render_widget(
+            Paragraph::new(ticket).block(Block::bordered().title(" Ticket ")),
+            ticket_area,
+        );
+        frame.render_widget(Paragraph::new(self.status.as_str()), status_area);
+    }
+}
+
+fn key_name(code: KeyCode) -> String {
+    match code {
+        KeyCode::Enter => "Return".to_string(),
+        KeyCode::Char(' ') => "Space".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Runs the panel against a connected `broker` until the user quits.
+pub async fn run<B: Broker>(broker: B, mut panel: Panel) -> Result<(), String> {
+    // Terminal input blocks, so it is read on its own thread.
+    let (keys, mut key_events) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            if keys.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut terminal = ratatui::init();
+    panel.refresh(&broker).await;
+    let mut timer = tokio::time::interval(REFRESH_INTERVAL);
+    let result = loop {
+        if let Err(e) = terminal.draw(|frame| panel.draw(frame)) {
+            break Err(format!("Error drawing the panel: {}", e));
+        }
+        tokio::select! {
+            _ = timer.tick() => panel.update(&broker).await,
+            event = key_events.recv() => match event {
+                Some(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                    match panel.handle_key(key) {
+                        Action::None => {}
+                        Action::Refresh => panel.refresh(&broker).await,
+                        Action::Place => panel.place(&broker).await,
+                        Action::Quit => break Ok(()),
+                    }
+                }
+                Some(_) => {}
+                None => break Err("Terminal input closed".to_string()),
+            },
+        }
+    };
+    ratatui::restore();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::Terminal;
+    use ratatui::backend::TestBackend;
+
+    fn press(panel: &mut Panel, code: KeyCode) -> Action {
+        panel.handle_key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    fn panel() -> Panel {
+        Panel::new(
+            vec!["TSLA".to_string(), "NVDA".to_string()],
+            KeyCode::Enter,
+            KeyCode::F(1),
+        )
+    }
+
+    #[test]
+    fn parses_config_hotkeys() {
+        assert_eq!(parse_hotkey("Return"), Ok(KeyCode::Enter));
+        assert_eq!(parse_hotkey("F1"), Ok(KeyCode::F(1)));
+        assert_eq!(parse_hotkey("f12"), Ok(KeyCode::F(12)));
+        assert_eq!(parse_hotkey("p"), Ok(KeyCode::Char('p')));
+        assert!(parse_hotkey("F13").is_err());
+        assert!(parse_hotkey("Hyper").is_err());
+    }
+
+    #[test]
+    fn keys_edit_the_ticket_and_hotkeys_act() {
+        let mut panel = panel();
+        press(&mut panel, KeyCode::Down);
+        press(&mut panel, KeyCode::Char('s'));
+        for c in "240.5.0".chars() {
+            press(&mut panel, KeyCode::Char(c));
+        }
+        press(&mut panel, KeyCode::Tab);
+        press(&mut panel, KeyCode::Char('2'));
+        press(&mut panel, KeyCode::Backspace);
+        press(&mut panel, KeyCode::Char('1'));
+
+        let request = panel.order_request().unwrap();
+        assert_eq!(request.ticker, "NVDA");
+        assert_eq!(request.action, "SELL");
+        assert_eq!((request.stop_price, request.entry_price), (240.50, 1.0));
+        assert_eq!(request.qty, None);
+
+        assert_eq!(press(&mut panel, KeyCode::F(1)), Action::Place);
+        assert_eq!(press(&mut panel, KeyCode::Enter), Action::Refresh);
+        assert_eq!(press(&mut panel, KeyCode::Char('q')), Action::Quit);
+    }
+
+    #[test]
+    fn draws_quotes_and_the_ticket() {
+        let mut panel = panel();
+        panel.quotes.insert(
+            "TSLA".to_string(),
+            Quote {
+                symbol: "TSLA".to_string(),
+                bid: Some(250.1),
+                ask: Some(250.2),
+                low: Some(241.0),
+                high: Some(256.75),
+                ..Quote::default()
+            },
+        );
+        panel.ticket.stop = "240".to_string();
+        let mut terminal = Terminal::new(TestBackend::new(100, 20)).unwrap();
+        terminal.draw(|frame| panel.draw(frame)).unwrap();
+
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.contains("256.75"));
+        assert!(screen.contains("BUY TSLA [stop: 240]"));
+        assert!(screen.contains("F1 place  Return refresh"));
+    }
+}
@@ -0,0 +1,121 @@
+//! Checks API keys and roles on the router, backed by the paper-trading simulator.
+
+use std::sync::Once;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use http_body_util::BodyExt;
+use serde_json::{Value, json};
+use time::OffsetDateTime;
+use tower::ServiceExt;
+
+use rust::replay::ReplayTick;
+use rust::router;
+use rust::simulator::{SimBroker, SimulatorConfig};
+
+static CONFIGURE: Once = Once::new();
+
+fn broker() -> SimBroker {
+    CONFIGURE.call_once(|| {
+        // SAFETY: runs once, before any test reads the config or spawns threads that read the environment.
+        unsafe {
+            std::env::set_var(
+                "IBKR_PANEL_CONFIG",
+                concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/auth.toml"),
+            );
+        }
+    });
+    SimBroker::replay(SimulatorConfig::default(), Vec::new())
+}
+
+async fn send(
+    broker: &SimBroker,
+    method: &str,
+    uri: &str,
+    header: Option<(&str, &str)>,
+) -> (StatusCode, Value) {
+    let mut request = Request::builder().method(method).uri(uri);
+    if let Some((name, value)) = header {
+        request = request.header(name, value);
+    }
+    let response = router::app(broker.clone())
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    let text = String::from_utf8_lossy(&bytes).to_string();
+    (status, serde_json::from_str(&text).unwrap_or(Value::Null))
+}
+
+const VIEWER: Option<(&str, &str)> = Some(("X-API-Key", "read-1111"));
+const TRADER: Option<(&str, &str)> = Some(("Authorization", "Bearer trade-2222"));
+const ADMIN: Option<(&str, &str)> = Some(("X-API-Key", "admin-3333"));
+
+#[tokio::test]
+async fn roles_limit_what_each_key_can_do() {
+    let broker = broker();
+    let connect = "/connect?address=sim&port=0&client_id=1";
+
+    assert_eq!(send(&broker, "GET", "/", None).await.0, StatusCode::OK);
+    assert_eq!(
+        send(&broker, "GET", "/orders", None).await.0,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        send(&broker, "GET", "/orders", Some(("X-API-Key", "read-111")))
+            .await
+            .0,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        send(&broker, "POST", connect, TRADER).await.0,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        send(&broker, "POST", connect, ADMIN).await,
+        (StatusCode::OK, json!(true))
+    );
+
+    broker
+        .apply_tick(&ReplayTick {
+            time: OffsetDateTime::now_utc(),
+            symbol: "AAPL".to_string(),
+            bid: Some(190.0),
+            ask: Some(190.02),
+            last: Some(190.01),
+            volume: None,
+        })
+        .await;
+    let order = "/order?ticker=AAPL&qty=10&stop_price=185&entry_price=0&action=BUY";
+    let (status, body) = send(&broker, "POST", order, VIEWER).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+    assert_eq!(
+        send(&broker, "GET", "/orders", VIEWER).await.0,
+        StatusCode::OK
+    );
+    let (status, placed) = send(&broker, "POST", order, TRADER).await;
+    assert_eq!(
+        (status, &placed[0]),
+        (StatusCode::OK, &json!(true)),
+        "{}",
+        placed
+    );
+
+    assert_eq!(
+        send(&broker, "GET", "/audit", TRADER).await.0,
+        StatusCode::FORBIDDEN
+    );
+    let (status, audit) = send(&broker, "GET", "/audit?caller=alice", ADMIN).await;
+    assert_eq!(status, StatusCode::OK);
+    let entries = audit["Ok"].as_array().unwrap();
+    // Newest first: the refused audit read, the placed order, then the refused connect.
+    assert_eq!(entries[0]["status"], json!(403));
+    assert_eq!(entries[1]["uri"], json!(order));
+    assert_eq!(entries[1]["role"], json!("trader"));
+    assert!(entries[1]["outcome"].as_str().unwrap().contains("true"));
+    assert_eq!(entries[2]["uri"], json!(connect));
+    assert_eq!(entries[2]["status"], json!(403));
+    let (_, refused) = send(&broker, "GET", "/audit?caller=viewer", ADMIN).await;
+    assert_eq!(refused["Ok"][0]["uri"], json!(order));
+}
@@ -0,0 +1,593 @@
+//! Runs the router's endpoints against the mock gateway in `rust::mock_gateway`.
+
+use std::sync::{LazyLock, Once};
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use futures_util::StreamExt;
+use http_body_util::BodyExt;
+use serde_json::{Value, json};
+use tokio::sync::{Mutex, MutexGuard};
+use tower::ServiceExt;
+
+use rust::broker::OrderEvent;
+use rust::cli;
+use rust::connector::IbkrBroker;
+use rust::journal;
+use rust::mock_gateway::{MockGateway, MockOrder, MockPosition, MockSymbol, Script};
+use rust::order_book::ORDER_BOOK;
+use rust::router;
+
+/// Every test drives the same broker and process-wide order book, so they take turns.
+static BROKER: LazyLock<IbkrBroker> = LazyLock::new(IbkrBroker::default);
+static SESSION: Mutex<()> = Mutex::const_new(());
+static CONFIGURE: Once = Once::new();
+
+struct Session {
+    gateway: MockGateway,
+    /// Orders from earlier tests stay in the book; ours start here.
+    first_order_id: i64,
+    _turn: MutexGuard<'static, ()>,
+}
+
+impl Session {
+    /// Tracked orders for `symbol` placed during this session, oldest first.
+    async fn orders(&self, symbol: &str) -> Vec<Value> {
+        // Let the order streams catch up with what the gateway already sent.
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        let orders = call("GET", "/orders").await;
+        orders
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|o| {
+                o["symbol"] == symbol && o["order_id"].as_i64() >= Some(self.first_order_id)
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// Starts a gateway for `script` and connects the panel to it.
+async fn connect(script: Script) -> Session {
+    CONFIGURE.call_once(|| {
+        // SAFETY: runs once, before any test reads the config or spawns threads that read the environment.
+        unsafe {
+            std::env::set_var(
+                "IBKR_PANEL_CONFIG",
+                concat!(
+                    env!("CARGO_MANIFEST_DIR"),
+                    "/tests/fixtures/mock_gateway.toml"
+                ),
+            );
+        }
+    });
+    let turn = SESSION.lock().await;
+    let gateway = MockGateway::start(script).await.unwrap();
+    let first_order_id = {
+        let book = ORDER_BOOK.read().await;
+        tokio::spawn(journal::record(book.subscribe()));
+        book.list().last().map_or(0, |o| o.order_id as i64 + 1)
+    };
+
+    let uri = format!(
+        "/connect?address=127.0.0.1&port={}&client_id=100",
+        gateway.port()
+    );
+    assert_eq!(call("POST", &uri).await, json!(true));
+    Session {
+        gateway,
+        first_order_id,
+        _turn: turn,
+    }
+}
+
+async fn disconnect() {
+    call("POST", "/disconnect").await;
+    assert_eq!(call("GET", "/is_connected").await, json!(false));
+}
+
+async fn send(method: &str, uri: &str, body: Option<Value>) -> (StatusCode, String) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json");
+    let request = match body {
+        Some(body) => request.body(Body::from(body.to_string())),
+        None => request.body(Body::empty()),
+    };
+    let response = router::app(BROKER.clone())
+        .oneshot(request.unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    (status, String::from_utf8_lossy(&bytes).to_string())
+}
+
+async fn call(method: &str, uri: &str) -> Value {
+    call_with(method, uri, None).await
+}
+
+async fn call_with(method: &str, uri: &str, body: Option<Value>) -> Value {
+    let (status, text) = send(method, uri, body).await;
+    assert_eq!(status, StatusCode::OK, "{} {}: {}", method, uri, text);
+    if text.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_str(&text).unwrap()
+    }
+}
+
+fn assert_ok(result: &Value) {
+    assert_eq!(result[0], json!(true), "{}", result);
+}
+
+#[tokio::test]
+async fn reads_account_and_market_data() {
+    let session = connect(Script {
+        positions: vec![MockPosition {
+            symbol: "TSLA".to_string(),
+            quantity: 100.0,
+            average_cost: 240.0,
+        }],
+        ..Default::default()
+    })
+    .await;
+    assert_eq!(call("GET", "/is_connected").await, json!(true));
+
+    let values = call("GET", "/get_account_values").await;
+    assert!(values.to_string().contains("NetLiquidation"), "{}", values);
+    let positions = call("GET", "/get_positions").await;
+    assert_eq!(positions[0]["symbol"], "TSLA", "{}", positions);
+    assert_eq!(positions[0]["quantity"], json!(100.0));
+
+    let price = call("GET", "/market_data?ticker=AAPL").await;
+    assert!(price.as_f64().is_some_and(|p| p > 180.0), "{}", price);
+    let (lod, hod) =
+        serde_json::from_value::<(f64, f64)>(call("GET", "/get_lod_hod?ticker=AAPL").await)
+            .unwrap();
+    assert!(lod > 0.0 && lod < 190.0 && hod > 190.0, "{} {}", lod, hod);
+
+    // 0.5% of 100k over a $5 stop.
+    let size = call("GET", "/size?ticker=AAPL&entry_price=190&stop_price=185").await;
+    assert_eq!(size["Ok"]["qty"], json!(100), "{}", size);
+
+    let preview = call(
+        "POST",
+        "/order/preview?ticker=AAPL&qty=100&stop_price=185&entry_price=0&action=BUY",
+    )
+    .await;
+    assert_eq!(
+        preview["Ok"]["initial_margin_change"],
+        json!(4750.0),
+        "{}",
+        preview
+    );
+    // The what-if order is not a real one.
+    assert!(session.gateway.orders().is_empty());
+
+    disconnect().await;
+}
+
+#[tokio::test]
+async fn market_entry_places_ladder_and_scales_out() {
+    let session = connect(Script::default()).await;
+
+    let placed = call(
+        "POST",
+        "/order?ticker=AAPL&qty=100&stop_price=185&entry_price=0&action=BUY&ladder=halves&client_order_key=e2e-1",
+    )
+    .await;
+    assert_ok(&placed);
+    // A retry with the same key returns the first result without a second entry.
+    let retried = call(
+        "POST",
+        "/order?ticker=AAPL&qty=100&stop_price=185&entry_price=0&action=BUY&ladder=halves&client_order_key=e2e-1",
+    )
+    .await;
+    assert_eq!(retried, placed);
+    assert_eq!(session.gateway.position("AAPL"), 100.0);
+
+    let orders = session.orders("AAPL").await;
+    assert_eq!(orders.len(), 5, "{:?}", orders);
+    let entry = &orders[0];
+    assert_eq!(entry["status"], "Filled");
+    assert_eq!(entry["fills"].as_array().unwrap().len(), 1);
+    let trade_id = entry["order_id"].as_i64().unwrap();
+    let stops: Vec<&Value> = orders.iter().filter(|o| o["order_type"] == "STP").collect();
+    let targets: Vec<&Value> = orders.iter().filter(|o| o["order_type"] == "LMT").collect();
+    assert_eq!((stops.len(), targets.len()), (2, 2));
+
+    // The first target fills and its OCA stop is cancelled with it.
+    let first_target = targets[0]["limit_price"].as_f64().unwrap();
+    session.gateway.tick("AAPL", first_target);
+    assert_eq!(session.gateway.position("AAPL"), 50.0);
+    let orders = session.orders("AAPL").await;
+    let status =
+        |id: &Value| orders.iter().find(|o| o["order_id"] == *id).unwrap()["status"].clone();
+    assert_eq!(status(&targets[0]["order_id"]), "Filled");
+    assert_eq!(status(&stops[0]["order_id"]), "Cancelled");
+
+    let executions = call("GET", "/executions?symbol=AAPL").await;
+    let fills: Vec<&Value> = executions["Ok"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|e| e["trade_id"] == json!(trade_id))
+        .collect();
+    assert_eq!(fills.len(), 2, "{}", executions);
+    assert!(fills.iter().all(|e| e["commission"].as_f64().is_some()));
+
+    // Move the remaining stop, then cancel it.
+    let stop_id = stops[1]["order_id"].as_i64().unwrap();
+    let moved = call("PATCH", &format!("/orders/{}?stop_price=186", stop_id)).await;
+    assert_ok(&moved);
+    let stop = call("GET", &format!("/orders/{}", stop_id)).await;
+    assert_eq!(stop["stop_price"], json!(186.0));
+    assert_ok(&call("DELETE", &format!("/orders/{}", stop_id)).await);
+    assert_ok(&call("POST", "/orders/cancel_all?symbol=AAPL").await);
+
+    let refreshed = call("GET", "/orders?refresh=true").await;
+    assert!(refreshed.as_array().is_some());
+
+    let closed = call("POST", "/positions/AAPL/close").await;
+    assert_eq!(closed["Ok"]["status"], "Filled", "{}", closed);
+    assert_eq!(session.gateway.position("AAPL"), 0.0);
+
+    let journal = call("GET", "/journal?symbol=AAPL").await;
+    let trades = journal["Ok"].as_array().unwrap();
+    let journaled = trades
+        .iter()
+        .find(|t| t["trade_id"] == json!(trade_id) && t["client_id"] == json!(100))
+        .unwrap_or_else(|| panic!("{}", journal));
+    let id = journaled["id"].as_i64().unwrap();
+    let noted = call("PATCH", &format!("/journal/{}?notes=clean%20break", id)).await;
+    assert_ok(&noted);
+    let trade = call("GET", &format!("/journal/{}", id)).await;
+    assert_eq!(trade["Ok"]["notes"], "clean break", "{}", trade);
+    assert_eq!(trade["Ok"]["trade_id"], json!(trade_id), "{}", trade);
+    let (status, csv) = send("GET", "/journal/export?symbol=AAPL", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(csv.lines().count() >= 2, "{}", csv);
+
+    disconnect().await;
+}
+
+#[tokio::test]
+async fn market_entry_that_does_not_fill_is_cancelled() {
+    let mut halted = MockSymbol::new("AAPL", 265598, 190.0);
+    halted.halted = true;
+    let session = connect(Script {
+        symbols: vec![halted],
+        ..Default::default()
+    })
+    .await;
+
+    let placed = call(
+        "POST",
+        "/order?ticker=AAPL&qty=100&stop_price=185&entry_price=0&action=BUY&ladder=halves",
+    )
+    .await;
+    assert_eq!(placed[0], json!(false), "{}", placed);
+    assert!(
+        placed[1].as_str().unwrap().contains("cancelled"),
+        "{}",
+        placed
+    );
+
+    let sent = session.gateway.orders();
+    assert_eq!(sent.len(), 1, "{:?}", sent);
+    assert_eq!(
+        (sent[0].order_type.as_str(), sent[0].status.as_str()),
+        ("MKT", "Cancelled")
+    );
+    let orders = session.orders("AAPL").await;
+    assert_eq!(orders.len(), 1, "{:?}", orders);
+    assert_eq!(session.gateway.position("AAPL"), 0.0);
+
+    disconnect().await;
+}
+
+#[tokio::test]
+async fn entries_with_a_stop_through_the_quote_are_refused() {
+    let session = connect(Script::default()).await;
+
+    let placed = call(
+        "POST",
+        "/order?ticker=AAPL&qty=100&stop_price=195&entry_price=0&action=BUY&ladder=halves",
+    )
+    .await;
+    assert_eq!(placed[0], json!(false), "{}", placed);
+    assert!(placed[1].as_str().unwrap().contains("Stop"), "{}", placed);
+    let placed = call(
+        "POST",
+        "/order?ticker=TSLA&qty=100&stop_price=245&entry_price=0&action=SELL&ladder=halves",
+    )
+    .await;
+    assert_eq!(placed[0], json!(false), "{}", placed);
+    assert!(session.gateway.orders().is_empty());
+    assert_eq!(session.gateway.position("AAPL"), 0.0);
+
+    disconnect().await;
+}
+
+#[tokio::test]
+async fn exits_and_kill_switch_work_on_open_positions() {
+    let session = connect(Script {
+        positions: vec![
+            MockPosition {
+                symbol: "TSLA".to_string(),
+                quantity: 100.0,
+                average_cost: 240.0,
+            },
+            MockPosition {
+                symbol: "AAPL".to_string(),
+                quantity: -20.0,
+                average_cost: 195.0,
+            },
+        ],
+        ..Default::default()
+    })
+    .await;
+
+    let reversed = call("POST", "/positions/TSLA/reverse").await;
+    assert_eq!(reversed["Ok"]["filled"], json!(200.0), "{}", reversed);
+    assert_eq!(session.gateway.position("TSLA"), -100.0);
+
+    let closed = call("POST", "/positions/close_all").await;
+    assert_eq!(closed.as_array().unwrap().len(), 2, "{}", closed);
+    assert_eq!(session.gateway.position("TSLA"), 0.0);
+    assert_eq!(session.gateway.position("AAPL"), 0.0);
+
+    assert_ok(&call("POST", "/kill_switch?flatten=true").await);
+    let status = call("GET", "/kill_switch").await;
+    assert_eq!(status["entries_locked"], json!(true));
+    let blocked = call(
+        "POST",
+        "/order?ticker=AAPL&qty=10&stop_price=185&entry_price=0&action=BUY",
+    )
+    .await;
+    assert_eq!(blocked[0], json!(false));
+    assert!(
+        session
+            .gateway
+            .requests()
+            .iter()
+            .any(|r| r.starts_with("58|"))
+    );
+
+    let status = call("POST", "/kill_switch/reset").await;
+    assert_eq!(status["entries_locked"], json!(false));
+
+    disconnect().await;
+}
+
+#[tokio::test]
+async fn reversing_into_a_restricted_short_is_refused() {
+    let mut script = Script {
+        positions: vec![MockPosition {
+            symbol: "MSFT".to_string(),
+            quantity: 100.0,
+            average_cost: 410.0,
+        }],
+        ..Default::default()
+    };
+    script.symbols.push(MockSymbol::new("MSFT", 272093, 420.0));
+    let session = connect(script).await;
+
+    let reversed = call("POST", "/positions/MSFT/reverse").await;
+    let error = reversed["Err"].as_str().unwrap_or_default();
+    assert!(
+        error.contains("short sales are not allowed"),
+        "{}",
+        reversed
+    );
+    assert_eq!(session.gateway.position("MSFT"), 100.0);
+
+    disconnect().await;
+}
+
+/// Runs a one-shot command the way `main` does, as client 90.
+async fn run_cli(session: &Session, command: &str) -> Result<String, String> {
+    let mut args: Vec<String> = command.split(' ').map(str::to_string).collect();
+    args.extend(["--port".to_string(), session.gateway.port().to_string()]);
+    cli::run(IbkrBroker::default(), cli::parse(&args)?).await
+}
+
+#[tokio::test]
+async fn cli_flatten_cancels_exits_placed_by_another_client() {
+    let session = connect(Script::default()).await;
+    assert_ok(
+        &call(
+            "POST",
+            "/order?ticker=AAPL&qty=100&stop_price=185&entry_price=0&action=BUY&ladder=halves",
+        )
+        .await,
+    );
+    assert_eq!(session.orders("AAPL").await.len(), 5);
+    disconnect().await;
+    let exits = || -> Vec<MockOrder> {
+        session
+            .gateway
+            .orders()
+            .into_iter()
+            .filter(|o| o.client_id == 100 && o.order_type != "MKT")
+            .collect()
+    };
+    assert_eq!(exits().len(), 4);
+    assert!(exits().iter().all(|o| o.status != "Cancelled"));
+
+    let flattened = run_cli(&session, "flatten").await;
+    assert!(flattened.is_ok(), "{:?}", flattened);
+    assert_eq!(session.gateway.position("AAPL"), 0.0);
+    assert!(
+        exits().iter().all(|o| o.status == "Cancelled"),
+        "{:?}",
+        exits()
+    );
+
+    // A connected server would hear about its cancelled orders; ours has gone, so tell the
+    // shared book for the tests that follow.
+    let mut book = ORDER_BOOK.write().await;
+    for exit in exits() {
+        book.apply(&OrderEvent::Status {
+            order_id: exit.order_id,
+            client_id: exit.client_id,
+            status: exit.status,
+            filled: exit.filled,
+            remaining: exit.quantity - exit.filled,
+            average_fill_price: exit.average_fill_price,
+            perm_id: exit.perm_id,
+        });
+    }
+}
+
+#[tokio::test]
+async fn cli_orders_refuse_ladders_with_exit_rules() {
+    let session = connect(Script::default()).await;
+    disconnect().await;
+
+    let refused = run_cli(
+        &session,
+        "order buy AAPL --stop 185 --qty 10 --ladder breakeven",
+    )
+    .await;
+    assert!(
+        refused.as_ref().is_err_and(|e| e.contains("exit rules")),
+        "{:?}",
+        refused
+    );
+    assert!(
+        !session
+            .gateway
+            .requests()
+            .iter()
+            .any(|r| r.starts_with("3|"))
+    );
+}
+
+#[tokio::test]
+async fn unknown_symbols_are_rejected() {
+    let _session = connect(Script::default()).await;
+
+    assert_eq!(call("GET", "/market_data?ticker=ZZZZ").await, Value::Null);
+    let placed = call(
+        "POST",
+        "/order?ticker=ZZZZ&qty=10&stop_price=5&entry_price=0&action=BUY",
+    )
+    .await;
+    assert_eq!(placed[0], json!(false), "{}", placed);
+
+    disconnect().await;
+}
+
+#[tokio::test]
+async fn replays_can_be_recorded_and_paced() {
+    let _session = connect(Script::default()).await;
+    let name = format!("endpoints-{}.csv", std::process::id());
+
+    assert_eq!(call("GET", "/replay/recording").await, Value::Null);
+    let started = call("POST", &format!("/replay/recording/start?path={}", name)).await;
+    assert_eq!(started["Ok"]["format"], json!("csv"), "{}", started);
+    let again = call("POST", "/replay/recording/start?path=other.csv").await;
+    assert!(again["Err"].is_string(), "{}", again);
+    let recording = call("GET", "/replay/recording").await;
+    assert_eq!(recording["ticks"], json!(0), "{}", recording);
+    let stopped = call("POST", "/replay/recording/stop").await;
+    assert!(stopped["Ok"].is_object(), "{}", stopped);
+    assert_eq!(call("GET", "/replay/recording").await, Value::Null);
+    let outside = call("POST", "/replay/recording/start?path=../escape.csv").await;
+    assert!(outside["Err"].is_string(), "{}", outside);
+
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/target/test-replays");
+    let file = std::path::Path::new(dir).join(&name);
+    std::fs::write(
+        &file,
+        "time,symbol,bid,ask,last,volume\n\
+         2025-01-02T14:30:00Z,AAPL,190.0,190.02,190.01,1000\n\
+         2025-01-02T14:30:01Z,AAPL,190.5,190.52,190.51,2000\n",
+    )
+    .unwrap();
+    let unloaded = call("POST", "/replay/speed?speed=2").await;
+    assert!(unloaded["Err"].is_string(), "{}", unloaded);
+    let loaded = call("POST", &format!("/replay/start?file={}&speed=0", name)).await;
+    assert_eq!(loaded["Ok"]["total"], json!(2), "{}", loaded);
+    let negative = call("POST", "/replay/speed?speed=-1").await;
+    assert!(negative["Err"].is_string(), "{}", negative);
+    let paced = call("POST", "/replay/speed?speed=2").await;
+    assert_eq!(paced["Ok"]["speed"], json!(2.0), "{}", paced);
+    let paused = call("POST", "/replay/speed?speed=0").await;
+    assert_eq!(paused["Ok"]["speed"], Value::Null, "{}", paused);
+
+    call("POST", "/replay/stop").await;
+    std::fs::remove_file(file).unwrap();
+    disconnect().await;
+}
+
+#[tokio::test]
+async fn triggers_can_be_managed() {
+    let _session = connect(Script::default()).await;
+
+    let new = json!({
+        "conditions": [{ "condition": "crosses_above", "price": 200.0 }],
+        "order": {
+            "ticker": "AAPL",
+            "qty": 10,
+            "stop_price": 195.0,
+            "entry_price": 0.0,
+            "action": "BUY",
+        },
+    });
+    let created = call_with("POST", "/triggers", Some(new.clone())).await;
+    let id = created["Ok"]["id"].as_i64().unwrap();
+    assert_eq!(created["Ok"]["status"], "pending", "{}", created);
+
+    let pending = call("GET", "/triggers?status=pending").await;
+    assert!(
+        pending["Ok"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|t| t["id"] == json!(id))
+    );
+    let mut changed = new;
+    changed["conditions"][0]["price"] = json!(205.0);
+    assert_ok(&call_with("PUT", &format!("/triggers/{}", id), Some(changed)).await);
+    let trigger = call("GET", &format!("/triggers/{}", id)).await;
+    assert_eq!(trigger["Ok"]["conditions"][0]["price"], json!(205.0));
+    assert_ok(&call("DELETE", &format!("/triggers/{}", id)).await);
+    let trigger = call("GET", &format!("/triggers/{}", id)).await;
+    assert_eq!(trigger["Ok"]["status"], "cancelled");
+
+    disconnect().await;
+}
+
+#[tokio::test]
+async fn order_updates_stream_over_websocket() {
+    let _session = connect(Script::default()).await;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router::app(BROKER.clone())).await });
+    let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/orders/ws", address))
+        .await
+        .unwrap();
+
+    let placed = call(
+        "POST",
+        "/order?ticker=TSLA&qty=10&stop_price=245&entry_price=0&action=BUY",
+    )
+    .await;
+    assert_ok(&placed);
+    let update = tokio::time::timeout(std::time::Duration::from_secs(5), socket.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    let order: Value = serde_json::from_str(update.to_text().unwrap()).unwrap();
+    assert_eq!(order["symbol"], "TSLA");
+
+    call("POST", "/positions/TSLA/close").await;
+    disconnect().await;
+}
@@ -0,0 +1,262 @@
+//! Rehearses a laddered entry on the paper-trading simulator through the router.
+
+use std::sync::Once;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use http_body_util::BodyExt;
+use serde_json::{Value, json};
+use time::OffsetDateTime;
+use tower::ServiceExt;
+
+use rust::replay::ReplayTick;
+use rust::router;
+use rust::simulator::{SimBroker, SimulatorConfig};
+
+static CONFIGURE: Once = Once::new();
+
+async fn call(broker: &SimBroker, method: &str, uri: &str) -> Value {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .body(Body::empty())
+        .unwrap();
+    let response = router::app(broker.clone()).oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    let text = String::from_utf8_lossy(&bytes).to_string();
+    assert_eq!(status, StatusCode::OK, "{} {}: {}", method, uri, text);
+    serde_json::from_str(&text).unwrap_or(Value::Null)
+}
+
+fn tick(bid: f64, ask: f64, last: f64) -> ReplayTick {
+    ReplayTick {
+        time: OffsetDateTime::now_utc(),
+        symbol: "AAPL".to_string(),
+        bid: Some(bid),
+        ask: Some(ask),
+        last: Some(last),
+        volume: None,
+    }
+}
+
+async fn position(broker: &SimBroker, symbol: &str) -> f64 {
+    let positions = call(broker, "GET", "/get_positions").await;
+    positions
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|p| p["symbol"] == symbol)
+        .map_or(0.0, |p| p["quantity"].as_f64().unwrap())
+}
+
+/// A connected simulator with no quotes of its own.
+async fn connect() -> SimBroker {
+    CONFIGURE.call_once(|| {
+        // SAFETY: runs once, before any test reads the config or spawns threads that read the environment.
+        unsafe {
+            std::env::set_var(
+                "IBKR_PANEL_CONFIG",
+                concat!(
+                    env!("CARGO_MANIFEST_DIR"),
+                    "/tests/fixtures/mock_gateway.toml"
+                ),
+            );
+        }
+    });
+    let broker = SimBroker::replay(SimulatorConfig::default(), Vec::new());
+    assert_eq!(
+        call(&broker, "POST", "/connect?address=sim&port=0&client_id=1").await,
+        json!(true)
+    );
+    broker
+}
+
+#[tokio::test]
+async fn halves_ladder_scales_out_on_replayed_quotes() {
+    let broker = connect().await;
+    broker.apply_tick(&tick(190.0, 190.02, 190.01)).await;
+
+    let placed = call(
+        &broker,
+        "POST",
+        "/order?ticker=AAPL&qty=100&stop_price=185&entry_price=0&action=BUY&ladder=halves",
+    )
+    .await;
+    assert_eq!(placed[0], json!(true), "{}", placed);
+    assert_eq!(position(&broker, "AAPL").await, 100.0);
+    let orders = call(&broker, "GET", "/orders").await;
+    let orders: Vec<&Value> = orders
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|o| o["symbol"] == "AAPL")
+        .collect();
+    assert_eq!(orders.len(), 5, "{:?}", orders);
+
+    // Filled at 190.03, so 1R is 5.03: the first target sits at 195.06.
+    broker.apply_tick(&tick(195.1, 195.12, 195.1)).await;
+    assert_eq!(position(&broker, "AAPL").await, 50.0);
+
+    // The second half's stop is still at 185.
+    broker.apply_tick(&tick(184.9, 184.95, 184.9)).await;
+    assert_eq!(position(&broker, "AAPL").await, 0.0);
+    let working: Vec<Value> = call(&broker, "GET", "/orders")
+        .await
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|o| o["symbol"] == "AAPL" && o["status"] == "Submitted")
+        .cloned()
+        .collect();
+    assert!(working.is_empty(), "{:?}", working);
+
+    let executions = call(&broker, "GET", "/executions?symbol=AAPL").await;
+    let executions = executions["Ok"].as_array().unwrap();
+    assert_eq!(executions.len(), 3, "{:?}", executions);
+    assert!(executions[1]["realized_pnl"].as_f64().unwrap() > 0.0);
+    assert!(executions[2]["realized_pnl"].as_f64().unwrap() < 0.0);
+}
+
+#[tokio::test]
+async fn only_buy_and_sell_are_accepted() {
+    let broker = connect().await;
+    broker
+        .apply_tick(&ReplayTick {
+            symbol: "INTC".to_string(),
+            ..tick(20.0, 20.02, 20.01)
+        })
+        .await;
+    let placed = call(
+        &broker,
+        "POST",
+        "/order?ticker=INTC&qty=10&stop_price=21&entry_price=0&action=SHORT",
+    )
+    .await;
+    assert_eq!(placed, json!([false, "Unknown action SHORT"]));
+    let preview = call(
+        &broker,
+        "POST",
+        "/order/preview?ticker=INTC&qty=10&stop_price=21&entry_price=0&action=SHORT",
+    )
+    .await;
+    assert_eq!(preview, json!({"Err": "Unknown action SHORT"}));
+    assert_eq!(position(&broker, "INTC").await, 0.0);
+
+    let placed = call(
+        &broker,
+        "POST",
+        "/order?ticker=INTC&qty=10&stop_price=21&entry_price=0&action=sell",
+    )
+    .await;
+    assert_eq!(placed[0], json!(true), "{}", placed);
+    assert_eq!(position(&broker, "INTC").await, -10.0);
+}
+
+#[tokio::test]
+async fn reversing_is_risk_checked_at_twice_the_position() {
+    let broker = connect().await;
+    broker
+        .apply_tick(&ReplayTick {
+            symbol: "AMD".to_string(),
+            ..tick(150.0, 150.02, 150.01)
+        })
+        .await;
+    let placed = call(
+        &broker,
+        "POST",
+        "/order?ticker=AMD&qty=3000&stop_price=145&entry_price=0&action=BUY",
+    )
+    .await;
+    assert_eq!(placed[0], json!(true), "{}", placed);
+    assert_eq!(position(&broker, "AMD").await, 3000.0);
+
+    // Selling 6000 to go short 3000 is over the 5000 share limit.
+    let reversed = call(&broker, "POST", "/positions/AMD/reverse").await;
+    let error = reversed["Err"].as_str().unwrap_or_default();
+    assert!(error.contains("5000"), "{}", reversed);
+    assert_eq!(position(&broker, "AMD").await, 3000.0);
+}
+
+#[tokio::test]
+async fn stepped_replay_file_drives_quotes_and_stops() {
+    let broker = connect().await;
+    let name = format!("replay-{}.csv", std::process::id());
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/target/test-replays");
+    std::fs::create_dir_all(dir).unwrap();
+    let file = std::path::Path::new(dir).join(&name);
+    std::fs::write(
+        &file,
+        "time,symbol,bid,ask,last,volume\n\
+         2025-01-02T14:30:00Z,MSFT,420.0,420.02,420.01,1000\n\
+         2025-01-02T14:30:01Z,MSFT,421.0,421.02,421.01,2000\n\
+         2025-01-02T14:30:05Z,MSFT,414.9,414.95,414.9,3000\n",
+    )
+    .unwrap();
+    let uri = format!("/replay/start?file={}&speed=0", name);
+    let outside = call(&broker, "POST", "/replay/start?file=../../watchlist.toml").await;
+    assert!(outside["Err"].is_string(), "{}", outside);
+    let status = call(&broker, "POST", &uri).await;
+    assert_eq!(status["Ok"]["total"], json!(3), "{}", status);
+
+    call(&broker, "POST", "/replay/step").await;
+    assert_eq!(
+        call(&broker, "GET", "/market_data?ticker=MSFT").await,
+        json!(420.01)
+    );
+    let placed = call(
+        &broker,
+        "POST",
+        "/order?ticker=MSFT&qty=10&stop_price=415&entry_price=0&action=BUY&ladder=halves",
+    )
+    .await;
+    assert_eq!(placed[0], json!(true), "{}", placed);
+
+    let status = call(&broker, "POST", "/replay/step?count=2").await;
+    assert_eq!(status["Ok"]["finished"], json!(true), "{}", status);
+    // The simulator matches replayed quotes on its own task.
+    for _ in 0..50 {
+        if position(&broker, "MSFT").await == 0.0 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(position(&broker, "MSFT").await, 0.0);
+    let (lod, hod) = serde_json::from_value::<(f64, f64)>(
+        call(&broker, "GET", "/get_lod_hod?ticker=MSFT").await,
+    )
+    .unwrap();
+    assert_eq!((lod, hod), (414.9, 421.01));
+
+    call(&broker, "POST", "/replay/stop").await;
+    std::fs::remove_file(file).unwrap();
+}
+
+#[tokio::test]
+async fn order_panel_is_served_with_its_settings_and_quotes() {
+    let broker = connect().await;
+    let request = Request::builder().uri("/").body(Body::empty()).unwrap();
+    let response = router::app(broker.clone()).oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&bytes).contains("/panel/settings"));
+
+    let settings = call(&broker, "GET", "/panel/settings").await;
+    assert_eq!(settings["watchlist"], json!(["AAPL", "TSLA"]));
+    assert_eq!(settings["hotkey_place_order"], json!("F1"));
+    assert_eq!(
+        settings["ladders"],
+        json!(["breakeven", "halves", "thirds"])
+    );
+
+    broker
+        .apply_tick(&ReplayTick {
+            symbol: "NVDA".to_string(),
+            ..tick(130.0, 130.02, 130.01)
+        })
+        .await;
+    let quotes = call(&broker, "GET", "/quotes?symbols=nvda,XYZQ").await;
+    assert_eq!(quotes.as_array().unwrap().len(), 1, "{}", quotes);
+    assert_eq!(quotes[0]["symbol"], json!("NVDA"));
+    assert_eq!(quotes[0]["ask"], json!(130.02));
+}